@@ -1,5 +1,15 @@
 #![no_std]
 
+pub mod symbols;
+
+use core::ptr::NonNull;
+
+/// Physical address where the loader places the kernel image.
+pub const KERNEL_ADDRESS: u64 = 0x40000000;
+
+/// Size of a page described by the UEFI memory map, in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PixelFormat {
     RgbResv8BitPerColor,
@@ -15,9 +25,83 @@ pub struct FrameBufferConfig {
     pub pixel_format: PixelFormat,
 }
 
+/// Type of a memory region, using the same values as `EFI_MEMORY_TYPE`.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryType(pub u32);
+
+impl MemoryType {
+    pub const RESERVED: Self = Self(0);
+    pub const LOADER_CODE: Self = Self(1);
+    pub const LOADER_DATA: Self = Self(2);
+    pub const BOOT_SERVICES_CODE: Self = Self(3);
+    pub const BOOT_SERVICES_DATA: Self = Self(4);
+    pub const RUNTIME_SERVICES_CODE: Self = Self(5);
+    pub const RUNTIME_SERVICES_DATA: Self = Self(6);
+    pub const CONVENTIONAL: Self = Self(7);
+    pub const UNUSABLE: Self = Self(8);
+    pub const ACPI_RECLAIM: Self = Self(9);
+    pub const ACPI_NON_VOLATILE: Self = Self(10);
+    pub const MMIO: Self = Self(11);
+    pub const MMIO_PORT_SPACE: Self = Self(12);
+    pub const PAL_CODE: Self = Self(13);
+    pub const PERSISTENT_MEMORY: Self = Self(14);
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryDescriptor {
+    pub ty: MemoryType,
+    pub phys_start: u64,
+    pub page_count: u64,
+    pub attribute: u64,
+}
+
+impl MemoryDescriptor {
+    #[inline]
+    pub fn phys_end(&self) -> u64 {
+        self.phys_start + self.page_count * PAGE_SIZE as u64
+    }
+}
+
+/// Memory map handed over from the loader, taken at the time of exiting boot services.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryMap {
+    descriptors: *const MemoryDescriptor,
+    len: usize,
+}
+
+impl MemoryMap {
+    /// # Safety
+    /// `descriptors` must point to `len` initialised descriptors that live as long as the kernel.
+    pub unsafe fn from_raw_parts(descriptors: *const MemoryDescriptor, len: usize) -> Self {
+        Self { descriptors, len }
+    }
+
+    #[inline]
+    pub fn descriptors(&self) -> &'static [MemoryDescriptor] {
+        if self.len == 0 {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.descriptors, self.len) }
+    }
+
+    #[inline]
+    pub fn iter(&self) -> core::slice::Iter<'static, MemoryDescriptor> {
+        self.descriptors().iter()
+    }
+}
+
 #[derive(Debug)]
 pub struct KernelArgs {
     pub frame_buffer: FrameBufferConfig,
+    pub memory_map: MemoryMap,
+    /// The flattened device tree installed by the firmware, if any.
+    pub device_tree: Option<NonNull<u8>>,
+    /// The RSDP of ACPI 2.0 or later installed by the firmware, if any.
+    pub acpi_rsdp: Option<NonNull<u8>>,
 }
 
 pub type Entrypoint = extern "C" fn(KernelArgs) -> !;
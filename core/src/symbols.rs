@@ -0,0 +1,199 @@
+//! Compact symbol table embedded into the kernel image after linking.
+//!
+//! The table starts with a 16-byte header of the magic, the number of symbols and the length of
+//! the string table. Then 16-byte entries of the address, the size and the offset of the name
+//! follow, sorted by their addresses, and the string table comes at the end. Names are stored in
+//! the same order as the entries without any terminators.
+
+pub const MAGIC: [u8; 4] = *b"KSYM";
+
+pub const HEADER_SIZE: usize = 16;
+pub const ENTRY_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    strings: &'a [u8],
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Parses the table, returning `None` if the data is not a symbol table, e.g. all zeroes
+    /// because the post-link step did not run.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || data[..4] != MAGIC {
+            return None;
+        }
+
+        let count = read_u32(data, 4) as usize;
+        let strings_len = read_u32(data, 8) as usize;
+        let strings_start = HEADER_SIZE + count * ENTRY_SIZE;
+        if data.len() < strings_start + strings_len {
+            return None;
+        }
+
+        Some(Self {
+            entries: &data[HEADER_SIZE..strings_start],
+            strings: &data[strings_start..strings_start + strings_len],
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let entry = &self.entries[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+        let start = read_u32(entry, 12) as usize;
+        let end = match index + 1 {
+            next if next < self.len() => read_u32(self.entries, next * ENTRY_SIZE + 12) as usize,
+            _ => self.strings.len(),
+        };
+
+        Some(Symbol {
+            name: core::str::from_utf8(self.strings.get(start..end)?).ok()?,
+            address: read_u64(entry, 0),
+            size: read_u32(entry, 8) as u64,
+        })
+    }
+
+    /// Finds the symbol containing the address.
+    pub fn lookup(&self, address: u64) -> Option<Symbol<'a>> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if read_u64(self.entries, middle * ENTRY_SIZE) <= address {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        let symbol = self.get(low.checked_sub(1)?)?;
+        (address < symbol.address + symbol.size.max(1)).then_some(symbol)
+    }
+}
+
+/// Encodes the symbols, which must be sorted by their addresses, into the buffer.
+///
+/// Returns the number of bytes written, or `None` if the buffer is too small.
+pub fn encode(symbols: &[Symbol], buf: &mut [u8]) -> Option<usize> {
+    let strings_start = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+    let strings_len = symbols.iter().map(|s| s.name.len()).sum::<usize>();
+    let len = strings_start + strings_len;
+    if buf.len() < len {
+        return None;
+    }
+
+    buf[..4].copy_from_slice(&MAGIC);
+    buf[4..8].copy_from_slice(&(symbols.len() as u32).to_le_bytes());
+    buf[8..12].copy_from_slice(&(strings_len as u32).to_le_bytes());
+    buf[12..16].fill(0);
+
+    symbols.iter().enumerate().fold(0, |offset, (i, symbol)| {
+        let entry = &mut buf[HEADER_SIZE + i * ENTRY_SIZE..HEADER_SIZE + (i + 1) * ENTRY_SIZE];
+        entry[..8].copy_from_slice(&symbol.address.to_le_bytes());
+        entry[8..12].copy_from_slice(&(symbol.size as u32).to_le_bytes());
+        entry[12..].copy_from_slice(&(offset as u32).to_le_bytes());
+
+        let start = strings_start + offset;
+        buf[start..start + symbol.name.len()].copy_from_slice(symbol.name.as_bytes());
+        offset + symbol.name.len()
+    });
+
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &[Symbol] = &[
+        Symbol {
+            name: "kernel_main",
+            address: 0x4000_1000,
+            size: 0x100,
+        },
+        Symbol {
+            name: "kernel::timer::init",
+            address: 0x4000_1100,
+            size: 0x40,
+        },
+        Symbol {
+            name: "handle_exception",
+            address: 0x4000_2000,
+            size: 0,
+        },
+    ];
+
+    fn encoded(buf: &mut [u8]) -> SymbolTable<'_> {
+        let len = encode(SYMBOLS, buf).unwrap();
+        SymbolTable::parse(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; 256];
+        let table = encoded(&mut buf);
+
+        assert_eq!(3, table.len());
+        (0..3).for_each(|i| assert_eq!(Some(SYMBOLS[i]), table.get(i)));
+        assert_eq!(None, table.get(3));
+    }
+
+    #[test]
+    fn lookup_containing_symbol() {
+        let mut buf = [0; 256];
+        let table = encoded(&mut buf);
+
+        assert_eq!(None, table.lookup(0x4000_0FFF));
+        assert_eq!(Some(SYMBOLS[0]), table.lookup(0x4000_1000));
+        assert_eq!(Some(SYMBOLS[0]), table.lookup(0x4000_10FF));
+        assert_eq!(Some(SYMBOLS[1]), table.lookup(0x4000_1100));
+        assert_eq!(None, table.lookup(0x4000_1140));
+        assert_eq!(Some(SYMBOLS[2]), table.lookup(0x4000_2000));
+        assert_eq!(None, table.lookup(0x4000_2001));
+    }
+
+    #[test]
+    fn reject_invalid_data() {
+        assert!(SymbolTable::parse(&[0; 64]).is_none());
+        assert!(SymbolTable::parse(b"KSYM").is_none());
+
+        let mut buf = [0; 256];
+        let len = encode(SYMBOLS, &mut buf).unwrap();
+        assert!(SymbolTable::parse(&buf[..len - 1]).is_none());
+    }
+
+    #[test]
+    fn reject_small_buffer() {
+        let mut buf = [0; 64];
+        assert_eq!(None, encode(SYMBOLS, &mut buf));
+    }
+}
@@ -10,18 +10,22 @@ use anyhow::{anyhow, Result};
 use core::cmp::{max, min};
 use core::fmt::Write;
 use core::ops::DerefMut;
+use core::ptr::NonNull;
 use elf_rs::{Elf, ElfFile, ProgramType};
-use mikan_core::{Entrypoint, FrameBufferConfig, KernelArgs};
+use mikan_core::{Entrypoint, FrameBufferConfig, KernelArgs, MemoryMap, KERNEL_ADDRESS};
 use uefi::data_types::PhysicalAddress;
 use uefi::prelude::*;
 use uefi::proto::console::gop::{FrameBuffer, GraphicsOutput, ModeInfo, PixelFormat};
 use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile};
 use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType, SearchType};
-use uefi::CString16;
-use uefi::Identify;
+use uefi::table::cfg::ACPI2_GUID;
+use uefi::{guid, CString16, Guid, Identify};
 
 use crate::buf::{allocate_aligned, allocate_uninit};
 
+/// GUID of the configuration table holding the flattened device tree.
+const DEVICE_TREE_GUID: Guid = guid!("b1b621d5-f19c-41a5-830b-d9152c69aae0");
+
 macro_rules! err {
     () => {
         |e| anyhow!(e)
@@ -45,8 +49,6 @@ macro_rules! eprintln {
     };
 }
 
-const KERNEL_ADDRESS: u32 = 0x40000000;
-
 struct WrappedFile {
     file: RegularFile,
 }
@@ -69,6 +71,15 @@ impl Write for WrappedFile {
     }
 }
 
+fn convert_memory_descriptor(descriptor: &MemoryDescriptor) -> mikan_core::MemoryDescriptor {
+    mikan_core::MemoryDescriptor {
+        ty: mikan_core::MemoryType(descriptor.ty.0),
+        phys_start: descriptor.phys_start,
+        page_count: descriptor.page_count,
+        attribute: descriptor.att.bits(),
+    }
+}
+
 struct Application {
     handle: Handle,
     system_table: SystemTable<Boot>,
@@ -259,16 +270,57 @@ impl Application {
         Ok(entry_point)
     }
 
+    fn find_config_table(&self, guid: &Guid) -> Option<NonNull<u8>> {
+        self.system_table
+            .config_table()
+            .iter()
+            .find(|entry| entry.guid == *guid)
+            .and_then(|entry| NonNull::new(entry.address as *mut u8))
+    }
+
     fn boot(self, entry_point: usize, frame_buffer: FrameBufferConfig) -> Result<()> {
+        let device_tree = self.find_config_table(&DEVICE_TREE_GUID);
+        match device_tree {
+            Some(ptr) => println!("Device tree found at {:p}", ptr)?,
+            None => println!("No device tree found")?,
+        }
+
+        let acpi_rsdp = self.find_config_table(&ACPI2_GUID);
+        match acpi_rsdp {
+            Some(ptr) => println!("ACPI RSDP found at {:p}", ptr)?,
+            None => println!("No ACPI RSDP found")?,
+        }
+
         println!("Booting kernel, exiting boot services")?;
 
         let mut buf = allocate_aligned::<MemoryDescriptor>(4096);
-        self.system_table
+
+        // No allocations are allowed after exiting boot services, so the kernel-side descriptors
+        // are allocated beforehand, as many as the raw buffer can hold at most.
+        let descriptors = allocate_uninit::<mikan_core::MemoryDescriptor>(
+            buf.len() / core::mem::size_of::<MemoryDescriptor>(),
+        )
+        .leak();
+
+        let (_, memory_map) = self
+            .system_table
             .exit_boot_services(self.handle, &mut buf)
-            .map(|_| ())
             .map_err(|_| anyhow!("Could not exit boot services"))?;
 
-        (unsafe { core::mem::transmute::<_, Entrypoint>(entry_point) })(KernelArgs { frame_buffer })
+        let len = descriptors
+            .iter_mut()
+            .zip(memory_map)
+            .map(|(slot, descriptor)| slot.write(convert_memory_descriptor(descriptor)))
+            .count();
+
+        let memory_map = unsafe { MemoryMap::from_raw_parts(descriptors.as_ptr().cast(), len) };
+
+        (unsafe { core::mem::transmute::<_, Entrypoint>(entry_point) })(KernelArgs {
+            frame_buffer,
+            memory_map,
+            device_tree,
+            acpi_rsdp,
+        })
     }
 
     fn execute(mut self) -> Result<()> {
@@ -5,6 +5,14 @@ use std::io::Write;
 use itertools::Itertools;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Only the kernel itself, as the tests run on the host.
+    if std::env::var("TARGET")? == "aarch64-unknown-elf" {
+        println!(
+            "cargo:rustc-link-arg-bins=-T{}/kernel.ld",
+            std::env::var("CARGO_MANIFEST_DIR")?
+        );
+    }
+
     let font = bdf::read(File::open("../resources/fonts/shinonome/shnm8x16a.bdf")?)?;
 
     let glyphs = font
@@ -0,0 +1,429 @@
+//! Discovery of hardware from the ACPI tables which the firmware installs.
+//!
+//! The tables are identity mapped, so the physical addresses in them are used as pointers.
+
+use crate::pci::EcamWindow;
+use crate::psci::Conduit;
+use crate::timer::TimerInterrupts;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+const HEADER_SIZE: usize = 36;
+
+const MADT_GICC: u8 = 0x0B;
+const MADT_GICD: u8 = 0x0C;
+const MADT_GICR: u8 = 0x0E;
+
+const MADT_GICC_ENABLED: u32 = 1 << 0;
+
+/// Flags of the ARM boot architecture in FADT.
+const FADT_PSCI_COMPLIANT: u16 = 1 << 0;
+const FADT_PSCI_USE_HVC: u16 = 1 << 1;
+
+/// Interface types in SPCR of the UARTs compatible with PL011.
+const SPCR_PL011: u8 = 0x03;
+const SPCR_SBSA_GENERIC: u8 = 0x0E;
+
+#[inline]
+fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[inline]
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+/// Every ACPI structure is valid only if all of its bytes sum to zero.
+fn is_valid_checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// A system description table, including its header.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Sdt {
+    bytes: &'static [u8],
+}
+
+impl Sdt {
+    /// # Safety
+    /// `address` must point to a table which lives as long as the kernel.
+    unsafe fn from_address(address: usize) -> Option<Self> {
+        let header = core::slice::from_raw_parts(address as *const u8, HEADER_SIZE);
+        let len = read_u32(header, 4)? as usize;
+        if len < HEADER_SIZE {
+            return None;
+        }
+
+        let bytes = core::slice::from_raw_parts(address as *const u8, len);
+        is_valid_checksum(bytes).then_some(Self { bytes })
+    }
+
+    #[inline]
+    pub(crate) fn signature(&self) -> &'static [u8; 4] {
+        self.bytes[..4].try_into().unwrap()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Acpi {
+    xsdt: Sdt,
+}
+
+impl Acpi {
+    /// Follows the RSDP of ACPI 2.0 or later to the XSDT.
+    ///
+    /// # Safety
+    /// `ptr` must point to the RSDP which, and whose tables, live as long as the kernel.
+    pub(crate) unsafe fn from_rsdp(ptr: *const u8) -> Option<Self> {
+        let rsdp = core::slice::from_raw_parts(ptr, RSDP_V2_SIZE);
+        if &rsdp[..8] != RSDP_SIGNATURE
+            || read_u8(rsdp, 15)? < 2
+            || !is_valid_checksum(&rsdp[..RSDP_V1_SIZE])
+            || !is_valid_checksum(rsdp)
+        {
+            return None;
+        }
+
+        let xsdt = Sdt::from_address(read_u64(rsdp, 24)? as usize)?;
+        (xsdt.signature() == b"XSDT").then_some(Self { xsdt })
+    }
+
+    /// Tables listed in the XSDT, skipping those with invalid checksums.
+    pub(crate) fn tables(&self) -> impl Iterator<Item = Sdt> {
+        self.xsdt.bytes[HEADER_SIZE..]
+            .chunks_exact(8)
+            .filter_map(|entry| unsafe {
+                Sdt::from_address(u64::from_le_bytes(entry.try_into().unwrap()) as usize)
+            })
+    }
+
+    pub(crate) fn find(&self, signature: &[u8; 4]) -> Option<Sdt> {
+        self.tables().find(|table| table.signature() == signature)
+    }
+
+    /// The Multiple APIC Description Table, describing the GIC.
+    pub(crate) fn madt(&self) -> Option<Madt> {
+        self.find(b"APIC").map(|table| Madt { table })
+    }
+
+    /// How to call PSCI, from the ARM boot architecture flags of the Fixed ACPI Description Table.
+    pub(crate) fn psci(&self) -> Option<Conduit> {
+        let fadt = self.find(b"FACP")?.bytes;
+        let flags = u16::from_le_bytes(fadt.get(129..131)?.try_into().unwrap());
+        match (
+            flags & FADT_PSCI_COMPLIANT != 0,
+            flags & FADT_PSCI_USE_HVC != 0,
+        ) {
+            (true, true) => Some(Conduit::Hvc),
+            (true, false) => Some(Conduit::Smc),
+            (false, _) => None,
+        }
+    }
+
+    /// INTIDs of the architected timer from the Generic Timer Description Table, whose GSIVs are
+    /// the same as INTIDs on the GIC.
+    pub(crate) fn timer_interrupts(&self) -> Option<TimerInterrupts> {
+        let gtdt = self.find(b"GTDT")?.bytes;
+
+        Some(TimerInterrupts {
+            secure_physical: read_u32(gtdt, 48)?,
+            non_secure_physical: read_u32(gtdt, 56)?,
+            virtual_: read_u32(gtdt, 64)?,
+            hypervisor_physical: read_u32(gtdt, 72)?,
+        })
+    }
+
+    /// ECAM windows of the PCIe host bridges from the MCFG table, whose base addresses are those
+    /// of bus 0 even if the range starts at another bus.
+    pub(crate) fn pci_ecam(&self) -> impl Iterator<Item = EcamWindow> {
+        self.find(b"MCFG")
+            .into_iter()
+            .flat_map(|table| table.bytes[HEADER_SIZE + 8..].chunks_exact(16))
+            .map(|entry| {
+                let (bus_start, bus_end) = (entry[10], entry[11]);
+                EcamWindow {
+                    base: read_u64(entry, 0).unwrap() as usize + ((bus_start as usize) << 20),
+                    size: (bus_end.saturating_sub(bus_start) as usize + 1) << 20,
+                    bus_start,
+                    bus_end,
+                }
+            })
+    }
+
+    /// Base address of the console UART from the Serial Port Console Redirection table.
+    pub(crate) fn uart(&self) -> Option<usize> {
+        let spcr = self.find(b"SPCR")?.bytes;
+        match read_u8(spcr, 36)? {
+            SPCR_PL011 | SPCR_SBSA_GENERIC => Some(read_u64(spcr, 44)? as usize),
+            _ => None,
+        }
+    }
+}
+
+/// A GIC CPU interface structure, one per processor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct GicCpuInterface {
+    pub(crate) uid: u32,
+    pub(crate) flags: u32,
+    /// Base of the GICv2 CPU interface.
+    pub(crate) base: usize,
+    /// Base of the GICv3 redistributor, if the redistributors are not described in ranges.
+    pub(crate) redistributor: usize,
+    pub(crate) mpidr: u64,
+}
+
+impl GicCpuInterface {
+    /// Whether the processor is usable, otherwise it must not be started.
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.flags & MADT_GICC_ENABLED != 0
+    }
+}
+
+/// A GIC distributor structure.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct GicDistributor {
+    pub(crate) base: usize,
+    /// Version of the GIC, or zero if it should be detected from the hardware.
+    pub(crate) version: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Madt {
+    table: Sdt,
+}
+
+impl Madt {
+    /// Interrupt controller structures as pairs of the type and the whole structure.
+    fn entries(&self) -> impl Iterator<Item = (u8, &'static [u8])> {
+        let mut rest = self.table.bytes.get(HEADER_SIZE + 8..).unwrap_or_default();
+        core::iter::from_fn(move || {
+            let len = *rest.get(1)? as usize;
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+
+            let (entry, next) = rest.split_at(len);
+            rest = next;
+            Some((entry[0], entry))
+        })
+    }
+
+    pub(crate) fn cpu_interfaces(&self) -> impl Iterator<Item = GicCpuInterface> {
+        self.entries()
+            .filter(|&(ty, _)| ty == MADT_GICC)
+            .filter_map(|(_, entry)| {
+                Some(GicCpuInterface {
+                    uid: read_u32(entry, 8)?,
+                    flags: read_u32(entry, 12)?,
+                    base: read_u64(entry, 32)? as usize,
+                    redistributor: read_u64(entry, 60)? as usize,
+                    mpidr: read_u64(entry, 68)?,
+                })
+            })
+    }
+
+    pub(crate) fn distributor(&self) -> Option<GicDistributor> {
+        let (_, entry) = self.entries().find(|&(ty, _)| ty == MADT_GICD)?;
+        Some(GicDistributor {
+            base: read_u64(entry, 8)? as usize,
+            version: read_u8(entry, 20)?,
+        })
+    }
+
+    /// Bases of the GICv3 redistributor discovery ranges.
+    pub(crate) fn redistributors(&self) -> impl Iterator<Item = usize> {
+        self.entries()
+            .filter(|&(ty, _)| ty == MADT_GICR)
+            .filter_map(|(_, entry)| Some(read_u64(entry, 4)? as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leak(bytes: Vec<u8>) -> usize {
+        Box::leak(bytes.into_boxed_slice()).as_ptr() as usize
+    }
+
+    fn fix_checksum(bytes: &mut [u8], at: usize) {
+        bytes[at] = 0;
+        bytes[at] = 0u8.wrapping_sub(bytes.iter().fold(0u8, |s, &b| s.wrapping_add(b)));
+    }
+
+    fn table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(signature);
+        bytes[4..8].copy_from_slice(&((HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        bytes[8] = 2;
+        bytes.extend(body);
+        fix_checksum(&mut bytes, 9);
+        bytes
+    }
+
+    fn rsdp(tables: &[Vec<u8>]) -> Vec<u8> {
+        let entries = tables
+            .iter()
+            .flat_map(|t| (leak(t.clone()) as u64).to_le_bytes())
+            .collect::<Vec<_>>();
+        let xsdt = leak(table(b"XSDT", &entries));
+
+        let mut rsdp = vec![0; RSDP_V2_SIZE];
+        rsdp[..8].copy_from_slice(RSDP_SIGNATURE);
+        rsdp[15] = 2;
+        rsdp[20..24].copy_from_slice(&(RSDP_V2_SIZE as u32).to_le_bytes());
+        rsdp[24..32].copy_from_slice(&(xsdt as u64).to_le_bytes());
+        fix_checksum(&mut rsdp[..RSDP_V1_SIZE], 8);
+        fix_checksum(&mut rsdp, 32);
+        rsdp
+    }
+
+    fn madt() -> Vec<u8> {
+        let mut body = vec![0; 8];
+
+        let mut gicd = vec![0; 24];
+        gicd[..2].copy_from_slice(&[MADT_GICD, 24]);
+        gicd[8..16].copy_from_slice(&0x0800_0000u64.to_le_bytes());
+        gicd[20] = 3;
+        body.extend(gicd);
+
+        (0..2u64).for_each(|i| {
+            let mut gicc = vec![0; 80];
+            gicc[..2].copy_from_slice(&[MADT_GICC, 80]);
+            gicc[8..12].copy_from_slice(&(i as u32).to_le_bytes());
+            gicc[12..16].copy_from_slice(&1u32.to_le_bytes());
+            gicc[68..76].copy_from_slice(&i.to_le_bytes());
+            body.extend(gicc);
+        });
+
+        let mut gicr = vec![0; 16];
+        gicr[..2].copy_from_slice(&[MADT_GICR, 16]);
+        gicr[4..12].copy_from_slice(&0x080A_0000u64.to_le_bytes());
+        gicr[12..16].copy_from_slice(&0xF6_0000u32.to_le_bytes());
+        body.extend(gicr);
+
+        table(b"APIC", &body)
+    }
+
+    fn gtdt() -> Vec<u8> {
+        let mut body = vec![0; 48];
+        [29u32, 30, 27, 26]
+            .iter()
+            .enumerate()
+            .for_each(|(i, gsiv)| {
+                body[12 + i * 8..16 + i * 8].copy_from_slice(&gsiv.to_le_bytes())
+            });
+        table(b"GTDT", &body)
+    }
+
+    fn fadt(flags: u16) -> Vec<u8> {
+        let mut body = vec![0; 276 - HEADER_SIZE];
+        body[129 - HEADER_SIZE..131 - HEADER_SIZE].copy_from_slice(&flags.to_le_bytes());
+        table(b"FACP", &body)
+    }
+
+    fn mcfg() -> Vec<u8> {
+        let mut body = vec![0; 8];
+        body.extend(0x40_1000_0000u64.to_le_bytes());
+        body.extend([0, 0, 0, 0xFF, 0, 0, 0, 0]);
+        table(b"MCFG", &body)
+    }
+
+    #[test]
+    fn reject_invalid_rsdp() {
+        let mut bytes = rsdp(&[]);
+        bytes[16] ^= 1;
+        assert!(unsafe { Acpi::from_rsdp(bytes.as_ptr()) }.is_none());
+
+        let mut bytes = rsdp(&[]);
+        bytes[0] = b'X';
+        assert!(unsafe { Acpi::from_rsdp(bytes.as_ptr()) }.is_none());
+    }
+
+    #[test]
+    fn skip_tables_with_invalid_checksums() {
+        let mut broken = gtdt();
+        broken[40] ^= 1;
+
+        let bytes = rsdp(&[broken, mcfg()]);
+        let acpi = unsafe { Acpi::from_rsdp(bytes.as_ptr()) }.unwrap();
+
+        assert_eq!(
+            vec![b"MCFG"],
+            acpi.tables().map(|t| t.signature()).collect::<Vec<_>>()
+        );
+        assert!(acpi.timer_interrupts().is_none());
+    }
+
+    #[test]
+    fn parse_madt() {
+        let bytes = rsdp(&[madt()]);
+        let madt = unsafe { Acpi::from_rsdp(bytes.as_ptr()) }
+            .unwrap()
+            .madt()
+            .unwrap();
+
+        assert_eq!(
+            Some(GicDistributor {
+                base: 0x0800_0000,
+                version: 3
+            }),
+            madt.distributor()
+        );
+        assert_eq!(
+            vec![0, 1],
+            madt.cpu_interfaces().map(|c| c.mpidr).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![0x080A_0000], madt.redistributors().collect::<Vec<_>>());
+        assert!(madt.cpu_interfaces().all(|c| c.is_enabled()));
+    }
+
+    #[test]
+    fn parse_psci_flags() {
+        let psci = |flags| {
+            let bytes = rsdp(&[fadt(flags)]);
+            unsafe { Acpi::from_rsdp(bytes.as_ptr()) }.unwrap().psci()
+        };
+
+        assert_eq!(Some(Conduit::Hvc), psci(0b11));
+        assert_eq!(Some(Conduit::Smc), psci(0b01));
+        assert_eq!(None, psci(0b10));
+    }
+
+    #[test]
+    fn parse_gtdt_and_mcfg() {
+        let bytes = rsdp(&[madt(), gtdt(), mcfg()]);
+        let acpi = unsafe { Acpi::from_rsdp(bytes.as_ptr()) }.unwrap();
+
+        assert_eq!(
+            Some(TimerInterrupts {
+                secure_physical: 29,
+                non_secure_physical: 30,
+                virtual_: 27,
+                hypervisor_physical: 26,
+            }),
+            acpi.timer_interrupts()
+        );
+        assert_eq!(
+            vec![EcamWindow {
+                base: 0x40_1000_0000,
+                size: 0x1000_0000,
+                bus_start: 0,
+                bus_end: 0xFF,
+            }],
+            acpi.pci_ecam().collect::<Vec<_>>()
+        );
+    }
+}
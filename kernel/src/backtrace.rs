@@ -0,0 +1,120 @@
+use core::arch::asm;
+
+use crate::symbols;
+
+/// Stops walking after this many frames, in case the chain is corrupted.
+const MAX_DEPTH: usize = 32;
+
+/// Iterator of return addresses, following the chain of AArch64 frame records.
+///
+/// Every frame record is a pair of the caller's frame pointer and the link register, pointed by
+/// `x29`. This requires the kernel to be built with frame pointers.
+pub(crate) struct Backtrace {
+    fp: usize,
+    depth: usize,
+}
+
+impl Backtrace {
+    /// Starts from the frame of the caller.
+    #[inline(always)]
+    pub(crate) fn capture() -> Self {
+        let fp: usize;
+        unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+        Self { fp, depth: 0 }
+    }
+
+    /// Starts from the frame record at the address, e.g. `x29` saved in a trap frame.
+    pub(crate) fn from_frame_pointer(fp: usize) -> Self {
+        Self { fp, depth: 0 }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fp == 0 || !self.fp.is_multiple_of(8) || self.depth >= MAX_DEPTH {
+            return None;
+        }
+
+        let [prev, lr] = unsafe { *(self.fp as *const [usize; 2]) };
+        if lr == 0 {
+            return None;
+        }
+
+        // The stack grows downwards, so the caller's frame must be above the current one.
+        self.fp = if prev > self.fp { prev } else { 0 };
+        self.depth += 1;
+        Some(lr)
+    }
+}
+
+/// Prints the return addresses, one per line, with the functions containing them if known.
+pub(crate) fn print<I>(addresses: I)
+where
+    I: IntoIterator<Item = usize>,
+{
+    println!("Backtrace:");
+    addresses
+        .into_iter()
+        .enumerate()
+        .for_each(|(i, address)| match symbols::resolve(address) {
+            Some((symbol, offset)) => {
+                println!(
+                    "  #{:<2} {:#018x} {}+{:#x}",
+                    i, address, symbol.name, offset
+                )
+            }
+            None => println!("  #{:<2} {:#018x}", i, address),
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_frame_records() {
+        let mut stack = [[0usize; 2]; 4];
+        let frames = stack.as_mut_ptr();
+        let base = frames as usize;
+        unsafe {
+            frames.write([base + 16, 0x1000]);
+            frames.add(1).write([base + 48, 0x2000]);
+            frames.add(3).write([0, 0x3000]);
+        }
+
+        let addresses = Backtrace::from_frame_pointer(base).collect::<Vec<_>>();
+
+        assert_eq!(vec![0x1000, 0x2000, 0x3000], addresses);
+    }
+
+    #[test]
+    fn stop_at_descending_frame() {
+        let mut stack = [[0usize; 2]; 2];
+        let frames = stack.as_mut_ptr();
+        let base = frames as usize;
+        unsafe { frames.add(1).write([base, 0x2000]) };
+
+        let addresses = Backtrace::from_frame_pointer(base + 16).collect::<Vec<_>>();
+
+        assert_eq!(vec![0x2000], addresses);
+    }
+
+    #[test]
+    fn stop_at_invalid_frame_pointer() {
+        assert_eq!(0, Backtrace::from_frame_pointer(0).count());
+        assert_eq!(0, Backtrace::from_frame_pointer(0x1001).count());
+    }
+
+    #[test]
+    fn stop_at_max_depth() {
+        let mut stack = [[0usize; 2]; MAX_DEPTH + 8];
+        let frames = stack.as_mut_ptr();
+        let base = frames as usize;
+        (0..stack.len())
+            .for_each(|i| unsafe { frames.add(i).write([base + (i + 1) * 16, 0x1000 + i]) });
+
+        assert_eq!(MAX_DEPTH, Backtrace::from_frame_pointer(base).count());
+    }
+}
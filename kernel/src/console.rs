@@ -6,14 +6,12 @@ use crate::{Canvas, Colors, Region, TextWriter};
 
 const ROWS: usize = 25;
 const COLUMNS: usize = 80;
-const WIDTH: usize = COLUMNS * FONT_WIDTH;
-const HEIGHT: usize = ROWS * FONT_HEIGHT;
+pub(crate) const WIDTH: usize = COLUMNS * FONT_WIDTH;
+pub(crate) const HEIGHT: usize = ROWS * FONT_HEIGHT;
 
-pub(crate) struct Console<W>
-where
-    W: 'static,
-{
-    writer: &'static mut W,
+/// Text console keeping the characters on the screen, which draws on the canvas it is given for
+/// every write. It must be given the same canvas every time, as it redraws only what changes.
+pub(crate) struct Console {
     position: Position,
     cursor: (usize, usize),
     background: Color,
@@ -21,13 +19,9 @@ where
     buffer: [[char; COLUMNS + 1]; ROWS],
 }
 
-impl<W> Console<W>
-where
-    W: Canvas,
-{
-    pub(crate) fn new(writer: &'static mut W) -> Self {
+impl Console {
+    pub(crate) fn new() -> Self {
         Self {
-            writer,
             position: Position::zero(),
             cursor: (0, 0),
             background: Colors::white(),
@@ -36,14 +30,6 @@ where
         }
     }
 
-    pub(crate) fn with_position<P>(mut self, position: P) -> Self
-    where
-        P: Into<Position>,
-    {
-        self.position = position.into();
-        self
-    }
-
     pub(crate) fn with_color<C>(mut self, color: C) -> Self
     where
         C: Into<Color>,
@@ -63,26 +49,43 @@ where
         self.position + (x * FONT_WIDTH, y * FONT_HEIGHT).into()
     }
 
-    pub(crate) fn next(&mut self) {
+    /// Writes on the canvas through the returned writer.
+    pub(crate) fn on<'a, W>(&'a mut self, writer: &'a mut W) -> ConsoleWriter<'a, W>
+    where
+        W: TextWriter,
+    {
+        ConsoleWriter {
+            console: self,
+            writer,
+        }
+    }
+
+    fn next<W>(&mut self, writer: &mut W)
+    where
+        W: Canvas,
+    {
         let (x, y) = self.cursor;
         if x >= COLUMNS - 1 {
-            self.new_line()
+            self.new_line(writer)
         } else {
             self.cursor = (x + 1, y)
         }
     }
 
-    pub(crate) fn new_line(&mut self) {
+    fn new_line<W>(&mut self, writer: &mut W)
+    where
+        W: Canvas,
+    {
         let (_, y) = self.cursor;
         if y < ROWS - 1 {
             self.cursor = (0, y + 1);
         } else {
             self.cursor = (0, ROWS - 1);
-            self.writer.fill_in(self.region(), self.background);
+            writer.fill_in(self.region(), self.background);
             self.buffer.copy_within(1.., 0);
             self.buffer.last_mut().unwrap().fill('\0');
             self.buffer.iter().enumerate().for_each(|(i, line)| {
-                self.writer.write_chars(
+                writer.write_chars(
                     self.position + (0, i * FONT_HEIGHT).into(),
                     *line,
                     self.foreground,
@@ -92,20 +95,27 @@ where
     }
 }
 
-impl<W> Write for Console<W>
+/// The console borrowed with the canvas to draw on.
+pub(crate) struct ConsoleWriter<'a, W> {
+    console: &'a mut Console,
+    writer: &'a mut W,
+}
+
+impl<W> Write for ConsoleWriter<'_, W>
 where
     W: TextWriter,
 {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let (console, writer) = (&mut *self.console, &mut *self.writer);
         s.chars().for_each(|c| {
-            let (x, y) = self.cursor;
+            let (x, y) = console.cursor;
             if c == '\n' {
-                return self.new_line();
+                return console.new_line(writer);
             }
 
-            self.writer.write_ascii(self.position(), c, self.foreground);
-            self.buffer[y][x] = c;
-            self.next()
+            writer.write_ascii(console.position(), c, console.foreground);
+            console.buffer[y][x] = c;
+            console.next(writer)
         });
 
         Ok(())
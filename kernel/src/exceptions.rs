@@ -0,0 +1,437 @@
+use core::arch::{asm, global_asm};
+use core::fmt::{Display, Formatter};
+
+use crate::backtrace::{self, Backtrace};
+
+/// Size of `TrapFrame` in bytes, which must be kept in sync with the assembly below.
+const TRAP_FRAME_SIZE: usize = 272;
+
+/// Registers saved on the stack by the exception vectors.
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct TrapFrame {
+    pub(crate) x: [u64; 31],
+    pub(crate) elr: u64,
+    pub(crate) spsr: u64,
+    pub(crate) sp: u64,
+}
+
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == TRAP_FRAME_SIZE);
+
+// Every vector saves x0 and x1, then jumps to the common path with the index of the vector in x1.
+// The common path saves the rest of the trap frame and the caller-saved SIMD registers, which the
+// handler written in Rust may clobber.
+global_asm!(
+    r#"
+.macro VECTOR index
+    .balign 0x80
+    sub sp, sp, #{frame_size}
+    stp x0, x1, [sp, #16 * 0]
+    mov x1, #\index
+    b __exception_common
+.endm
+
+.balign 0x800
+.global __exception_vectors
+__exception_vectors:
+    VECTOR 0
+    VECTOR 1
+    VECTOR 2
+    VECTOR 3
+    VECTOR 4
+    VECTOR 5
+    VECTOR 6
+    VECTOR 7
+    VECTOR 8
+    VECTOR 9
+    VECTOR 10
+    VECTOR 11
+    VECTOR 12
+    VECTOR 13
+    VECTOR 14
+    VECTOR 15
+
+__exception_common:
+    stp x2, x3, [sp, #16 * 1]
+    stp x4, x5, [sp, #16 * 2]
+    stp x6, x7, [sp, #16 * 3]
+    stp x8, x9, [sp, #16 * 4]
+    stp x10, x11, [sp, #16 * 5]
+    stp x12, x13, [sp, #16 * 6]
+    stp x14, x15, [sp, #16 * 7]
+    stp x16, x17, [sp, #16 * 8]
+    stp x18, x19, [sp, #16 * 9]
+    stp x20, x21, [sp, #16 * 10]
+    stp x22, x23, [sp, #16 * 11]
+    stp x24, x25, [sp, #16 * 12]
+    stp x26, x27, [sp, #16 * 13]
+    stp x28, x29, [sp, #16 * 14]
+    mrs x2, elr_el1
+    stp x30, x2, [sp, #16 * 15]
+    mrs x2, spsr_el1
+    add x3, sp, #{frame_size}
+    stp x2, x3, [sp, #16 * 16]
+
+    sub sp, sp, #16 * 25
+    stp q0, q1, [sp, #32 * 0]
+    stp q2, q3, [sp, #32 * 1]
+    stp q4, q5, [sp, #32 * 2]
+    stp q6, q7, [sp, #32 * 3]
+    stp q16, q17, [sp, #32 * 4]
+    stp q18, q19, [sp, #32 * 5]
+    stp q20, q21, [sp, #32 * 6]
+    stp q22, q23, [sp, #32 * 7]
+    stp q24, q25, [sp, #32 * 8]
+    stp q26, q27, [sp, #32 * 9]
+    stp q28, q29, [sp, #32 * 10]
+    stp q30, q31, [sp, #32 * 11]
+    mrs x2, fpcr
+    mrs x3, fpsr
+    stp x2, x3, [sp, #32 * 12]
+
+    add x0, sp, #16 * 25
+    bl handle_exception
+
+    ldp x2, x3, [sp, #32 * 12]
+    msr fpcr, x2
+    msr fpsr, x3
+    ldp q0, q1, [sp, #32 * 0]
+    ldp q2, q3, [sp, #32 * 1]
+    ldp q4, q5, [sp, #32 * 2]
+    ldp q6, q7, [sp, #32 * 3]
+    ldp q16, q17, [sp, #32 * 4]
+    ldp q18, q19, [sp, #32 * 5]
+    ldp q20, q21, [sp, #32 * 6]
+    ldp q22, q23, [sp, #32 * 7]
+    ldp q24, q25, [sp, #32 * 8]
+    ldp q26, q27, [sp, #32 * 9]
+    ldp q28, q29, [sp, #32 * 10]
+    ldp q30, q31, [sp, #32 * 11]
+    add sp, sp, #16 * 25
+
+    ldr x2, [sp, #16 * 16]
+    msr spsr_el1, x2
+    ldp x30, x2, [sp, #16 * 15]
+    msr elr_el1, x2
+    ldp x0, x1, [sp, #16 * 0]
+    ldp x2, x3, [sp, #16 * 1]
+    ldp x4, x5, [sp, #16 * 2]
+    ldp x6, x7, [sp, #16 * 3]
+    ldp x8, x9, [sp, #16 * 4]
+    ldp x10, x11, [sp, #16 * 5]
+    ldp x12, x13, [sp, #16 * 6]
+    ldp x14, x15, [sp, #16 * 7]
+    ldp x16, x17, [sp, #16 * 8]
+    ldp x18, x19, [sp, #16 * 9]
+    ldp x20, x21, [sp, #16 * 10]
+    ldp x22, x23, [sp, #16 * 11]
+    ldp x24, x25, [sp, #16 * 12]
+    ldp x26, x27, [sp, #16 * 13]
+    ldp x28, x29, [sp, #16 * 14]
+    add sp, sp, #{frame_size}
+    eret
+"#,
+    frame_size = const TRAP_FRAME_SIZE,
+);
+
+extern "C" {
+    static __exception_vectors: u8;
+}
+
+/// Installs the exception vector table to `VBAR_EL1`.
+pub(crate) fn init() {
+    unsafe {
+        asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) &__exception_vectors as *const u8 as u64,
+            options(nostack),
+        );
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Source {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerElAArch64,
+    LowerElAArch32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Kind {
+    Synchronous,
+    Irq,
+    Fiq,
+    SError,
+}
+
+impl From<u64> for Source {
+    fn from(index: u64) -> Self {
+        match index >> 2 {
+            0 => Self::CurrentElSp0,
+            1 => Self::CurrentElSpx,
+            2 => Self::LowerElAArch64,
+            _ => Self::LowerElAArch32,
+        }
+    }
+}
+
+impl From<u64> for Kind {
+    fn from(index: u64) -> Self {
+        match index & 0b11 {
+            0 => Self::Synchronous,
+            1 => Self::Irq,
+            2 => Self::Fiq,
+            _ => Self::SError,
+        }
+    }
+}
+
+/// Exception class, decoded from `ESR_EL1.EC`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ExceptionClass {
+    Unknown,
+    TrappedWfiWfe,
+    SimdFpAccess,
+    IllegalExecutionState,
+    Svc,
+    Hvc,
+    Smc,
+    TrappedSystemRegister,
+    InstructionAbortLowerEl,
+    InstructionAbortSameEl,
+    PcAlignmentFault,
+    DataAbortLowerEl,
+    DataAbortSameEl,
+    SpAlignmentFault,
+    FpException,
+    SError,
+    BreakpointLowerEl,
+    BreakpointSameEl,
+    SoftwareStepLowerEl,
+    SoftwareStepSameEl,
+    WatchpointLowerEl,
+    WatchpointSameEl,
+    Brk,
+    Other(u8),
+}
+
+impl ExceptionClass {
+    pub(crate) fn is_abort(&self) -> bool {
+        matches!(
+            self,
+            Self::InstructionAbortLowerEl
+                | Self::InstructionAbortSameEl
+                | Self::DataAbortLowerEl
+                | Self::DataAbortSameEl
+        )
+    }
+}
+
+impl From<u64> for ExceptionClass {
+    fn from(esr: u64) -> Self {
+        match (esr >> 26) as u8 & 0x3F {
+            0x00 => Self::Unknown,
+            0x01 => Self::TrappedWfiWfe,
+            0x07 => Self::SimdFpAccess,
+            0x0E => Self::IllegalExecutionState,
+            0x15 => Self::Svc,
+            0x16 => Self::Hvc,
+            0x17 => Self::Smc,
+            0x18 => Self::TrappedSystemRegister,
+            0x20 => Self::InstructionAbortLowerEl,
+            0x21 => Self::InstructionAbortSameEl,
+            0x22 => Self::PcAlignmentFault,
+            0x24 => Self::DataAbortLowerEl,
+            0x25 => Self::DataAbortSameEl,
+            0x26 => Self::SpAlignmentFault,
+            0x2C => Self::FpException,
+            0x2F => Self::SError,
+            0x30 => Self::BreakpointLowerEl,
+            0x31 => Self::BreakpointSameEl,
+            0x32 => Self::SoftwareStepLowerEl,
+            0x33 => Self::SoftwareStepSameEl,
+            0x34 => Self::WatchpointLowerEl,
+            0x35 => Self::WatchpointSameEl,
+            0x3C => Self::Brk,
+            ec => Self::Other(ec),
+        }
+    }
+}
+
+impl Display for ExceptionClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "Unknown reason (undefined instruction)"),
+            Self::TrappedWfiWfe => write!(f, "Trapped WFI/WFE"),
+            Self::SimdFpAccess => write!(f, "Trapped SIMD/FP access"),
+            Self::IllegalExecutionState => write!(f, "Illegal execution state"),
+            Self::Svc => write!(f, "SVC instruction"),
+            Self::Hvc => write!(f, "HVC instruction"),
+            Self::Smc => write!(f, "SMC instruction"),
+            Self::TrappedSystemRegister => write!(f, "Trapped MSR/MRS/system instruction"),
+            Self::InstructionAbortLowerEl => write!(f, "Instruction abort from lower EL"),
+            Self::InstructionAbortSameEl => write!(f, "Instruction abort"),
+            Self::PcAlignmentFault => write!(f, "PC alignment fault"),
+            Self::DataAbortLowerEl => write!(f, "Data abort from lower EL"),
+            Self::DataAbortSameEl => write!(f, "Data abort"),
+            Self::SpAlignmentFault => write!(f, "SP alignment fault"),
+            Self::FpException => write!(f, "Floating-point exception"),
+            Self::SError => write!(f, "SError interrupt"),
+            Self::BreakpointLowerEl | Self::BreakpointSameEl => write!(f, "Breakpoint"),
+            Self::SoftwareStepLowerEl | Self::SoftwareStepSameEl => write!(f, "Software step"),
+            Self::WatchpointLowerEl | Self::WatchpointSameEl => write!(f, "Watchpoint"),
+            Self::Brk => write!(f, "BRK instruction"),
+            Self::Other(ec) => write!(f, "Exception class {:#04x}", ec),
+        }
+    }
+}
+
+/// Fault status of instruction and data aborts, decoded from `ESR_EL1.ISS`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum FaultStatus {
+    AddressSize(u8),
+    Translation(u8),
+    AccessFlag(u8),
+    Permission(u8),
+    SynchronousExternal,
+    Alignment,
+    Other(u8),
+}
+
+impl From<u64> for FaultStatus {
+    fn from(esr: u64) -> Self {
+        let status = esr as u8 & 0x3F;
+        let level = status & 0b11;
+        match status >> 2 {
+            0b0000 => Self::AddressSize(level),
+            0b0001 => Self::Translation(level),
+            0b0010 => Self::AccessFlag(level),
+            0b0011 => Self::Permission(level),
+            _ if status == 0b010000 => Self::SynchronousExternal,
+            _ if status == 0b100001 => Self::Alignment,
+            _ => Self::Other(status),
+        }
+    }
+}
+
+impl Display for FaultStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::AddressSize(level) => write!(f, "address size fault at level {}", level),
+            Self::Translation(level) => write!(f, "translation fault at level {}", level),
+            Self::AccessFlag(level) => write!(f, "access flag fault at level {}", level),
+            Self::Permission(level) => write!(f, "permission fault at level {}", level),
+            Self::SynchronousExternal => write!(f, "synchronous external abort"),
+            Self::Alignment => write!(f, "alignment fault"),
+            Self::Other(status) => write!(f, "fault status {:#04x}", status),
+        }
+    }
+}
+
+#[inline]
+fn esr() -> u64 {
+    let esr: u64;
+    unsafe { asm!("mrs {}, esr_el1", out(reg) esr, options(nomem, nostack)) };
+    esr
+}
+
+#[inline]
+fn far() -> u64 {
+    let far: u64;
+    unsafe { asm!("mrs {}, far_el1", out(reg) far, options(nomem, nostack)) };
+    far
+}
+
+#[no_mangle]
+extern "C" fn handle_exception(frame: &mut TrapFrame, index: u64) {
+    let (source, kind) = (Source::from(index), Kind::from(index));
+    if kind == Kind::Irq {
+        crate::interrupts::handle_irq();
+        // Switching only after the end of interrupt, or the timer would stop for the next task.
+        return crate::task::preempt();
+    }
+
+    crate::panic::take_over();
+    println!("*** {:?} exception from {:?} ***", kind, source);
+
+    if kind == Kind::Synchronous {
+        let esr = esr();
+        let class = ExceptionClass::from(esr);
+
+        println!("{}", class);
+        if class.is_abort() {
+            let write = esr & (1 << 6) != 0;
+            println!(
+                "  {} while {} {:#018x}",
+                FaultStatus::from(esr),
+                if write { "writing" } else { "reading" },
+                far()
+            );
+        }
+
+        println!("ESR_EL1: {:#018x}  FAR_EL1: {:#018x}", esr, far());
+    }
+
+    dump(frame);
+    backtrace::print(
+        core::iter::once(frame.elr as usize)
+            .chain(Backtrace::from_frame_pointer(frame.x[29] as usize)),
+    );
+
+    crate::panic::park()
+}
+
+fn dump(frame: &TrapFrame) {
+    println!(
+        "ELR: {:#018x}  SPSR: {:#010x}  SP: {:#018x}",
+        frame.elr, frame.spsr, frame.sp
+    );
+
+    frame.x.chunks_exact(3).enumerate().for_each(|(i, x)| {
+        println!(
+            "x{:02}: {:#018x}  x{:02}: {:#018x}  x{:02}: {:#018x}",
+            i * 3,
+            x[0],
+            i * 3 + 1,
+            x[1],
+            i * 3 + 2,
+            x[2]
+        )
+    });
+    println!("x30: {:#018x}", frame.x[30]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_vector_index() {
+        assert_eq!(
+            (Source::CurrentElSpx, Kind::Synchronous),
+            (4.into(), 4.into())
+        );
+        assert_eq!((Source::LowerElAArch64, Kind::Irq), (9.into(), 9.into()));
+        assert_eq!(
+            (Source::LowerElAArch32, Kind::SError),
+            (15.into(), 15.into())
+        );
+    }
+
+    #[test]
+    fn decode_data_abort() {
+        let esr = 0x96000045;
+
+        assert_eq!(ExceptionClass::DataAbortSameEl, esr.into());
+        assert_eq!(FaultStatus::Translation(1), esr.into());
+    }
+
+    #[test]
+    fn decode_undefined_instruction() {
+        assert_eq!(ExceptionClass::Unknown, 0x02000000.into());
+        assert_eq!(ExceptionClass::Brk, 0xF2000000.into());
+        assert_eq!(ExceptionClass::Other(0x3F), 0xFC000000.into());
+    }
+}
@@ -0,0 +1,745 @@
+//! Parser of the flattened device tree (DTB) which the firmware installs as a configuration table.
+
+use crate::interrupts::{IntId, Trigger};
+use crate::pci::EcamWindow;
+use crate::psci::Conduit;
+use crate::timer::TimerInterrupts;
+
+const MAGIC: u32 = 0xD00D_FEED;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// Deepest nesting of nodes to follow, which is far more than real trees have.
+const MAX_DEPTH: usize = 16;
+
+/// Cells of `reg` unless the parent specifies `#address-cells` and `#size-cells`.
+const DEFAULT_CELLS: Cells = Cells {
+    address: 2,
+    size: 1,
+};
+
+/// Space code in the first cell of a PCI address, for the 32-bit memory space.
+const PCI_SPACE_MEMORY32: u32 = 0b10;
+
+/// Interrupt types in the first cell of a GIC interrupt specifier.
+const GIC_SPI: u32 = 0;
+const GIC_PPI: u32 = 1;
+
+/// Bits in the flags cell of a GIC interrupt specifier for the level-sensitive types.
+const IRQ_TYPE_LEVEL_MASK: u32 = 0xC;
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[inline]
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn read_str(bytes: &[u8], offset: usize) -> Option<&str> {
+    let bytes = bytes.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Reads a number spanning the big-endian cells.
+fn fold_cells(bytes: &[u8]) -> u64 {
+    bytes.chunks_exact(4).fold(0, |acc, cell| {
+        acc << 32 | u32::from_be_bytes(cell.try_into().unwrap()) as u64
+    })
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Cells {
+    address: u32,
+    size: u32,
+}
+
+/// A range of physical addresses taken from a `reg` property.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Reg {
+    pub(crate) address: usize,
+    pub(crate) size: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Fdt<'a> {
+    data: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    pub(crate) fn from_bytes(data: &'a [u8]) -> Option<Self> {
+        if read_u32(data, 0)? != MAGIC || data.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let total_size = read_u32(data, 4)? as usize;
+        let structs_offset = read_u32(data, 8)? as usize;
+        let strings_offset = read_u32(data, 12)? as usize;
+        let strings_size = read_u32(data, 32)? as usize;
+        let structs_size = read_u32(data, 36)? as usize;
+        let data = data.get(..total_size)?;
+
+        Some(Self {
+            data,
+            structs: data.get(structs_offset..structs_offset + structs_size)?,
+            strings: data.get(strings_offset..strings_offset + strings_size)?,
+        })
+    }
+
+    /// # Safety
+    /// `ptr` must point to a device tree blob which lives as long as the kernel.
+    pub(crate) unsafe fn from_ptr(ptr: *const u8) -> Option<Fdt<'static>> {
+        let header = core::slice::from_raw_parts(ptr, HEADER_SIZE);
+        if read_u32(header, 0)? != MAGIC {
+            return None;
+        }
+
+        let total_size = read_u32(header, 4)? as usize;
+        Fdt::from_bytes(core::slice::from_raw_parts(ptr, total_size))
+    }
+
+    /// The whole blob, which must be kept from being overwritten.
+    #[inline]
+    pub(crate) fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// All nodes in depth-first order, starting from the root.
+    pub(crate) fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            cells: [DEFAULT_CELLS; MAX_DEPTH + 1],
+        }
+    }
+
+    /// Finds the first node compatible with any of the strings.
+    pub(crate) fn find_compatible(&self, compatible: &[&str]) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Regions of the physical memory described by `memory` nodes.
+    pub(crate) fn memory(&self) -> impl Iterator<Item = Reg> + 'a {
+        self.nodes()
+            .filter(|node| {
+                node.property("device_type")
+                    .and_then(|p| p.as_str())
+                    .is_some_and(|ty| ty == "memory")
+            })
+            .flat_map(|node| node.reg())
+    }
+
+    /// Base address of the first PL011 UART.
+    pub(crate) fn uart(&self) -> Option<usize> {
+        Some(self.find_compatible(&["arm,pl011"])?.reg().next()?.address)
+    }
+
+    /// Registers and interrupts of the virtio-mmio transports, most of which have no device behind.
+    pub(crate) fn virtio_mmio(&self) -> impl Iterator<Item = (usize, IntId, Trigger)> + 'a {
+        self.nodes()
+            .filter(|node| node.is_compatible(&["virtio,mmio"]))
+            .filter_map(|node| {
+                let (intid, trigger) = node.interrupts()?.next()?;
+                Some((node.reg().next()?.address, intid, trigger))
+            })
+    }
+
+    /// MPIDRs of the cores from `reg` of the `cpu` nodes, skipping those disabled.
+    pub(crate) fn cpus(&self) -> impl Iterator<Item = u64> + 'a {
+        let string = |node: &Node<'a>, name| node.property(name).and_then(|p| p.as_str());
+        self.nodes()
+            .filter(move |node| string(node, "device_type") == Some("cpu"))
+            .filter(move |node| string(node, "status").is_none_or(|s| s == "okay" || s == "ok"))
+            .filter_map(|node| Some(node.reg().next()?.address as u64))
+    }
+
+    /// How to call PSCI 0.2 or later, whose functions have the standard IDs.
+    pub(crate) fn psci(&self) -> Option<Conduit> {
+        let node = self.find_compatible(&["arm,psci-1.0", "arm,psci-0.2"])?;
+        Conduit::from_method(node.property("method")?.as_str()?)
+    }
+
+    /// INTIDs of the architected timer.
+    pub(crate) fn timer_interrupts(&self) -> Option<TimerInterrupts> {
+        let node = self.find_compatible(&["arm,armv8-timer", "arm,armv7-timer"])?;
+        let mut interrupts = node.interrupts()?.map(|(intid, _)| intid);
+
+        Some(TimerInterrupts {
+            secure_physical: interrupts.next()?,
+            non_secure_physical: interrupts.next()?,
+            virtual_: interrupts.next()?,
+            hypervisor_physical: interrupts.next()?,
+        })
+    }
+
+    /// The ECAM window of the first generic PCIe host bridge.
+    pub(crate) fn pci_ecam(&self) -> Option<EcamWindow> {
+        let node = self.find_compatible(&["pci-host-ecam-generic"])?;
+        let reg = node.reg().next()?;
+        let (bus_start, bus_end) = match node.property("bus-range") {
+            Some(p) => (p.u32s().next()?, p.u32s().nth(1)?),
+            None => (0, 255),
+        };
+
+        Some(EcamWindow {
+            base: reg.address,
+            size: reg.size,
+            bus_start: bus_start as u8,
+            bus_end: bus_end as u8,
+        })
+    }
+
+    /// CPU addresses of the 32-bit memory space of the first generic PCIe host bridge, from `ranges`
+    /// whose entries are the PCI address in three cells, the CPU address and the size.
+    pub(crate) fn pci_memory_window(&self) -> Option<Reg> {
+        let node = self.find_compatible(&["pci-host-ecam-generic"])?;
+        let cpu = 12 + node.cells.address as usize * 4;
+        let size = node.own_cells().size as usize * 4;
+
+        node.property("ranges")?
+            .value
+            .chunks_exact(cpu + size)
+            .find(|entry| (read_u32(entry, 0).unwrap() >> 24) & 0b11 == PCI_SPACE_MEMORY32)
+            .map(|entry| Reg {
+                address: fold_cells(&entry[12..cpu]) as usize,
+                size: fold_cells(&entry[cpu..]) as usize,
+            })
+    }
+
+    fn token(&self, offset: usize) -> Option<u32> {
+        read_u32(self.structs, offset)
+    }
+
+    /// Skips the properties and `NOP`s from the offset, returning where the next token is.
+    fn skip_properties(&self, mut offset: usize) -> Option<usize> {
+        loop {
+            match self.token(offset)? {
+                FDT_PROP => {
+                    let len = self.token(offset + 4)? as usize;
+                    offset = align4(offset + 12 + len);
+                }
+                FDT_NOP => offset += 4,
+                _ => return Some(offset),
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first token after the name.
+    offset: usize,
+    /// Cells specified by the parent, used to decode `reg` of this node.
+    cells: Cells,
+}
+
+impl<'a> Node<'a> {
+    /// Name of the node including the unit address, e.g. `pl011@9000000`.
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub(crate) fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.offset,
+        }
+    }
+
+    pub(crate) fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    pub(crate) fn is_compatible(&self, compatible: &[&str]) -> bool {
+        self.property("compatible")
+            .is_some_and(|p| p.strs().any(|s| compatible.contains(&s)))
+    }
+
+    /// Address ranges in `reg`, decoded with the cells of the parent.
+    pub(crate) fn reg(&self) -> impl Iterator<Item = Reg> + 'a {
+        let Cells { address, size } = self.cells;
+        let mut cells = self.property("reg").into_iter().flat_map(|p| p.u32s());
+
+        core::iter::from_fn(move || {
+            let mut read =
+                |count| (0..count).try_fold(0u64, |acc, _| Some(acc << 32 | cells.next()? as u64));
+            Some(Reg {
+                address: read(address)? as usize,
+                size: read(size)? as usize,
+            })
+        })
+    }
+
+    /// INTIDs and triggers in `interrupts`, assuming the three-cell specifiers of the GIC bindings.
+    pub(crate) fn interrupts(&self) -> Option<impl Iterator<Item = (IntId, Trigger)> + 'a> {
+        let mut cells = self.property("interrupts")?.u32s();
+
+        Some(core::iter::from_fn(move || {
+            let (ty, number, flags) = (cells.next()?, cells.next()?, cells.next()?);
+            let intid = match ty {
+                GIC_SPI => number + 32,
+                GIC_PPI => number + 16,
+                _ => return None,
+            };
+            let trigger = match flags & IRQ_TYPE_LEVEL_MASK {
+                0 => Trigger::Edge,
+                _ => Trigger::Level,
+            };
+            Some((intid, trigger))
+        }))
+    }
+
+    fn own_cells(&self) -> Cells {
+        let cells = |name| self.property(name).and_then(|p| p.as_u32());
+        Cells {
+            address: cells("#address-cells").unwrap_or(DEFAULT_CELLS.address),
+            size: cells("#size-cells").unwrap_or(DEFAULT_CELLS.size),
+        }
+    }
+}
+
+/// Iterator of nodes in depth-first order.
+pub(crate) struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+    /// Cells specified by the nodes on the current path, indexed by the depth of their children.
+    cells: [Cells; MAX_DEPTH + 1],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.offset = self.fdt.skip_properties(self.offset)?;
+            match self.fdt.token(self.offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = read_str(self.fdt.structs, self.offset + 4)?;
+                    let node = Node {
+                        fdt: self.fdt,
+                        name,
+                        offset: align4(self.offset + 4 + name.len() + 1),
+                        cells: self.cells[self.depth],
+                    };
+
+                    if self.depth >= MAX_DEPTH {
+                        return None;
+                    }
+
+                    self.depth += 1;
+                    self.cells[self.depth] = node.own_cells();
+                    self.offset = node.offset;
+                    return Some(node);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset += 4;
+                }
+                // `FDT_END`, or a corrupted token.
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Property<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => read_u32(self.value, 0),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&'a str> {
+        self.strs().next()
+    }
+
+    /// Cells of the value in big-endian.
+    pub(crate) fn u32s(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+    }
+
+    /// Strings of a string list, separated by NULs.
+    pub(crate) fn strs(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+
+/// Iterator of the properties of a node, stopping at the first child or the end of the node.
+pub(crate) struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset)? {
+                FDT_PROP => {
+                    let len = self.fdt.token(self.offset + 4)? as usize;
+                    let name_offset = self.fdt.token(self.offset + 8)? as usize;
+                    let start = self.offset + 12;
+                    self.offset = align4(start + len);
+
+                    return Some(Property {
+                        name: read_str(self.fdt.strings, name_offset)?,
+                        value: self.fdt.structs.get(start..start + len)?,
+                    });
+                }
+                FDT_NOP => self.offset += 4,
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FDT_END: u32 = 0x9;
+
+    /// Builds a blob in the same layout as `dtc` does.
+    #[derive(Default)]
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend(token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structs.resize(align4(self.structs.len()), 0);
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend(name.as_bytes());
+            self.strings.push(0);
+
+            self.token(FDT_PROP)
+                .token(value.len() as u32)
+                .token(name_offset);
+            self.structs.extend(value);
+            self.pad();
+            self
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let value = cells
+                .iter()
+                .flat_map(|c| c.to_be_bytes())
+                .collect::<Vec<_>>();
+            self.prop(name, &value)
+        }
+
+        fn build(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+
+            let structs_offset = HEADER_SIZE + 16;
+            let strings_offset = structs_offset + self.structs.len();
+            let total_size = strings_offset + self.strings.len();
+
+            let mut blob = Vec::new();
+            [
+                MAGIC,
+                total_size as u32,
+                structs_offset as u32,
+                strings_offset as u32,
+                HEADER_SIZE as u32,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ]
+            .iter()
+            .for_each(|v| blob.extend(v.to_be_bytes()));
+            blob.extend([0; 16]);
+            blob.extend(&self.structs);
+            blob.extend(&self.strings);
+            blob
+        }
+    }
+
+    /// A subset of the tree which QEMU generates for `-machine virt`.
+    fn qemu_virt() -> Vec<u8> {
+        Builder::default()
+            .begin("")
+            .cells("#address-cells", &[2])
+            .cells("#size-cells", &[2])
+            .prop("compatible", b"linux,dummy-virt\0")
+            .begin("psci")
+            .prop("compatible", b"arm,psci-1.0\0arm,psci-0.2\0arm,psci\0")
+            .prop("method", b"hvc\0")
+            .end()
+            .begin("memory@40000000")
+            .prop("device_type", b"memory\0")
+            .cells("reg", &[0, 0x4000_0000, 0, 0x2000_0000])
+            .end()
+            .begin("virtio_mmio@a000000")
+            .prop("compatible", b"virtio,mmio\0")
+            .cells("reg", &[0, 0x0A00_0000, 0, 0x200])
+            .cells("interrupts", &[0, 16, 1])
+            .end()
+            .begin("pl011@9000000")
+            .prop("compatible", b"arm,pl011\0arm,primecell\0")
+            .cells("reg", &[0, 0x0900_0000, 0, 0x1000])
+            .end()
+            .begin("intc@8000000")
+            .prop("compatible", b"arm,cortex-a15-gic\0")
+            .cells(
+                "reg",
+                &[0, 0x0800_0000, 0, 0x10000, 0, 0x0801_0000, 0, 0x10000],
+            )
+            .end()
+            .begin("timer")
+            .prop("compatible", b"arm,armv8-timer\0arm,armv7-timer\0")
+            .cells(
+                "interrupts",
+                &[1, 13, 0x104, 1, 14, 0x104, 1, 11, 0x104, 1, 10, 0x104],
+            )
+            .end()
+            .begin("cpus")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[0])
+            .begin("cpu@0")
+            .prop("device_type", b"cpu\0")
+            .cells("reg", &[0])
+            .end()
+            .begin("cpu@1")
+            .prop("device_type", b"cpu\0")
+            .cells("reg", &[1])
+            .end()
+            .begin("cpu@2")
+            .prop("device_type", b"cpu\0")
+            .prop("status", b"disabled\0")
+            .cells("reg", &[2])
+            .end()
+            .end()
+            .begin("pcie@10000000")
+            .prop("compatible", b"pci-host-ecam-generic\0")
+            .cells("#address-cells", &[3])
+            .cells("#size-cells", &[2])
+            .cells("bus-range", &[0, 0xFF])
+            .cells("reg", &[0x40, 0x1000_0000, 0, 0x1000_0000])
+            .cells(
+                "ranges",
+                &[
+                    0x0100_0000,
+                    0,
+                    0,
+                    0,
+                    0x3EFF_0000,
+                    0,
+                    0x1_0000, // I/O space
+                    0x0200_0000,
+                    0,
+                    0x1000_0000,
+                    0,
+                    0x1000_0000,
+                    0,
+                    0x2EFF_0000, // 32-bit memory
+                    0x0300_0000,
+                    0x80,
+                    0,
+                    0x80,
+                    0,
+                    0x80,
+                    0, // 64-bit memory
+                ],
+            )
+            .end()
+            .end()
+            .build()
+    }
+
+    #[test]
+    fn reject_invalid_blob() {
+        assert!(Fdt::from_bytes(&[0; 64]).is_none());
+
+        let mut blob = qemu_virt();
+        blob.truncate(blob.len() - 1);
+        assert!(Fdt::from_bytes(&blob).is_none());
+    }
+
+    #[test]
+    fn walk_nodes() {
+        let blob = qemu_virt();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+
+        assert_eq!(
+            vec![
+                "",
+                "psci",
+                "memory@40000000",
+                "virtio_mmio@a000000",
+                "pl011@9000000",
+                "intc@8000000",
+                "timer",
+                "cpus",
+                "cpu@0",
+                "cpu@1",
+                "cpu@2",
+                "pcie@10000000"
+            ],
+            fdt.nodes().map(|n| n.name()).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn read_properties() {
+        let blob = qemu_virt();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let uart = fdt.find_compatible(&["arm,primecell"]).unwrap();
+
+        assert_eq!("pl011@9000000", uart.name());
+        assert_eq!(
+            vec!["arm,pl011", "arm,primecell"],
+            uart.property("compatible")
+                .unwrap()
+                .strs()
+                .collect::<Vec<_>>(),
+        );
+        assert!(uart.property("interrupts").is_none());
+        assert_eq!(Some(0x0900_0000), fdt.uart());
+    }
+
+    #[test]
+    fn decode_reg_with_parent_cells() {
+        let blob = Builder::default()
+            .begin("")
+            .begin("soc")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1])
+            .begin("device@1000")
+            .cells("reg", &[0x1000, 0x100, 0x2000, 0x200])
+            .end()
+            .end()
+            .begin("device@3000")
+            .cells("reg", &[0, 0x3000, 0x300])
+            .end()
+            .end()
+            .build();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let reg = |name| {
+            fdt.nodes()
+                .find(|n| n.name() == name)
+                .unwrap()
+                .reg()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                Reg {
+                    address: 0x1000,
+                    size: 0x100
+                },
+                Reg {
+                    address: 0x2000,
+                    size: 0x200
+                },
+            ],
+            reg("device@1000"),
+        );
+        assert_eq!(
+            vec![Reg {
+                address: 0x3000,
+                size: 0x300
+            }],
+            reg("device@3000"),
+        );
+    }
+
+    #[test]
+    fn discover_qemu_virt() {
+        let blob = qemu_virt();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+
+        assert_eq!(
+            vec![Reg {
+                address: 0x4000_0000,
+                size: 0x2000_0000
+            }],
+            fdt.memory().collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            Some(TimerInterrupts {
+                secure_physical: 29,
+                non_secure_physical: 30,
+                virtual_: 27,
+                hypervisor_physical: 26,
+            }),
+            fdt.timer_interrupts(),
+        );
+        assert_eq!(
+            Some(EcamWindow {
+                base: 0x40_1000_0000,
+                size: 0x1000_0000,
+                bus_start: 0,
+                bus_end: 0xFF,
+            }),
+            fdt.pci_ecam(),
+        );
+        assert_eq!(
+            Some(Reg {
+                address: 0x1000_0000,
+                size: 0x2EFF_0000
+            }),
+            fdt.pci_memory_window(),
+        );
+        assert_eq!(
+            vec![(0x0A00_0000, 48, Trigger::Edge)],
+            fdt.virtio_mmio().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![0, 1], fdt.cpus().collect::<Vec<_>>());
+        assert_eq!(Some(Conduit::Hvc), fdt.psci());
+    }
+}
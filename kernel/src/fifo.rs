@@ -0,0 +1,217 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+struct Slot<T> {
+    /// Lap counter of the slot, stored relative to the index of the slot so that a zeroed
+    /// array is a valid empty queue.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        sequence: AtomicUsize::new(0),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+    };
+}
+
+/// Fixed-capacity lock-free ring buffer, which any number of producers and consumers can use
+/// concurrently, including interrupt handlers.
+pub(crate) struct Fifo<T, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T, const N: usize> Sync for Fifo<T, N> where T: Send {}
+
+impl<T, const N: usize> Fifo<T, N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            slots: [Slot::EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    fn sequence(&self, position: usize) -> usize {
+        let index = position % N;
+        self.slots[index]
+            .sequence
+            .load(Ordering::Acquire)
+            .wrapping_add(index)
+    }
+
+    #[inline]
+    fn set_sequence(&self, position: usize, sequence: usize) {
+        let index = position % N;
+        self.slots[index]
+            .sequence
+            .store(sequence.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// Appends the value to the end, or gives it back if the buffer is full.
+    pub(crate) fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.tail.load(Ordering::Relaxed);
+        loop {
+            let difference = self.sequence(position) as isize - position as isize;
+            if difference == 0 {
+                match self.tail.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => position = current,
+                }
+            } else if difference < 0 {
+                return Err(value);
+            } else {
+                position = self.tail.load(Ordering::Relaxed);
+            }
+        }
+
+        unsafe { (*self.slots[position % N].value.get()).write(value) };
+        self.set_sequence(position, position.wrapping_add(1));
+        Ok(())
+    }
+
+    /// Takes the value at the front, if any.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut position = self.head.load(Ordering::Relaxed);
+        loop {
+            let difference = self.sequence(position) as isize - position.wrapping_add(1) as isize;
+            if difference == 0 {
+                match self.head.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => position = current,
+                }
+            } else if difference < 0 {
+                return None;
+            } else {
+                position = self.head.load(Ordering::Relaxed);
+            }
+        }
+
+        let value = unsafe { (*self.slots[position % N].value.get()).assume_init_read() };
+        self.set_sequence(position, position.wrapping_add(N));
+        Some(value)
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of values in the buffer, which may be outdated as soon as it returns.
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(N)
+    }
+}
+
+impl<T, const N: usize> Drop for Fifo<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop_in_order() {
+        let fifo = Fifo::<u32, 4>::new();
+
+        assert_eq!(None, fifo.pop());
+        assert_eq!(Ok(()), fifo.push(1));
+        assert_eq!(Ok(()), fifo.push(2));
+        assert_eq!(2, fifo.len());
+        assert_eq!(Some(1), fifo.pop());
+        assert_eq!(Some(2), fifo.pop());
+        assert!(fifo.is_empty());
+    }
+
+    #[test]
+    fn wraparound() {
+        let fifo = Fifo::<usize, 3>::new();
+
+        (0..100).for_each(|i| {
+            assert_eq!(Ok(()), fifo.push(i));
+            assert_eq!(Ok(()), fifo.push(i + 1000));
+            assert_eq!(Some(i), fifo.pop());
+            assert_eq!(Some(i + 1000), fifo.pop());
+        });
+        assert!(fifo.is_empty());
+    }
+
+    #[test]
+    fn overflow() {
+        let fifo = Fifo::<u32, 2>::new();
+
+        assert_eq!(Ok(()), fifo.push(1));
+        assert_eq!(Ok(()), fifo.push(2));
+        assert_eq!(Err(3), fifo.push(3));
+        assert_eq!(2, fifo.len());
+
+        assert_eq!(Some(1), fifo.pop());
+        assert_eq!(Ok(()), fifo.push(3));
+        assert_eq!(Some(2), fifo.pop());
+        assert_eq!(Some(3), fifo.pop());
+    }
+
+    #[test]
+    fn drop_remaining_values() {
+        let value = std::rc::Rc::new(());
+        {
+            let fifo = Fifo::<_, 4>::new();
+            fifo.push(value.clone()).unwrap();
+            fifo.push(value.clone()).unwrap();
+            assert_eq!(3, std::rc::Rc::strong_count(&value));
+        }
+        assert_eq!(1, std::rc::Rc::strong_count(&value));
+    }
+
+    #[test]
+    fn concurrent_producers() {
+        static FIFO: Fifo<usize, 64> = Fifo::new();
+
+        let producers = (0..4)
+            .map(|p| {
+                std::thread::spawn(move || {
+                    (0..1000).for_each(|i| {
+                        while FIFO.push(p * 1000 + i).is_err() {
+                            std::thread::yield_now();
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut received = Vec::new();
+        while received.len() < 4000 {
+            match FIFO.pop() {
+                Some(v) => received.push(v),
+                None => std::thread::yield_now(),
+            }
+        }
+        producers.into_iter().for_each(|p| p.join().unwrap());
+
+        received.sort_unstable();
+        assert_eq!((0..4000).collect::<Vec<_>>(), received);
+    }
+}
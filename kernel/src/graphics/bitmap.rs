@@ -0,0 +1,130 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter::{Enumerate, Map};
+use core::slice::IterMut;
+
+use super::*;
+
+/// Off-screen canvas holding the pixels in the format of the frame buffer, so that they are
+/// copied to it as they are.
+pub(crate) struct Bitmap {
+    buf: Vec<[u8; PIXEL_SIZE]>,
+    width: usize,
+    height: usize,
+    pixel_format: PixelFormat,
+    transparent: Option<[u8; PIXEL_SIZE]>,
+}
+
+impl Bitmap {
+    /// Creates a bitmap filled in black.
+    pub(crate) fn new(width: usize, height: usize, pixel_format: PixelFormat) -> Self {
+        Self {
+            buf: vec![[0; PIXEL_SIZE]; width * height],
+            width,
+            height,
+            pixel_format,
+            transparent: None,
+        }
+    }
+
+    /// Makes the pixels in the color see-through, when drawn over another bitmap.
+    pub(crate) fn with_transparent<C>(mut self, color: C) -> Self
+    where
+        C: Into<Color>,
+    {
+        self.transparent = Some(pixel_format_to_writer(self.pixel_format).write(color.into()));
+        self
+    }
+
+    #[inline]
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub(crate) fn region(&self) -> Region {
+        Region::new(Position::zero(), self.width, self.height)
+    }
+
+    /// Raw pixels in the row from the position, up to the right edge.
+    pub(crate) fn row(&self, position: Position) -> &[[u8; PIXEL_SIZE]] {
+        let start = position.into_raw_parts(self.width);
+        &self.buf[start..start + self.width - position.x]
+    }
+
+    /// Copies the region of the other bitmap to the position, skipping its transparent pixels.
+    ///
+    /// The region must be within the other bitmap, and the copy within this one.
+    pub(crate) fn copy_from(&mut self, position: Position, src: &Self, region: Region) {
+        (0..region.height).for_each(|y| {
+            let from = src.row(region.position + (0, y).into());
+            let start = (position + (0, y).into()).into_raw_parts(self.width);
+            let to = &mut self.buf[start..start + region.width];
+
+            match src.transparent {
+                Some(transparent) => to
+                    .iter_mut()
+                    .zip(from)
+                    .filter(|(_, p)| **p != transparent)
+                    .for_each(|(to, from)| *to = *from),
+                None => to.copy_from_slice(&from[..region.width]),
+            }
+        });
+    }
+}
+
+impl Canvas for Bitmap {
+    #[rustfmt::skip]
+    type Pixels<'b> =
+        Map<Enumerate<IterMut<'b, [u8; 4]>>, impl FnMut((usize, &'b mut [u8; 4])) -> Pixel>
+    where
+        Self: 'b;
+
+    fn pixels(&mut self) -> Self::Pixels<'_> {
+        let width = self.width;
+        let pixel_format = self.pixel_format;
+        self.buf.iter_mut().enumerate().map(move |(i, buf)| Pixel {
+            buf,
+            position: Position::from_raw_parts(i, width),
+            writer: pixel_format_to_writer(pixel_format),
+        })
+    }
+
+    fn at(&mut self, position: Position) -> Option<Pixel<'_>> {
+        if position.x >= self.width || position.y >= self.height {
+            return None;
+        }
+
+        Some(Pixel {
+            buf: &mut self.buf[position.into_raw_parts(self.width)],
+            position,
+            writer: pixel_format_to_writer(self.pixel_format),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_skipping_transparent_pixels() {
+        let format = PixelFormat::RgbResv8BitPerColor;
+        let mut dst = Bitmap::new(4, 4, format);
+        dst.fill(Colors::blue());
+        let mut src = Bitmap::new(2, 2, format).with_transparent(Colors::green());
+        src.fill(Colors::green());
+        src.at((1, 1).into()).unwrap().write(Colors::red());
+
+        dst.copy_from((2, 1).into(), &src, src.region());
+
+        let blue = [0, 0, 0xFF, 0];
+        assert_eq!(&[blue; 4], dst.row((0, 1).into()));
+        assert_eq!(&[blue, blue, blue, [0xFF, 0, 0, 0]], dst.row((0, 2).into()));
+    }
+}
@@ -20,4 +20,7 @@ colors!(
     (red, 0xFF, 0x00, 0x00),
     (green, 0x00, 0xFF, 0x00),
     (blue, 0x00, 0x00, 0xFF),
+    (gray, 0x84, 0x84, 0x84),
+    (light_gray, 0xC6, 0xC6, 0xC6),
+    (navy, 0x00, 0x00, 0x84),
 );
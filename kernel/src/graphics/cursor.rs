@@ -0,0 +1,152 @@
+//! Mouse cursor on the top layer, moved around within the screen.
+
+use mikan_core::PixelFormat;
+
+use super::bitmap::Bitmap;
+use super::layer::{LayerId, LayerManager};
+use super::{Canvas, Color, Colors, Position};
+
+const WIDTH: usize = 15;
+const HEIGHT: usize = 24;
+
+/// The arrow, where `@` is the border, `.` is the inside and spaces are transparent.
+const SHAPE: [&[u8; WIDTH]; HEIGHT] = [
+    b"@              ",
+    b"@@             ",
+    b"@.@            ",
+    b"@..@           ",
+    b"@...@          ",
+    b"@....@         ",
+    b"@.....@        ",
+    b"@......@       ",
+    b"@.......@      ",
+    b"@........@     ",
+    b"@.........@    ",
+    b"@..........@   ",
+    b"@...........@  ",
+    b"@............@ ",
+    b"@......@@@@@@@@",
+    b"@......@       ",
+    b"@....@@.@      ",
+    b"@...@ @.@      ",
+    b"@..@   @.@     ",
+    b"@.@    @.@     ",
+    b"@@      @.@    ",
+    b"@       @.@    ",
+    b"         @.@   ",
+    b"         @@@   ",
+];
+
+/// Color of the transparent pixels, which the arrow does not use.
+fn transparent() -> Color {
+    Colors::green()
+}
+
+/// The arrow whose tip is at the position, on a layer of its own.
+pub(crate) struct Cursor {
+    layer: LayerId,
+    position: Position,
+    bounds: (usize, usize),
+}
+
+impl Cursor {
+    /// Creates the layer of the cursor and shows it, with the tip within the width and the height.
+    pub(crate) fn new(
+        layers: &mut LayerManager,
+        pixel_format: PixelFormat,
+        position: Position,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let mut bitmap = Bitmap::new(WIDTH, HEIGHT, pixel_format).with_transparent(transparent());
+        bitmap.fill_by(|Position { x, y }| match SHAPE[y][x] {
+            b'@' => Colors::black(),
+            b'.' => Colors::white(),
+            _ => transparent(),
+        });
+
+        let mut cursor = Self {
+            layer: layers.create(bitmap),
+            position,
+            bounds: (width, height),
+        };
+        cursor.position = cursor.clamp(position.x as i64, position.y as i64);
+        layers.move_to(cursor.layer, cursor.position);
+        layers.show(cursor.layer);
+        cursor
+    }
+
+    #[inline]
+    pub(crate) fn layer(&self) -> LayerId {
+        self.layer
+    }
+
+    #[inline]
+    pub(crate) fn position(&self) -> Position {
+        self.position
+    }
+
+    fn clamp(&self, x: i64, y: i64) -> Position {
+        let (width, height) = self.bounds;
+        (
+            x.clamp(0, width.saturating_sub(1) as i64) as usize,
+            y.clamp(0, height.saturating_sub(1) as i64) as usize,
+        )
+            .into()
+    }
+
+    pub(crate) fn move_to(&mut self, layers: &mut LayerManager, position: Position) {
+        self.position = self.clamp(position.x as i64, position.y as i64);
+        layers.move_to(self.layer, self.position);
+    }
+
+    pub(crate) fn move_by(&mut self, layers: &mut LayerManager, dx: i32, dy: i32) {
+        let position = self.clamp(
+            self.position.x as i64 + dx as i64,
+            self.position.y as i64 + dy as i64,
+        );
+        self.move_to(layers, position);
+    }
+
+    /// Moves the tip to the point of an absolute pointer, whose axes span `u16`.
+    pub(crate) fn point_at(&mut self, layers: &mut LayerManager, x: u16, y: u16) {
+        let (width, height) = self.bounds;
+        let scale =
+            |value: u16, len: usize| value as usize * len.saturating_sub(1) / u16::MAX as usize;
+        self.move_to(layers, (scale(x, width), scale(y, height)).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: PixelFormat = PixelFormat::RgbResv8BitPerColor;
+
+    #[test]
+    fn draw_arrow_on_transparent_layer() {
+        let mut layers = LayerManager::new(64, 64, FORMAT);
+        let cursor = Cursor::new(&mut layers, FORMAT, (10, 10).into(), 64, 64);
+        assert!(layers.is_visible(cursor.layer()));
+
+        let bitmap = layers.bitmap(cursor.layer()).unwrap();
+        assert_eq!([0, 0, 0, 0], bitmap.row((0, 0).into())[0]);
+        assert_eq!([0xFF, 0xFF, 0xFF, 0], bitmap.row((1, 2).into())[0]);
+        assert_eq!([0, 0xFF, 0, 0], bitmap.row((1, 0).into())[0]);
+    }
+
+    #[test]
+    fn clamp_to_screen() {
+        let mut layers = LayerManager::new(32, 32, FORMAT);
+        let mut cursor = Cursor::new(&mut layers, FORMAT, (100, 100).into(), 32, 32);
+        assert_eq!((31, 31), (cursor.position().x, cursor.position().y));
+
+        cursor.move_by(&mut layers, -50, -3);
+        assert_eq!((0, 28), (cursor.position().x, cursor.position().y));
+
+        cursor.point_at(&mut layers, u16::MAX, 0);
+        assert_eq!((31, 0), (cursor.position().x, cursor.position().y));
+        let position = layers.position(cursor.layer()).unwrap();
+        assert_eq!((31, 0), (position.x, position.y));
+    }
+}
@@ -4,18 +4,39 @@ use mikan_core::FrameBufferConfig;
 
 use super::*;
 
-#[inline]
-fn pixel_format_to_writer<'a>(pixel_format: PixelFormat) -> &'a dyn PixelWriter {
-    match pixel_format {
-        PixelFormat::RgbResv8BitPerColor => &RgbPixelWriter,
-        PixelFormat::BgrResv8BitPerColor => &BgrPixelWriter,
-    }
-}
-
 pub(crate) struct FrameBuffer {
     config: FrameBufferConfig,
 }
 
+impl FrameBuffer {
+    #[inline]
+    pub(crate) fn width(&self) -> usize {
+        self.config.width
+    }
+
+    #[inline]
+    pub(crate) fn height(&self) -> usize {
+        self.config.height
+    }
+
+    #[inline]
+    pub(crate) fn pixel_format(&self) -> PixelFormat {
+        self.config.pixel_format
+    }
+
+    /// Copies raw pixels in the same format to the row from the position, clipping at the right.
+    pub(crate) fn write_row(&mut self, position: Position, pixels: &[[u8; PIXEL_SIZE]]) {
+        if position.x >= self.config.width || position.y >= self.config.height {
+            return;
+        }
+
+        let len = pixels.len().min(self.config.width - position.x);
+        let offset = position.into_offset(self.config.pixels_per_scan_line);
+        let row = &mut self.config.buf[offset..offset + len * PIXEL_SIZE];
+        unsafe { row.as_chunks_unchecked_mut() }.copy_from_slice(&pixels[..len]);
+    }
+}
+
 impl Canvas for FrameBuffer {
     #[rustfmt::skip]
     type Pixels<'b> =
@@ -36,7 +57,11 @@ impl Canvas for FrameBuffer {
             })
     }
 
-    fn at(&mut self, position: Position) -> Option<Pixel> {
+    fn at(&mut self, position: Position) -> Option<Pixel<'_>> {
+        if position.x >= self.config.width || position.y >= self.config.height {
+            return None;
+        }
+
         let offset = position.into_offset(self.config.pixels_per_scan_line);
         let pixel_format = self.config.pixel_format;
 
@@ -0,0 +1,305 @@
+//! Layers of off-screen bitmaps stacked on the screen, composited in a shadow buffer so that only
+//! the finished pixels reach the frame buffer.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::bitmap::Bitmap;
+use super::frame_buffer::FrameBuffer;
+use super::*;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct LayerId(usize);
+
+struct Layer {
+    id: LayerId,
+    position: Position,
+    /// Boxed to stay in place while the layers are added and removed.
+    bitmap: Box<Bitmap>,
+}
+
+impl Layer {
+    #[inline]
+    fn region(&self) -> Region {
+        Region::new(self.position, self.bitmap.width(), self.bitmap.height())
+    }
+}
+
+pub(crate) struct LayerManager {
+    layers: Vec<Layer>,
+    /// Layers shown on the screen, from the bottom to the top.
+    stack: Vec<LayerId>,
+    shadow: Bitmap,
+    /// Regions of the screen to composite again, none of which overlap.
+    dirty: Vec<Region>,
+    next_id: usize,
+}
+
+impl LayerManager {
+    pub(crate) fn new(width: usize, height: usize, pixel_format: PixelFormat) -> Self {
+        Self {
+            layers: Vec::new(),
+            stack: Vec::new(),
+            shadow: Bitmap::new(width, height, pixel_format),
+            dirty: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds a layer of the bitmap at the top-left corner, which is hidden until shown.
+    pub(crate) fn create(&mut self, bitmap: Bitmap) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+        self.layers.push(Layer {
+            id,
+            position: Position::zero(),
+            bitmap: Box::new(bitmap),
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: LayerId) {
+        self.hide(id);
+        self.layers.retain(|l| l.id != id);
+    }
+
+    fn layer(&self, id: LayerId) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.id == id)
+    }
+
+    fn layer_mut(&mut self, id: LayerId) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|l| l.id == id)
+    }
+
+    /// The bitmap of the layer, whose changes are not shown until the region is invalidated.
+    pub(crate) fn bitmap(&mut self, id: LayerId) -> Option<&mut Bitmap> {
+        self.layer_mut(id).map(|l| l.bitmap.as_mut())
+    }
+
+    /// Draws on the bitmap of the layer, all of which is composited again.
+    pub(crate) fn draw<F>(&mut self, id: LayerId, f: F)
+    where
+        F: FnOnce(&mut Bitmap),
+    {
+        if let Some(layer) = self.layer_mut(id) {
+            f(&mut layer.bitmap);
+            let region = layer.bitmap.region();
+            self.invalidate(id, region);
+        }
+    }
+
+    /// Marks the region of the layer, in its own coordinates, to composite again.
+    pub(crate) fn invalidate(&mut self, id: LayerId, region: Region) {
+        if !self.is_visible(id) {
+            return;
+        }
+
+        let Some(layer) = self.layer(id) else {
+            return;
+        };
+        let region = Region::new(
+            layer.position + region.position,
+            region.width,
+            region.height,
+        );
+        if let Some(region) = region.intersect(&layer.region()) {
+            self.mark(region);
+        }
+    }
+
+    fn mark(&mut self, mut region: Region) {
+        while let Some(i) = self
+            .dirty
+            .iter()
+            .position(|r| r.intersect(&region).is_some())
+        {
+            region = region.union(&self.dirty.swap_remove(i));
+        }
+        self.dirty.push(region);
+    }
+
+    #[inline]
+    pub(crate) fn is_visible(&self, id: LayerId) -> bool {
+        self.stack.contains(&id)
+    }
+
+    pub(crate) fn position(&self, id: LayerId) -> Option<Position> {
+        self.layer(id).map(|l| l.position)
+    }
+
+    pub(crate) fn move_to(&mut self, id: LayerId, position: Position) {
+        let visible = self.is_visible(id);
+        let Some(layer) = self.layer_mut(id) else {
+            return;
+        };
+
+        let previous = layer.region();
+        layer.position = position;
+        let current = layer.region();
+        if visible {
+            self.mark(previous);
+            self.mark(current);
+        }
+    }
+
+    /// Puts the layer at the index in the stack from the bottom, showing it if hidden.
+    pub(crate) fn set_z(&mut self, id: LayerId, z: usize) {
+        let Some(region) = self.layer(id).map(|l| l.region()) else {
+            return;
+        };
+
+        self.stack.retain(|&i| i != id);
+        self.stack.insert(z.min(self.stack.len()), id);
+        self.mark(region);
+    }
+
+    /// Index of the layer in the stack from the bottom, or `None` if hidden.
+    pub(crate) fn z(&self, id: LayerId) -> Option<usize> {
+        self.stack.iter().position(|&i| i == id)
+    }
+
+    /// Shows the layer at the top, unless already shown.
+    pub(crate) fn show(&mut self, id: LayerId) {
+        if !self.is_visible(id) {
+            self.set_z(id, usize::MAX);
+        }
+    }
+
+    pub(crate) fn hide(&mut self, id: LayerId) {
+        if let Some(z) = self.z(id) {
+            self.stack.remove(z);
+            let region = self.layer(id).unwrap().region();
+            self.mark(region);
+        }
+    }
+
+    /// Composites the dirty regions in the shadow buffer, then copies them to the frame buffer.
+    pub(crate) fn composite(&mut self, frame_buffer: &mut FrameBuffer) {
+        let screen = self.shadow.region();
+        core::mem::take(&mut self.dirty)
+            .iter()
+            .filter_map(|region| region.intersect(&screen))
+            .for_each(|region| {
+                self.shadow.fill_in(region, Colors::black());
+                self.stack.iter().for_each(|&id| {
+                    let layer = self.layers.iter().find(|l| l.id == id).unwrap();
+                    if let Some(r) = layer.region().intersect(&region) {
+                        let src = Region::new(
+                            (
+                                r.position.x - layer.position.x,
+                                r.position.y - layer.position.y,
+                            )
+                                .into(),
+                            r.width,
+                            r.height,
+                        );
+                        self.shadow.copy_from(r.position, &layer.bitmap, src);
+                    }
+                });
+
+                (0..region.height).for_each(|y| {
+                    let position = region.position + (0, y).into();
+                    frame_buffer.write_row(position, &self.shadow.row(position)[..region.width]);
+                });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use mikan_core::FrameBufferConfig;
+
+    use super::*;
+
+    const FORMAT: PixelFormat = PixelFormat::RgbResv8BitPerColor;
+    const RED: [u8; 4] = [0xFF, 0, 0, 0];
+    const GREEN: [u8; 4] = [0, 0xFF, 0, 0];
+    const BLUE: [u8; 4] = [0, 0, 0xFF, 0];
+    const UNTOUCHED: [u8; 4] = [0x55; 4];
+
+    fn frame_buffer(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer::from(FrameBufferConfig {
+            buf: vec![0x55; width * height * PIXEL_SIZE].leak(),
+            pixels_per_scan_line: width,
+            width,
+            height,
+            pixel_format: FORMAT,
+        })
+    }
+
+    fn filled(width: usize, height: usize, color: Color) -> Bitmap {
+        let mut bitmap = Bitmap::new(width, height, FORMAT);
+        bitmap.fill(color);
+        bitmap
+    }
+
+    fn raw(frame_buffer: &mut FrameBuffer, x: usize, y: usize) -> [u8; PIXEL_SIZE] {
+        *frame_buffer.at((x, y).into()).unwrap().buf
+    }
+
+    #[test]
+    fn composite_in_z_order() {
+        let mut frame_buffer = frame_buffer(8, 8);
+        let mut layers = LayerManager::new(8, 8, FORMAT);
+        let back = layers.create(filled(8, 8, Colors::blue()));
+        let front = layers.create(filled(2, 2, Colors::red()));
+        layers.move_to(front, (3, 3).into());
+        layers.show(back);
+        layers.show(front);
+
+        layers.composite(&mut frame_buffer);
+        assert_eq!(BLUE, raw(&mut frame_buffer, 0, 0));
+        assert_eq!(RED, raw(&mut frame_buffer, 4, 4));
+
+        layers.set_z(front, 0);
+        layers.composite(&mut frame_buffer);
+        assert_eq!(BLUE, raw(&mut frame_buffer, 4, 4));
+    }
+
+    #[test]
+    fn copy_only_dirty_regions() {
+        let mut frame_buffer = frame_buffer(8, 8);
+        let mut layers = LayerManager::new(8, 8, FORMAT);
+        let a = layers.create(filled(2, 2, Colors::red()));
+        layers.show(a);
+        layers.composite(&mut frame_buffer);
+        assert_eq!(RED, raw(&mut frame_buffer, 1, 1));
+        assert_eq!(UNTOUCHED, raw(&mut frame_buffer, 2, 2));
+
+        // Moving the layer uncovers where it was, which nothing is over.
+        layers.move_to(a, (4, 0).into());
+        layers.composite(&mut frame_buffer);
+        assert_eq!([0; 4], raw(&mut frame_buffer, 1, 1));
+        assert_eq!(RED, raw(&mut frame_buffer, 5, 1));
+        assert_eq!(UNTOUCHED, raw(&mut frame_buffer, 2, 2));
+
+        // Nothing is drawn while hidden, and the changes show up at once.
+        layers.hide(a);
+        layers.draw(a, |bitmap| bitmap.fill(Colors::green()));
+        layers.composite(&mut frame_buffer);
+        assert_eq!([0; 4], raw(&mut frame_buffer, 5, 1));
+        layers.show(a);
+        layers.composite(&mut frame_buffer);
+        assert_eq!(GREEN, raw(&mut frame_buffer, 5, 1));
+    }
+
+    #[test]
+    fn see_through_transparent_pixels() {
+        let mut frame_buffer = frame_buffer(4, 4);
+        let mut layers = LayerManager::new(4, 4, FORMAT);
+        let back = layers.create(filled(4, 4, Colors::blue()));
+        let mut sprite = Bitmap::new(4, 4, FORMAT).with_transparent(Colors::green());
+        sprite.fill(Colors::green());
+        sprite.at((1, 1).into()).unwrap().write(Colors::red());
+        let front = layers.create(sprite);
+        layers.show(back);
+        layers.show(front);
+
+        // Clipped at the right and the bottom edges of the screen.
+        layers.move_to(front, (2, 2).into());
+        layers.composite(&mut frame_buffer);
+        assert_eq!(BLUE, raw(&mut frame_buffer, 2, 2));
+        assert_eq!(RED, raw(&mut frame_buffer, 3, 3));
+    }
+}
@@ -1,10 +1,14 @@
 use core::ops::Add;
 use mikan_core::PixelFormat;
 
+pub(crate) mod bitmap;
 pub(crate) mod colors;
+pub(crate) mod cursor;
 pub(crate) mod fonts;
 pub(crate) mod frame_buffer;
+pub(crate) mod layer;
 pub(crate) mod text;
+pub(crate) mod window;
 
 pub(crate) use colors::Colors;
 
@@ -53,6 +57,14 @@ impl PixelWriter for BgrPixelWriter {
     }
 }
 
+#[inline]
+fn pixel_format_to_writer<'a>(pixel_format: PixelFormat) -> &'a dyn PixelWriter {
+    match pixel_format {
+        PixelFormat::RgbResv8BitPerColor => &RgbPixelWriter,
+        PixelFormat::BgrResv8BitPerColor => &BgrPixelWriter,
+    }
+}
+
 pub(crate) struct Pixel<'a> {
     buf: &'a mut [u8; PIXEL_SIZE],
     position: Position,
@@ -125,6 +137,53 @@ impl Region {
             height,
         }
     }
+
+    #[inline]
+    fn right(&self) -> usize {
+        self.position.x + self.width
+    }
+
+    #[inline]
+    fn bottom(&self) -> usize {
+        self.position.y + self.height
+    }
+
+    #[inline]
+    pub(crate) fn contains(&self, position: Position) -> bool {
+        (self.position.x..self.right()).contains(&position.x)
+            && (self.position.y..self.bottom()).contains(&position.y)
+    }
+
+    /// The part shared with the other region, or `None` if they do not overlap.
+    pub(crate) fn intersect(&self, other: &Self) -> Option<Self> {
+        let (x, y) = (
+            self.position.x.max(other.position.x),
+            self.position.y.max(other.position.y),
+        );
+        let (right, bottom) = (
+            self.right().min(other.right()),
+            self.bottom().min(other.bottom()),
+        );
+
+        match x < right && y < bottom {
+            true => Some(Self::new((x, y).into(), right - x, bottom - y)),
+            false => None,
+        }
+    }
+
+    /// The smallest region covering both.
+    pub(crate) fn union(&self, other: &Self) -> Self {
+        let (x, y) = (
+            self.position.x.min(other.position.x),
+            self.position.y.min(other.position.y),
+        );
+        let (right, bottom) = (
+            self.right().max(other.right()),
+            self.bottom().max(other.bottom()),
+        );
+
+        Self::new((x, y).into(), right - x, bottom - y)
+    }
 }
 
 pub(crate) trait Canvas {
@@ -134,7 +193,7 @@ pub(crate) trait Canvas {
 
     fn pixels(&mut self) -> Self::Pixels<'_>;
 
-    fn at(&mut self, position: Position) -> Option<Pixel>;
+    fn at(&mut self, position: Position) -> Option<Pixel<'_>>;
 
     fn fill(&mut self, color: Color) {
         self.pixels().for_each(|mut p| p.write(color));
@@ -169,4 +228,23 @@ mod tests {
     fn color_from_rgb() {
         assert_eq!(Color::new(0x12, 0x34, 0x56), Color::from(0x123456));
     }
+
+    #[test]
+    fn intersect_and_unite_regions() {
+        let a = Region::new((10, 10).into(), 20, 10);
+        let b = Region::new((25, 15).into(), 10, 10);
+
+        let i = a.intersect(&b).unwrap();
+        assert_eq!(
+            (25, 15, 5, 5),
+            (i.position.x, i.position.y, i.width, i.height)
+        );
+        let u = a.union(&b);
+        assert_eq!(
+            (10, 10, 25, 15),
+            (u.position.x, u.position.y, u.width, u.height)
+        );
+
+        assert!(a.intersect(&Region::new((30, 10).into(), 5, 5)).is_none());
+    }
 }
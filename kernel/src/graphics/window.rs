@@ -0,0 +1,363 @@
+//! Windows with a frame on the layers, focused by clicking and dragged by their title bars.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use mikan_core::PixelFormat;
+
+use super::bitmap::Bitmap;
+use super::layer::{LayerId, LayerManager};
+use super::text::{TextWriter, FONT_WIDTH};
+use super::{Canvas, Color, Colors, Position, Region};
+
+const BUTTON_LEFT: u8 = 1 << 0;
+
+/// Top-left corner of the client area, inside the border and below the title bar.
+pub(crate) const CLIENT_X: usize = 4;
+pub(crate) const CLIENT_Y: usize = 24;
+
+const TITLE_HEIGHT: usize = 24;
+const TITLE_TEXT: (usize, usize) = (24, 4);
+
+const CLOSE_BUTTON_WIDTH: usize = 16;
+const CLOSE_BUTTON_HEIGHT: usize = 14;
+/// Offsets of the close button from the top-right corner.
+const CLOSE_BUTTON_MARGIN: (usize, usize) = (5, 5);
+
+/// Narrowest window, in which the title bar has the room for the close button.
+const MIN_WIDTH: usize = TITLE_TEXT.0 + CLOSE_BUTTON_WIDTH + CLOSE_BUTTON_MARGIN.0;
+
+/// The button closing the window, where `@` is black, `$` is gray, `:` is light gray and `.` is
+/// white.
+const CLOSE_BUTTON: [&[u8; CLOSE_BUTTON_WIDTH]; CLOSE_BUTTON_HEIGHT] = [
+    b"...............@",
+    b".:::::::::::::$@",
+    b".:::::::::::::$@",
+    b".:::@@::::@@::$@",
+    b".::::@@::@@:::$@",
+    b".:::::@@@@::::$@",
+    b".::::::@@:::::$@",
+    b".:::::@@@@::::$@",
+    b".::::@@::@@:::$@",
+    b".:::@@::::@@::$@",
+    b".:::::::::::::$@",
+    b".:::::::::::::$@",
+    b".$$$$$$$$$$$$$$@",
+    b"@@@@@@@@@@@@@@@@",
+];
+
+struct Window {
+    layer: LayerId,
+    title: String,
+    width: usize,
+    height: usize,
+}
+
+impl Window {
+    fn title_bar(&self) -> Region {
+        Region::new(Position::zero(), self.width, TITLE_HEIGHT)
+    }
+
+    fn close_button(&self) -> Region {
+        let (x, y) = CLOSE_BUTTON_MARGIN;
+        Region::new(
+            (self.width - x - CLOSE_BUTTON_WIDTH, y).into(),
+            CLOSE_BUTTON_WIDTH,
+            CLOSE_BUTTON_HEIGHT,
+        )
+    }
+
+    /// Draws the border and the background of the client area, besides the title bar.
+    fn draw_frame(&self, bitmap: &mut Bitmap) {
+        let (w, h) = (self.width, self.height);
+        [
+            (0, 0, w, 1, Colors::light_gray()),
+            (1, 1, w - 2, 1, Colors::white()),
+            (0, 0, 1, h, Colors::light_gray()),
+            (1, 1, 1, h - 2, Colors::white()),
+            (w - 2, 1, 1, h - 2, Colors::gray()),
+            (w - 1, 0, 1, h, Colors::black()),
+            (2, 2, w - 4, h - 4, Colors::light_gray()),
+            (1, h - 2, w - 2, 1, Colors::gray()),
+            (0, h - 1, w, 1, Colors::black()),
+        ]
+        .into_iter()
+        .for_each(|(x, y, width, height, color)| {
+            bitmap.fill_in(Region::new((x, y).into(), width, height), color)
+        });
+        self.draw_title_bar(bitmap, false);
+    }
+
+    fn draw_title_bar(&self, bitmap: &mut Bitmap, focused: bool) {
+        let background = match focused {
+            true => Colors::navy(),
+            false => Colors::gray(),
+        };
+        bitmap.fill_in(
+            Region::new((3, 3).into(), self.width - 6, TITLE_HEIGHT - 6),
+            background,
+        );
+
+        let (x, y) = TITLE_TEXT;
+        self.title
+            .chars()
+            .take((self.width - x - CLOSE_BUTTON_WIDTH - CLOSE_BUTTON_MARGIN.0) / FONT_WIDTH)
+            .enumerate()
+            .for_each(|(i, c)| {
+                bitmap.write_ascii((x + i * FONT_WIDTH, y).into(), c, Colors::white())
+            });
+
+        let origin = self.close_button().position;
+        CLOSE_BUTTON.iter().enumerate().for_each(|(y, row)| {
+            row.iter().enumerate().for_each(|(x, c)| {
+                let color: Color = match c {
+                    b'@' => Colors::black(),
+                    b'$' => Colors::gray(),
+                    b':' => Colors::light_gray(),
+                    _ => Colors::white(),
+                };
+                if let Some(mut pixel) = bitmap.at(origin + (x, y).into()) {
+                    pixel.write(color);
+                }
+            })
+        });
+    }
+}
+
+/// Window being dragged, with the offset of the pointer from its top-left corner.
+struct Drag {
+    layer: LayerId,
+    offset: Position,
+}
+
+pub(crate) struct WindowManager {
+    windows: Vec<Window>,
+    /// Layer staying above every window, which is usually the mouse cursor.
+    overlay: LayerId,
+    pixel_format: PixelFormat,
+    focused: Option<LayerId>,
+    drag: Option<Drag>,
+    buttons: u8,
+}
+
+impl WindowManager {
+    pub(crate) fn new(overlay: LayerId, pixel_format: PixelFormat) -> Self {
+        Self {
+            windows: Vec::new(),
+            overlay,
+            pixel_format,
+            focused: None,
+            drag: None,
+            buttons: 0,
+        }
+    }
+
+    /// Opens a focused window whose client area is of the size, returning the layer of it. Narrow
+    /// windows are widened to fit the title bar.
+    pub(crate) fn create(
+        &mut self,
+        layers: &mut LayerManager,
+        title: &str,
+        position: Position,
+        width: usize,
+        height: usize,
+    ) -> LayerId {
+        let (width, height) = (
+            (width + CLIENT_X * 2).max(MIN_WIDTH),
+            height + CLIENT_Y + CLIENT_X,
+        );
+        let layer = layers.create(Bitmap::new(width, height, self.pixel_format));
+        let window = Window {
+            layer,
+            title: title.to_string(),
+            width,
+            height,
+        };
+        window.draw_frame(layers.bitmap(layer).unwrap());
+        layers.move_to(layer, position);
+
+        self.windows.push(window);
+        self.focus(layers, Some(layer));
+        layer
+    }
+
+    pub(crate) fn close(&mut self, layers: &mut LayerManager, layer: LayerId) {
+        if self.focused == Some(layer) {
+            self.focus(layers, None);
+        }
+        if self.drag.as_ref().is_some_and(|d| d.layer == layer) {
+            self.drag = None;
+        }
+        self.windows.retain(|w| w.layer != layer);
+        layers.remove(layer);
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn focused(&self) -> Option<LayerId> {
+        self.focused
+    }
+
+    /// Focuses the window and raises it to the top, or unfocuses every window if `None`.
+    pub(crate) fn focus(&mut self, layers: &mut LayerManager, layer: Option<LayerId>) {
+        if let Some(layer) = layer {
+            layers.hide(layer);
+            layers.set_z(layer, layers.z(self.overlay).unwrap_or(usize::MAX));
+        }
+        if self.focused == layer {
+            return;
+        }
+
+        [(self.focused, false), (layer, true)]
+            .into_iter()
+            .filter_map(|(layer, focused)| Some((layer?, focused)))
+            .for_each(|(layer, focused)| {
+                let Some(window) = self.windows.iter().find(|w| w.layer == layer) else {
+                    return;
+                };
+                window.draw_title_bar(layers.bitmap(layer).unwrap(), focused);
+                layers.invalidate(layer, window.title_bar());
+            });
+        self.focused = layer;
+    }
+
+    /// The topmost window under the position, with the position relative to it.
+    fn find(&self, layers: &LayerManager, position: Position) -> Option<(&Window, Position)> {
+        self.windows
+            .iter()
+            .filter_map(|w| {
+                let origin = layers.position(w.layer)?;
+                let region = Region::new(origin, w.width, w.height);
+                region.contains(position).then(|| {
+                    let relative = (position.x - origin.x, position.y - origin.y);
+                    (w, relative.into())
+                })
+            })
+            .max_by_key(|(w, _)| layers.z(w.layer))
+    }
+
+    /// Handles the pointer moved to the position, clicking and dragging with the left button.
+    pub(crate) fn handle_pointer(
+        &mut self,
+        layers: &mut LayerManager,
+        position: Position,
+        buttons: u8,
+    ) {
+        let pressed = buttons & !self.buttons;
+        self.buttons = buttons;
+
+        if buttons & BUTTON_LEFT == 0 {
+            self.drag = None;
+        }
+        if let Some(Drag { layer, offset }) = self.drag {
+            let position = (
+                position.x.saturating_sub(offset.x),
+                position.y.saturating_sub(offset.y),
+            );
+            layers.move_to(layer, position.into());
+            return;
+        }
+        if pressed & BUTTON_LEFT == 0 {
+            return;
+        }
+
+        let Some((window, relative)) = self.find(layers, position) else {
+            self.focus(layers, None);
+            return;
+        };
+        let layer = window.layer;
+
+        if window.close_button().contains(relative) {
+            self.close(layers, layer);
+            return;
+        }
+        if window.title_bar().contains(relative) {
+            self.drag = Some(Drag {
+                layer,
+                offset: relative,
+            });
+        }
+        self.focus(layers, Some(layer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: PixelFormat = PixelFormat::RgbResv8BitPerColor;
+
+    fn setup() -> (LayerManager, WindowManager, LayerId, LayerId) {
+        let mut layers = LayerManager::new(320, 240, FORMAT);
+        let cursor = layers.create(Bitmap::new(1, 1, FORMAT));
+        layers.show(cursor);
+
+        let mut windows = WindowManager::new(cursor, FORMAT);
+        let a = windows.create(&mut layers, "A", (10, 10).into(), 100, 50);
+        let b = windows.create(&mut layers, "B", (60, 40).into(), 100, 50);
+        (layers, windows, a, b)
+    }
+
+    fn click(windows: &mut WindowManager, layers: &mut LayerManager, x: usize, y: usize) {
+        windows.handle_pointer(layers, (x, y).into(), BUTTON_LEFT);
+        windows.handle_pointer(layers, (x, y).into(), 0);
+    }
+
+    #[test]
+    fn focus_and_raise_on_click() {
+        let (mut layers, mut windows, a, b) = setup();
+        assert_eq!(Some(b), windows.focused());
+        assert_eq!((Some(0), Some(1)), (layers.z(a), layers.z(b)));
+
+        // Where they overlap, the window on top is clicked.
+        click(&mut windows, &mut layers, 70, 50);
+        assert_eq!(Some(b), windows.focused());
+        click(&mut windows, &mut layers, 20, 30);
+        assert_eq!(Some(a), windows.focused());
+        assert_eq!((Some(1), Some(0)), (layers.z(a), layers.z(b)));
+
+        click(&mut windows, &mut layers, 300, 200);
+        assert_eq!(None, windows.focused());
+        assert_eq!(Some(1), layers.z(a));
+    }
+
+    #[test]
+    fn widen_narrow_windows() {
+        let (mut layers, mut windows, _, _) = setup();
+        let c = windows.create(&mut layers, "C", (0, 0).into(), 0, 0);
+
+        assert_eq!(Some(c), windows.focused());
+        let bitmap = layers.bitmap(c).unwrap();
+        assert_eq!(
+            (MIN_WIDTH, CLIENT_Y + CLIENT_X),
+            (bitmap.width(), bitmap.height())
+        );
+    }
+
+    #[test]
+    fn drag_by_title_bar() {
+        let (mut layers, mut windows, a, _) = setup();
+
+        windows.handle_pointer(&mut layers, (20, 15).into(), BUTTON_LEFT);
+        windows.handle_pointer(&mut layers, (50, 100).into(), BUTTON_LEFT);
+        let position = layers.position(a).unwrap();
+        assert_eq!((40, 95), (position.x, position.y));
+
+        // Dragging from the client area does nothing.
+        windows.handle_pointer(&mut layers, (50, 100).into(), 0);
+        windows.handle_pointer(&mut layers, (50, 130).into(), BUTTON_LEFT);
+        windows.handle_pointer(&mut layers, (0, 0).into(), BUTTON_LEFT);
+        let position = layers.position(a).unwrap();
+        assert_eq!((40, 95), (position.x, position.y));
+    }
+
+    #[test]
+    fn close_by_button() {
+        let (mut layers, mut windows, a, b) = setup();
+
+        // The close button of B, which is 108 pixels wide with the border.
+        click(&mut windows, &mut layers, 60 + 108 - 5 - 8, 40 + 10);
+        assert_eq!(None, windows.focused());
+        assert!(layers.position(b).is_none());
+        assert_eq!(Some(0), layers.z(a));
+    }
+}
@@ -0,0 +1,345 @@
+use core::arch::asm;
+
+use crate::acpi::Madt;
+use crate::fdt::Fdt;
+use crate::mmio::Mmio;
+
+use super::{IntId, Trigger};
+
+/// Addresses of the GIC on QEMU's `-machine virt` board.
+const VIRT_DISTRIBUTOR: usize = 0x0800_0000;
+const VIRT_CPU_INTERFACE: usize = 0x0801_0000;
+const VIRT_REDISTRIBUTOR: usize = 0x080A_0000;
+
+/// INTIDs at or above this value are special, e.g. 1023 is returned for spurious interrupts.
+pub(crate) const SPECIAL_INTIDS: IntId = 1020;
+
+const GICD_CTLR: usize = 0x0000;
+const GICD_TYPER: usize = 0x0004;
+const GICD_IGROUPR: usize = 0x0080;
+const GICD_ISENABLER: usize = 0x0100;
+const GICD_ICENABLER: usize = 0x0180;
+const GICD_IPRIORITYR: usize = 0x0400;
+const GICD_ITARGETSR: usize = 0x0800;
+const GICD_ICFGR: usize = 0x0C00;
+const GICD_SGIR: usize = 0x0F00;
+const GICD_IROUTER: usize = 0x6000;
+const GICD_PIDR2: usize = 0xFFE8;
+
+const GICC_CTLR: usize = 0x0000;
+const GICC_PMR: usize = 0x0004;
+const GICC_BPR: usize = 0x0008;
+const GICC_IAR: usize = 0x000C;
+const GICC_EOIR: usize = 0x0010;
+
+const GICR_CTLR: usize = 0x0000;
+const GICR_WAKER: usize = 0x0014;
+const GICR_TYPER: usize = 0x0008;
+const GICR_FRAME_SIZE: usize = 0x20000;
+const GICR_SGI_BASE: usize = 0x10000;
+
+const GICD_CTLR_ENABLE_GRP0: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_GRP1: u32 = 1 << 1;
+const GICD_CTLR_ARE: u32 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+/// Target list filter of `GICD_SGIR` sending to every core but the calling one.
+const GICD_SGIR_OTHERS: u32 = 0b01 << 24;
+/// Routing mode of `ICC_SGI1R_EL1` sending to every core but the calling one.
+const ICC_SGI1R_IRM: u64 = 1 << 40;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum GicVersion {
+    V2,
+    V3,
+}
+
+/// Where to find the GIC, discovered from the firmware tables.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct GicConfig {
+    pub(crate) version: GicVersion,
+    pub(crate) distributor: usize,
+    /// Base of the CPU interface on GICv2, or of the first redistributor on GICv3.
+    pub(crate) cpu_interface: usize,
+}
+
+impl GicConfig {
+    /// Detects the version of the GIC at the fixed addresses of QEMU's `virt` board.
+    pub(crate) fn qemu_virt() -> Self {
+        let distributor = unsafe { Mmio::new(VIRT_DISTRIBUTOR) };
+        match (distributor.read::<u32>(GICD_PIDR2) >> 4) & 0xF {
+            3 | 4 => Self {
+                version: GicVersion::V3,
+                distributor: VIRT_DISTRIBUTOR,
+                cpu_interface: VIRT_REDISTRIBUTOR,
+            },
+            _ => Self {
+                version: GicVersion::V2,
+                distributor: VIRT_DISTRIBUTOR,
+                cpu_interface: VIRT_CPU_INTERFACE,
+            },
+        }
+    }
+
+    /// Takes the first two `reg` entries of the interrupt controller node, which are the
+    /// distributor and either the CPU interface or the redistributors.
+    pub(crate) fn from_device_tree(fdt: &Fdt) -> Option<Self> {
+        let (version, node) = match fdt.find_compatible(&["arm,gic-v3"]) {
+            Some(node) => (GicVersion::V3, node),
+            None => (
+                GicVersion::V2,
+                fdt.find_compatible(&["arm,cortex-a15-gic", "arm,gic-400"])?,
+            ),
+        };
+
+        let mut reg = node.reg();
+        Some(Self {
+            version,
+            distributor: reg.next()?.address,
+            cpu_interface: reg.next()?.address,
+        })
+    }
+
+    /// Takes the distributor, and either the CPU interface of the first processor or the first
+    /// redistributor, which is listed in a discovery range or per processor.
+    pub(crate) fn from_madt(madt: &Madt) -> Option<Self> {
+        let distributor = madt.distributor()?;
+        let cpu = madt.cpu_interfaces().next()?;
+        let redistributor = madt
+            .redistributors()
+            .next()
+            .or((cpu.redistributor != 0).then_some(cpu.redistributor));
+
+        Some(match (distributor.version, redistributor) {
+            (0 | 3 | 4, Some(redistributor)) => Self {
+                version: GicVersion::V3,
+                distributor: distributor.base,
+                cpu_interface: redistributor,
+            },
+            (0..=2, _) => Self {
+                version: GicVersion::V2,
+                distributor: distributor.base,
+                cpu_interface: cpu.base,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Driver of the Generic Interrupt Controller, supporting GICv2 and GICv3.
+pub(crate) struct Gic {
+    version: GicVersion,
+    distributor: Mmio,
+    cpu_interface: Mmio,
+    lines: u32,
+}
+
+impl Gic {
+    /// # Safety
+    /// The configuration must describe the GIC of this machine, mapped at the addresses.
+    pub(crate) unsafe fn new(config: GicConfig) -> Self {
+        let distributor = Mmio::new(config.distributor);
+        let lines = ((distributor.read::<u32>(GICD_TYPER) & 0x1F) + 1) * 32;
+
+        Self {
+            version: config.version,
+            distributor,
+            cpu_interface: Mmio::new(config.cpu_interface),
+            lines: lines.min(SPECIAL_INTIDS),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn version(&self) -> GicVersion {
+        self.version
+    }
+
+    #[inline]
+    pub(crate) fn lines(&self) -> u32 {
+        self.lines
+    }
+
+    /// Disables all shared peripheral interrupts and enables the distributor.
+    pub(crate) fn init_distributor(&self) {
+        self.distributor.write::<u32>(GICD_CTLR, 0);
+
+        (32..self.lines).step_by(32).for_each(|id| {
+            self.distributor
+                .write::<u32>(GICD_ICENABLER + (id / 32) as usize * 4, u32::MAX);
+        });
+
+        let ctlr = match self.version {
+            GicVersion::V2 => GICD_CTLR_ENABLE_GRP0 | GICD_CTLR_ENABLE_GRP1,
+            GicVersion::V3 => GICD_CTLR_ENABLE_GRP0 | GICD_CTLR_ENABLE_GRP1 | GICD_CTLR_ARE,
+        };
+        self.distributor.write(GICD_CTLR, ctlr);
+    }
+
+    /// Enables the CPU interface of the calling core, letting every priority through.
+    pub(crate) fn init_cpu(&self) {
+        match self.version {
+            GicVersion::V2 => {
+                self.cpu_interface.write::<u32>(GICC_PMR, 0xFF);
+                self.cpu_interface.write::<u32>(GICC_BPR, 0);
+                self.cpu_interface.write::<u32>(GICC_CTLR, 0b11);
+            }
+            GicVersion::V3 => {
+                let redistributor = self.redistributor();
+                redistributor.write(
+                    GICR_WAKER,
+                    redistributor.read::<u32>(GICR_WAKER) & !GICR_WAKER_PROCESSOR_SLEEP,
+                );
+                while redistributor.read::<u32>(GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+                    core::hint::spin_loop();
+                }
+                redistributor.write::<u32>(GICR_CTLR, 0);
+
+                unsafe {
+                    asm!(
+                        "mrs {tmp}, icc_sre_el1",
+                        "orr {tmp}, {tmp}, #1",
+                        "msr icc_sre_el1, {tmp}",
+                        "isb",
+                        "msr icc_pmr_el1, {pmr}",
+                        "msr icc_bpr1_el1, xzr",
+                        "msr icc_igrpen1_el1, {enable}",
+                        "isb",
+                        tmp = out(reg) _,
+                        pmr = in(reg) 0xFFu64,
+                        enable = in(reg) 1u64,
+                        options(nostack),
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn enable(&self, id: IntId) {
+        let registers = self.registers_of(id);
+        let (index, bit) = ((id / 32) as usize * 4, 1u32 << (id % 32));
+
+        registers.write(
+            GICD_IGROUPR + index,
+            registers.read::<u32>(GICD_IGROUPR + index) | bit,
+        );
+        if self.version == GicVersion::V2 && id >= 32 {
+            let targets = self.distributor.read::<u8>(GICD_ITARGETSR);
+            self.distributor
+                .write::<u8>(GICD_ITARGETSR + id as usize, targets.max(1));
+        }
+        if self.version == GicVersion::V3 && id >= 32 {
+            self.distributor
+                .write::<u64>(GICD_IROUTER + id as usize * 8, affinity());
+        }
+
+        registers.write(GICD_ISENABLER + index, bit);
+    }
+
+    pub(crate) fn disable(&self, id: IntId) {
+        let registers = self.registers_of(id);
+        registers.write(GICD_ICENABLER + (id / 32) as usize * 4, 1u32 << (id % 32));
+    }
+
+    /// Sets the priority of the interrupt, where lower values mean higher priorities.
+    pub(crate) fn set_priority(&self, id: IntId, priority: u8) {
+        let registers = self.registers_of(id);
+        registers.write::<u8>(GICD_IPRIORITYR + id as usize, priority);
+    }
+
+    pub(crate) fn set_trigger(&self, id: IntId, trigger: Trigger) {
+        if id < 16 {
+            return;
+        }
+
+        let registers = self.registers_of(id);
+        let register = GICD_ICFGR + (id / 16) as usize * 4;
+        let bit = 1u32 << ((id % 16) * 2 + 1);
+        let value = registers.read::<u32>(register);
+        registers.write(
+            register,
+            match trigger {
+                Trigger::Level => value & !bit,
+                Trigger::Edge => value | bit,
+            },
+        );
+    }
+
+    /// Acknowledges the highest priority pending interrupt, if any.
+    pub(crate) fn acknowledge(&self) -> Option<IntId> {
+        let id = match self.version {
+            GicVersion::V2 => self.cpu_interface.read::<u32>(GICC_IAR) & 0x3FF,
+            GicVersion::V3 => {
+                let id: u64;
+                unsafe { asm!("mrs {}, icc_iar1_el1", out(reg) id, options(nostack)) };
+                id as u32 & 0xFF_FFFF
+            }
+        };
+
+        (id < SPECIAL_INTIDS).then_some(id)
+    }
+
+    pub(crate) fn end_of_interrupt(&self, id: IntId) {
+        match self.version {
+            GicVersion::V2 => self.cpu_interface.write::<u32>(GICC_EOIR, id),
+            GicVersion::V3 => unsafe {
+                asm!("msr icc_eoir1_el1, {}", in(reg) id as u64, options(nostack))
+            },
+        }
+    }
+
+    /// Sends the SGI to every core but the calling one.
+    pub(crate) fn send_sgi_to_others(&self, id: IntId) {
+        match self.version {
+            GicVersion::V2 => self
+                .distributor
+                .write::<u32>(GICD_SGIR, GICD_SGIR_OTHERS | id),
+            GicVersion::V3 => unsafe {
+                asm!(
+                    "msr icc_sgi1r_el1, {}",
+                    "isb",
+                    in(reg) ICC_SGI1R_IRM | (id as u64) << 24,
+                    options(nostack),
+                )
+            },
+        }
+    }
+
+    /// Returns the registers configuring the interrupt.
+    ///
+    /// On GICv3, SGIs and PPIs are configured in the redistributor of each core, whose SGI frame
+    /// shares the layout of the distributor.
+    fn registers_of(&self, id: IntId) -> Mmio {
+        match self.version {
+            GicVersion::V3 if id < 32 => self.redistributor().offset(GICR_SGI_BASE),
+            _ => self.distributor,
+        }
+    }
+
+    /// Finds the redistributor frame of the calling core.
+    fn redistributor(&self) -> Mmio {
+        let affinity = affinity();
+        let mut frame = self.cpu_interface;
+        loop {
+            let typer = frame.read::<u64>(GICR_TYPER);
+            if typer >> 32 == compact_affinity(affinity) || typer & GICR_TYPER_LAST != 0 {
+                return frame;
+            }
+            frame = frame.offset(GICR_FRAME_SIZE);
+        }
+    }
+}
+
+/// Reads the affinity of the calling core from `MPIDR_EL1`, in the layout of `GICD_IROUTER`.
+#[inline]
+fn affinity() -> u64 {
+    let mpidr: u64;
+    unsafe { asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack)) };
+    mpidr & 0xFF_00FF_FFFF
+}
+
+/// Converts the affinity into `Aff3.Aff2.Aff1.Aff0`, the layout of `GICR_TYPER`.
+#[inline]
+fn compact_affinity(affinity: u64) -> u64 {
+    (affinity >> 8 & 0xFF00_0000) | (affinity & 0xFF_FFFF)
+}
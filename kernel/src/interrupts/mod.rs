@@ -0,0 +1,124 @@
+use core::arch::asm;
+
+use crate::sync::{Once, SpinLock};
+
+pub(crate) mod gic;
+
+pub(crate) use gic::{Gic, GicConfig};
+
+/// Interrupt ID as defined by the GIC architecture.
+pub(crate) type IntId = u32;
+
+pub(crate) type Handler = fn(IntId);
+
+/// Priority given to interrupts unless specified, where lower values mean higher priorities.
+pub(crate) const DEFAULT_PRIORITY: u8 = 0xA0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Trigger {
+    Level,
+    Edge,
+}
+
+static GIC: Once<Gic> = Once::new();
+/// Handlers indexed by interrupt, whose lock also serializes the configuration of the GIC.
+static HANDLERS: SpinLock<[Option<Handler>; gic::SPECIAL_INTIDS as usize]> =
+    SpinLock::new([None; gic::SPECIAL_INTIDS as usize]);
+
+/// Brings up the interrupt controller for the boot core. IRQs stay masked until `unmask`.
+pub(crate) fn init(config: GicConfig) {
+    let gic = GIC.get_or_init(|| unsafe { Gic::new(config) });
+    gic.init_distributor();
+    gic.init_cpu();
+
+    log::info!(
+        "GIC{:?}: {} interrupt lines, distributor at {:#x}",
+        gic.version(),
+        gic.lines(),
+        config.distributor
+    );
+}
+
+/// Brings up the CPU interface of a secondary core, after `init` has run on the boot core.
+pub(crate) fn init_cpu() {
+    if let Some(gic) = GIC.get() {
+        gic.init_cpu();
+    }
+}
+
+/// Registers the handler of the interrupt, then enables the interrupt.
+pub(crate) fn register(id: IntId, trigger: Trigger, priority: u8, handler: Handler) {
+    let gic = GIC.get().expect("Interrupt controller is not initialised");
+    let mut handlers = HANDLERS.lock_irqsave();
+    handlers[id as usize] = Some(handler);
+
+    gic.set_trigger(id, trigger);
+    gic.set_priority(id, priority);
+    gic.enable(id);
+}
+
+/// Disables the interrupt, then unregisters its handler.
+pub(crate) fn unregister(id: IntId) {
+    let mut handlers = HANDLERS.lock_irqsave();
+    if let Some(gic) = GIC.get() {
+        gic.disable(id);
+    }
+    handlers[id as usize] = None;
+}
+
+/// Interrupts every core but the calling one with the SGI.
+pub(crate) fn send_to_others(id: IntId) {
+    if let Some(gic) = GIC.get() {
+        gic.send_sgi_to_others(id);
+    }
+}
+
+/// Dispatches the pending interrupt to its handler, called from the IRQ exception vector.
+pub(crate) fn handle_irq() {
+    let Some(gic) = GIC.get() else {
+        return;
+    };
+
+    if let Some(id) = gic.acknowledge() {
+        // Released before the call, so that the handler may register interrupts itself.
+        let handler = HANDLERS.lock()[id as usize];
+        match handler {
+            Some(handler) => handler(id),
+            None => log::warn!("Unhandled interrupt: {}", id),
+        }
+        gic.end_of_interrupt(id);
+    }
+}
+
+/// Unmasks IRQs on the calling core.
+#[inline]
+pub(crate) fn unmask() {
+    unsafe { asm!("msr daifclr, #2", options(nomem, nostack)) };
+}
+
+/// Masks IRQs on the calling core.
+#[inline]
+pub(crate) fn mask() {
+    unsafe { asm!("msr daifset, #2", options(nomem, nostack)) };
+}
+
+#[inline]
+pub(crate) fn are_masked() -> bool {
+    let daif: u64;
+    unsafe { asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack)) };
+    daif & (1 << 7) != 0
+}
+
+/// Runs the closure with IRQs masked, restoring the previous state afterwards.
+pub(crate) fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let masked = are_masked();
+    mask();
+    let result = f();
+    if !masked {
+        unmask();
+    }
+    result
+}
@@ -0,0 +1,126 @@
+use super::KeyCode;
+
+/// Arrangement of the characters on the keys, which the HID usages alone do not tell.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Layout {
+    /// ANSI layout of the United States.
+    Us,
+    /// JIS layout of Japan, whose symbols differ and which has the Yen and the Ro keys.
+    Jis,
+}
+
+impl Layout {
+    /// The layout to switch to from this one, going around all of them.
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::Us => Self::Jis,
+            Self::Jis => Self::Us,
+        }
+    }
+
+    /// The character the key types, with the letters already shifted for Caps Lock.
+    pub(crate) fn char(self, code: KeyCode, shift: bool) -> Option<char> {
+        use KeyCode::*;
+
+        if let Some(c) = code.letter() {
+            return Some(match shift {
+                true => c.to_ascii_uppercase(),
+                false => c,
+            });
+        }
+
+        let (normal, shifted) = match (self, code) {
+            (_, Enter | KeypadEnter) => ('\n', '\n'),
+            (_, Escape) => ('\x1B', '\x1B'),
+            (_, Backspace) => ('\x08', '\x08'),
+            (_, Tab) => ('\t', '\t'),
+            (_, Space) => (' ', ' '),
+            (_, Delete) => ('\x7F', '\x7F'),
+            (_, Comma) => (',', '<'),
+            (_, Period) => ('.', '>'),
+            (_, Slash) => ('/', '?'),
+            (_, KeypadDivide) => ('/', '/'),
+            (_, KeypadMultiply) => ('*', '*'),
+            (_, KeypadSubtract) => ('-', '-'),
+            (_, KeypadAdd) => ('+', '+'),
+            (_, KeypadDecimal) => ('.', '.'),
+            (_, Keypad0) => ('0', '0'),
+            (_, Keypad1) => ('1', '1'),
+            (_, Keypad2) => ('2', '2'),
+            (_, Keypad3) => ('3', '3'),
+            (_, Keypad4) => ('4', '4'),
+            (_, Keypad5) => ('5', '5'),
+            (_, Keypad6) => ('6', '6'),
+            (_, Keypad7) => ('7', '7'),
+            (_, Keypad8) => ('8', '8'),
+            (_, Keypad9) => ('9', '9'),
+            (_, Digit1) => ('1', '!'),
+            (_, Digit3) => ('3', '#'),
+            (_, Digit4) => ('4', '$'),
+            (_, Digit5) => ('5', '%'),
+
+            (Self::Us, Digit2) => ('2', '@'),
+            (Self::Us, Digit6) => ('6', '^'),
+            (Self::Us, Digit7) => ('7', '&'),
+            (Self::Us, Digit8) => ('8', '*'),
+            (Self::Us, Digit9) => ('9', '('),
+            (Self::Us, Digit0) => ('0', ')'),
+            (Self::Us, Minus) => ('-', '_'),
+            (Self::Us, Equal) => ('=', '+'),
+            (Self::Us, LeftBracket) => ('[', '{'),
+            (Self::Us, RightBracket) => (']', '}'),
+            (Self::Us, Backslash | NonUsHash | NonUsBackslash) => ('\\', '|'),
+            (Self::Us, Semicolon) => (';', ':'),
+            (Self::Us, Quote) => ('\'', '"'),
+            (Self::Us, Grave) => ('`', '~'),
+
+            (Self::Jis, Digit2) => ('2', '"'),
+            (Self::Jis, Digit6) => ('6', '&'),
+            (Self::Jis, Digit7) => ('7', '\''),
+            (Self::Jis, Digit8) => ('8', '('),
+            (Self::Jis, Digit9) => ('9', ')'),
+            (Self::Jis, Digit0) => return (!shift).then_some('0'),
+            (Self::Jis, Minus) => ('-', '='),
+            (Self::Jis, Equal) => ('^', '~'),
+            (Self::Jis, LeftBracket) => ('@', '`'),
+            (Self::Jis, RightBracket) => ('[', '{'),
+            // The key left of Enter, which is reported as either depending on the keyboard.
+            (Self::Jis, Backslash | NonUsHash) => (']', '}'),
+            (Self::Jis, Semicolon) => (';', '+'),
+            (Self::Jis, Quote) => (':', '*'),
+            (Self::Jis, Ro) => ('\\', '_'),
+            (Self::Jis, Yen) => ('¥', '|'),
+
+            _ => return None,
+        };
+
+        Some(match shift {
+            true => shifted,
+            false => normal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_symbols_of_layouts() {
+        assert_eq!(Some('@'), Layout::Us.char(KeyCode::Digit2, true));
+        assert_eq!(Some('"'), Layout::Jis.char(KeyCode::Digit2, true));
+        assert_eq!(Some('\''), Layout::Us.char(KeyCode::Quote, false));
+        assert_eq!(Some(':'), Layout::Jis.char(KeyCode::Quote, false));
+        assert_eq!(Some('¥'), Layout::Jis.char(KeyCode::Yen, false));
+        assert_eq!(None, Layout::Us.char(KeyCode::Yen, false));
+        assert_eq!(None, Layout::Jis.char(KeyCode::Digit0, true));
+        assert_eq!(Some('Q'), Layout::Jis.char(KeyCode::Q, true));
+        assert_eq!(None, Layout::Us.char(KeyCode::F1, false));
+    }
+
+    #[test]
+    fn go_around_layouts() {
+        assert_eq!(Layout::Jis, Layout::Us.next());
+        assert_eq!(Layout::Us, Layout::Jis.next());
+    }
+}
@@ -0,0 +1,433 @@
+//! Keyboard layer turning HID usages into key codes and characters, with modifiers and repeat.
+
+pub(crate) mod layout;
+
+use crate::message::{self, KeyEvent, Message};
+use crate::sync::SpinLock;
+use crate::timer::{self, TimerId};
+
+pub(crate) use layout::Layout;
+
+/// Time a key is held before it starts repeating.
+const REPEAT_DELAY_MS: u64 = 500;
+/// Time between the repeats, once started.
+const REPEAT_INTERVAL_MS: u64 = 40;
+
+/// Modifier bits of a HID boot protocol report, of the left and the right keys.
+const MODIFIER_CTRL: u8 = 0x11;
+const MODIFIER_SHIFT: u8 = 0x22;
+const MODIFIER_ALT: u8 = 0x44;
+const MODIFIER_GUI: u8 = 0x88;
+
+static KEYBOARD: SpinLock<Keyboard> = SpinLock::new(Keyboard::new(Layout::Us));
+/// Timer of the repeat, and whether it has passed the delay and fires at the interval.
+static REPEAT_TIMER: SpinLock<Option<(TimerId, bool)>> = SpinLock::new(None);
+
+/// Key on the keyboard, independent of the layout. Named after the US layout.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum KeyCode {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Digit0,
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Space,
+    Minus,
+    Equal,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    NonUsHash,
+    Semicolon,
+    Quote,
+    Grave,
+    Comma,
+    Period,
+    Slash,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    Right,
+    Left,
+    Down,
+    Up,
+    NumLock,
+    KeypadDivide,
+    KeypadMultiply,
+    KeypadSubtract,
+    KeypadAdd,
+    KeypadEnter,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+    Keypad0,
+    KeypadDecimal,
+    NonUsBackslash,
+    Application,
+    /// International1, the key left of Right Shift on the JIS layout.
+    Ro,
+    /// International2.
+    KatakanaHiragana,
+    /// International3, the key left of Backspace on the JIS layout.
+    Yen,
+    /// International4.
+    Henkan,
+    /// International5.
+    Muhenkan,
+    LeftCtrl,
+    LeftShift,
+    LeftAlt,
+    LeftGui,
+    RightCtrl,
+    RightShift,
+    RightAlt,
+    RightGui,
+}
+
+/// Key codes of the usages from 0x04, where the keyboard page has no gaps.
+#[rustfmt::skip]
+const KEY_CODES: [KeyCode; 0x62] = {
+    use KeyCode::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9, Digit0,
+        Enter, Escape, Backspace, Tab, Space, Minus, Equal, LeftBracket, RightBracket, Backslash,
+        NonUsHash, Semicolon, Quote, Grave, Comma, Period, Slash, CapsLock,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        PrintScreen, ScrollLock, Pause, Insert, Home, PageUp, Delete, End, PageDown,
+        Right, Left, Down, Up, NumLock,
+        KeypadDivide, KeypadMultiply, KeypadSubtract, KeypadAdd, KeypadEnter,
+        Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9, Keypad0,
+        KeypadDecimal, NonUsBackslash, Application,
+    ]
+};
+
+/// Key codes of the usages from 0x87, International1 to 5.
+const INTERNATIONAL_KEY_CODES: [KeyCode; 5] = [
+    KeyCode::Ro,
+    KeyCode::KatakanaHiragana,
+    KeyCode::Yen,
+    KeyCode::Henkan,
+    KeyCode::Muhenkan,
+];
+
+/// Key codes of the usages from 0xE0, in the order of the modifier bits.
+const MODIFIER_KEY_CODES: [KeyCode; 8] = [
+    KeyCode::LeftCtrl,
+    KeyCode::LeftShift,
+    KeyCode::LeftAlt,
+    KeyCode::LeftGui,
+    KeyCode::RightCtrl,
+    KeyCode::RightShift,
+    KeyCode::RightAlt,
+    KeyCode::RightGui,
+];
+
+impl KeyCode {
+    /// The key of the usage ID on the keyboard page, or `None` if it is not a known key.
+    pub(crate) fn from_usage(usage: u8) -> Option<Self> {
+        match usage {
+            0x04..=0x65 => Some(KEY_CODES[usage as usize - 0x04]),
+            0x87..=0x8B => Some(INTERNATIONAL_KEY_CODES[usage as usize - 0x87]),
+            0xE0..=0xE7 => Some(MODIFIER_KEY_CODES[usage as usize - 0xE0]),
+            _ => None,
+        }
+    }
+
+    /// The lowercase letter on the key, which is the same on every layout.
+    pub(crate) fn letter(&self) -> Option<char> {
+        KEY_CODES[..26]
+            .iter()
+            .position(|code| code == self)
+            .map(|i| (b'a' + i as u8) as char)
+    }
+
+    /// Whether the key changes the others, in which case it does not repeat.
+    pub(crate) fn is_modifier(&self) -> bool {
+        matches!(self, Self::CapsLock | Self::NumLock | Self::ScrollLock)
+            || MODIFIER_KEY_CODES.contains(self)
+    }
+}
+
+/// State of the modifiers when a key is pressed, either side counting for each.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Modifiers {
+    pub(crate) shift: bool,
+    pub(crate) ctrl: bool,
+    pub(crate) alt: bool,
+    pub(crate) gui: bool,
+    pub(crate) caps_lock: bool,
+}
+
+/// Key typed on the keyboard, either pressed or repeated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Key {
+    pub(crate) code: KeyCode,
+    pub(crate) modifiers: Modifiers,
+    /// The character typed, in which Ctrl turns the letters into the control characters.
+    pub(crate) char: Option<char>,
+}
+
+/// Turns the key events of the input drivers into keys, tracking the modifiers and the key to
+/// repeat.
+pub(crate) struct Keyboard {
+    layout: Layout,
+    modifiers: u8,
+    caps_lock: bool,
+    repeating: Option<KeyCode>,
+}
+
+impl Keyboard {
+    pub(crate) const fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            modifiers: 0,
+            caps_lock: false,
+            repeating: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    #[inline]
+    pub(crate) fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub(crate) fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.modifiers & MODIFIER_SHIFT != 0,
+            ctrl: self.modifiers & MODIFIER_CTRL != 0,
+            alt: self.modifiers & MODIFIER_ALT != 0,
+            gui: self.modifiers & MODIFIER_GUI != 0,
+            caps_lock: self.caps_lock,
+        }
+    }
+
+    /// The key held down to repeat, if any.
+    #[inline]
+    pub(crate) fn repeating(&self) -> Option<KeyCode> {
+        self.repeating
+    }
+
+    /// Handles the event, returning the key if pressed.
+    pub(crate) fn handle(&mut self, event: KeyEvent) -> Option<Key> {
+        let code = KeyCode::from_usage(event.usage)?;
+        self.modifiers = event.modifiers;
+
+        if !event.pressed {
+            if self.repeating == Some(code) {
+                self.repeating = None;
+            }
+            return None;
+        }
+
+        match code {
+            KeyCode::CapsLock => self.caps_lock = !self.caps_lock,
+            code if !code.is_modifier() => self.repeating = Some(code),
+            _ => {}
+        }
+        Some(self.key(code))
+    }
+
+    /// The key held down, typed again with the modifiers at the moment.
+    pub(crate) fn repeat(&self) -> Option<Key> {
+        self.repeating.map(|code| self.key(code))
+    }
+
+    fn key(&self, code: KeyCode) -> Key {
+        let modifiers = self.modifiers();
+        let shift = modifiers.shift ^ (modifiers.caps_lock && code.letter().is_some());
+        let char = match (modifiers.ctrl, code.letter()) {
+            (true, Some(c)) => Some((c as u8 - b'a' + 1) as char),
+            _ => self.layout.char(code, shift),
+        };
+
+        Key {
+            code,
+            modifiers,
+            char,
+        }
+    }
+}
+
+/// The layout the characters are typed in.
+pub(crate) fn layout() -> Layout {
+    KEYBOARD.lock().layout()
+}
+
+/// Switches the layout to type the characters in.
+pub(crate) fn set_layout(layout: Layout) {
+    KEYBOARD.lock().set_layout(layout);
+}
+
+/// Handles a key event from the input drivers, starting or stopping the repeat.
+pub(crate) fn handle(event: KeyEvent) -> Option<Key> {
+    let mut keyboard = KEYBOARD.lock();
+    let repeating = keyboard.repeating();
+    let key = keyboard.handle(event);
+
+    if keyboard.repeating() != repeating {
+        let mut repeat_timer = REPEAT_TIMER.lock();
+        if let Some((id, _)) = repeat_timer.take() {
+            timer::cancel(id);
+        }
+        if keyboard.repeating().is_some() {
+            *repeat_timer = Some((timer::set_timeout(REPEAT_DELAY_MS, post_repeat), false));
+        }
+    }
+
+    key
+}
+
+/// Handles the expiry of the repeat timer, returning the key to type again.
+///
+/// The timer may have been replaced after it posted the message, in which case this does nothing.
+pub(crate) fn repeat(id: TimerId) -> Option<Key> {
+    let keyboard = KEYBOARD.lock();
+    let mut repeat_timer = REPEAT_TIMER.lock();
+    let (current, interval) = (*repeat_timer)?;
+    if current != id {
+        return None;
+    }
+
+    if !interval {
+        *repeat_timer = Some((timer::set_interval(REPEAT_INTERVAL_MS, post_repeat), true));
+    }
+    keyboard.repeat()
+}
+
+fn post_repeat(id: TimerId) {
+    message::post(Message::KeyRepeat(id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(modifiers: u8, usage: u8, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            modifiers,
+            usage,
+            pressed,
+        }
+    }
+
+    fn char(keyboard: &mut Keyboard, modifiers: u8, usage: u8) -> Option<char> {
+        keyboard.handle(event(modifiers, usage, true))?.char
+    }
+
+    #[test]
+    fn convert_usages() {
+        assert_eq!(Some(KeyCode::A), KeyCode::from_usage(0x04));
+        assert_eq!(Some(KeyCode::Digit0), KeyCode::from_usage(0x27));
+        assert_eq!(Some(KeyCode::Application), KeyCode::from_usage(0x65));
+        assert_eq!(Some(KeyCode::Yen), KeyCode::from_usage(0x89));
+        assert_eq!(Some(KeyCode::RightGui), KeyCode::from_usage(0xE7));
+        assert_eq!(None, KeyCode::from_usage(0x66));
+        assert_eq!(None, KeyCode::from_usage(0x01));
+    }
+
+    #[test]
+    fn apply_modifiers() {
+        let mut keyboard = Keyboard::new(Layout::Us);
+
+        assert_eq!(Some('a'), char(&mut keyboard, 0, 0x04));
+        assert_eq!(Some('A'), char(&mut keyboard, 0x20, 0x04));
+        assert_eq!(Some('!'), char(&mut keyboard, 0x02, 0x1E));
+        assert_eq!(Some('\x03'), char(&mut keyboard, 0x01, 0x06));
+
+        // Caps Lock shifts only the letters, and Shift brings them back.
+        assert_eq!(None, char(&mut keyboard, 0, 0x39));
+        assert!(keyboard.modifiers().caps_lock);
+        assert_eq!(Some('A'), char(&mut keyboard, 0, 0x04));
+        assert_eq!(Some('a'), char(&mut keyboard, 0x02, 0x04));
+        assert_eq!(Some('1'), char(&mut keyboard, 0, 0x1E));
+        assert_eq!(None, char(&mut keyboard, 0, 0x39));
+        assert!(!keyboard.modifiers().caps_lock);
+
+        keyboard.set_layout(Layout::Jis);
+        assert_eq!(Some('"'), char(&mut keyboard, 0x02, 0x1F));
+    }
+
+    #[test]
+    fn repeat_the_last_key_held() {
+        let mut keyboard = Keyboard::new(Layout::Us);
+
+        keyboard.handle(event(0, 0x04, true));
+        keyboard.handle(event(0, 0x05, true));
+        assert_eq!(Some(KeyCode::B), keyboard.repeating());
+
+        // Modifiers neither start nor stop the repeat, but apply to it.
+        keyboard.handle(event(0x02, 0xE1, true));
+        assert_eq!(Some('B'), keyboard.repeat().and_then(|k| k.char));
+
+        keyboard.handle(event(0x02, 0x04, false));
+        assert_eq!(Some(KeyCode::B), keyboard.repeating());
+        keyboard.handle(event(0x02, 0x05, false));
+        assert_eq!(None, keyboard.repeat());
+    }
+}
@@ -0,0 +1,155 @@
+use core::fmt::Write;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::sync::SpinLock;
+use crate::timer;
+
+const EARLY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Keeps the output written before the console comes up, overwriting the oldest bytes when full.
+pub(crate) struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the buffered text, oldest first, skipping a character cut by the wraparound.
+    pub(crate) fn contents(&mut self) -> &str {
+        if self.len == N {
+            self.buf.rotate_left(self.head);
+            self.head = 0;
+        }
+
+        let bytes = &self.buf[..self.len];
+        let start = bytes
+            .iter()
+            .position(|b| b & 0xC0 != 0x80)
+            .unwrap_or(bytes.len());
+        let bytes = &bytes[start..];
+
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Write for RingBuffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        s.bytes().for_each(|b| {
+            self.buf[self.head] = b;
+            self.head = (self.head + 1) % N;
+            self.len = (self.len + 1).min(N);
+        });
+
+        Ok(())
+    }
+}
+
+static EARLY_BUFFER: SpinLock<RingBuffer<EARLY_BUFFER_SIZE>> = SpinLock::new(RingBuffer::new());
+
+/// Records the output while nothing can display it yet.
+pub(crate) fn buffer(args: core::fmt::Arguments) {
+    EARLY_BUFFER.lock_irqsave().write_fmt(args).ok();
+}
+
+/// Writes the recorded output to the writer, which is usually the console that just came up.
+pub(crate) fn replay<W>(writer: &mut W)
+where
+    W: Write,
+{
+    let mut buffer = EARLY_BUFFER.lock_irqsave();
+    writer.write_str(buffer.contents()).ok();
+    buffer.clear();
+}
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let uptime = timer::uptime();
+        println!(
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            uptime.as_secs(),
+            uptime.subsec_micros(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, so that the `log` macros in the kernel and dependencies are printed.
+pub(crate) fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_everything_until_full() {
+        let mut buffer = RingBuffer::<16>::new();
+        write!(buffer, "hello, world").unwrap();
+
+        assert_eq!("hello, world", buffer.contents());
+    }
+
+    #[test]
+    fn overwrite_oldest_bytes() {
+        let mut buffer = RingBuffer::<8>::new();
+        write!(buffer, "0123456789ab").unwrap();
+
+        assert_eq!("456789ab", buffer.contents());
+
+        write!(buffer, "cd").unwrap();
+        assert_eq!("6789abcd", buffer.contents());
+    }
+
+    #[test]
+    fn skip_character_cut_by_wraparound() {
+        let mut buffer = RingBuffer::<6>::new();
+        write!(buffer, "ab\u{3042}\u{3044}z").unwrap();
+
+        assert_eq!("\u{3044}z", buffer.contents());
+    }
+
+    #[test]
+    fn clear() {
+        let mut buffer = RingBuffer::<8>::new();
+        write!(buffer, "0123456789").unwrap();
+        buffer.clear();
+        write!(buffer, "ab").unwrap();
+
+        assert_eq!("ab", buffer.contents());
+    }
+}
@@ -2,56 +2,279 @@
 #![cfg_attr(not(test), no_std)]
 #![feature(slice_as_chunks)]
 #![feature(type_alias_impl_trait)]
+// Globals are shared between the cores and the IRQ handlers, so they go behind `sync`.
+#![deny(static_mut_refs)]
 
+extern crate alloc;
+
+macro_rules! println {
+    ($($t: tt)*) => {
+        $crate::print(format_args!("{}\n", format_args!($($t)*)))
+    };
+}
+
+mod acpi;
+mod backtrace;
 mod console;
+mod exceptions;
+mod fdt;
+mod fifo;
 mod graphics;
+mod interrupts;
+mod keyboard;
+mod logger;
+mod memory;
+mod message;
+mod mmio;
+mod panic;
+mod pci;
+mod psci;
+mod serial;
+mod smp;
+mod symbols;
+mod sync;
+mod task;
+mod timer;
+mod usb;
+mod virtio;
 
-#[cfg(not(test))]
-use core::panic::PanicInfo;
-
+use alloc::format;
+use alloc::vec::Vec;
 use core::fmt::Write;
 use mikan_core::KernelArgs;
 
+use crate::acpi::Acpi;
 use crate::console::Console;
+use crate::fdt::Fdt;
+use crate::graphics::bitmap::Bitmap;
+use crate::graphics::cursor::Cursor;
 use crate::graphics::frame_buffer::FrameBuffer;
-use crate::graphics::text::TextWriter;
+use crate::graphics::layer::{LayerId, LayerManager};
+use crate::graphics::text::{TextWriter, FONT_HEIGHT, FONT_WIDTH};
+use crate::graphics::window::{self, WindowManager};
 use crate::graphics::{Canvas, Colors, Region};
+use crate::interrupts::GicConfig;
+use crate::keyboard::{Key, KeyCode};
+use crate::message::Message;
+use crate::serial::Pl011;
+use crate::sync::{Mutex, Once, SpinLock, TicketLock};
+
+static FRAME_BUFFER: SpinLock<Option<FrameBuffer>> = SpinLock::new(None);
+/// Taken in turns, as the counter task keeps compositing on another core.
+static LAYERS: TicketLock<Option<LayerManager>> = TicketLock::new(None);
+/// The console draws on the bitmap of its layer, so it is locked only with the layers locked.
+static CONSOLE: SpinLock<Option<Console>> = SpinLock::new(None);
+static CONSOLE_LAYER: Once<LayerId> = Once::new();
+static CURSOR: Mutex<Option<Cursor>> = Mutex::new(None);
+static WINDOWS: Mutex<Option<WindowManager>> = Mutex::new(None);
+static COUNTER_WINDOW: Once<LayerId> = Once::new();
+static SERIAL: SpinLock<Option<Pl011>> = SpinLock::new(None);
 
-#[panic_handler]
-#[cfg(not(test))]
-fn panic(_info: &PanicInfo) -> ! {
-    todo!()
+/// Writes to both the serial port and the console on the screen. Until the console comes up, the
+/// output is kept in the early buffer instead.
+///
+/// The locks are taken with IRQs masked, so that interrupt handlers can print as well.
+fn print(args: core::fmt::Arguments) {
+    if let Some(s) = SERIAL.lock_irqsave().as_mut() {
+        s.write_fmt(args).ok();
+    }
+
+    let printed = composite(|layers| {
+        let mut console = CONSOLE.lock();
+        let (console, &layer) = (console.as_mut()?, CONSOLE_LAYER.get()?);
+        console.on(layers.bitmap(layer)?).write_fmt(args).ok();
+        layers.invalidate(layer, console.region());
+        Some(())
+    });
+    if printed.flatten().is_none() {
+        logger::buffer(args);
+    }
 }
 
-static mut FRAME_BUFFER: Option<FrameBuffer> = None;
-static mut CONSOLE: Option<Console<FrameBuffer>> = None;
+/// Changes the layers, then shows the result on the frame buffer. The layers stay locked with IRQs
+/// masked meanwhile, so that no other task or core draws in the middle.
+fn composite<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut LayerManager) -> R,
+{
+    let mut layers = LAYERS.lock_irqsave();
+    let layers = layers.as_mut()?;
+    let result = f(layers);
+    if let Some(frame_buffer) = FRAME_BUFFER.lock().as_mut() {
+        layers.composite(frame_buffer);
+    }
 
-macro_rules! println {
-    ($($t: tt)*) => {
-        if let Some(c) = unsafe { CONSOLE.as_mut() } {
-            writeln!(c, $($t)*).ok();
-        }
-    };
+    Some(result)
+}
+
+/// Releases the locks of the output held by the panicking core, so that the panic is printed.
+///
+/// # Safety
+/// The other cores must have been stopped, or a core failing to stop may still be using what it
+/// holds. This is only for the last words.
+unsafe fn break_output_locks() {
+    if SERIAL.is_locked() {
+        SERIAL.force_unlock();
+    }
+    if LAYERS.is_locked() {
+        LAYERS.force_unlock();
+    }
+    if CONSOLE.is_locked() {
+        CONSOLE.force_unlock();
+    }
+    if FRAME_BUFFER.is_locked() {
+        FRAME_BUFFER.force_unlock();
+    }
 }
 
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 extern "C" fn kernel_main(args: KernelArgs) -> ! {
-    unsafe {
-        FRAME_BUFFER = Some(FrameBuffer::from(args.frame_buffer));
-        CONSOLE = Some(
-            Console::new(FRAME_BUFFER.as_mut().unwrap())
-                .with_position((0, 82))
-                .with_color(Colors::black()),
-        );
+    let device_tree = args
+        .device_tree
+        .and_then(|ptr| unsafe { Fdt::from_ptr(ptr.as_ptr()) });
+    let acpi = args
+        .acpi_rsdp
+        .and_then(|ptr| unsafe { Acpi::from_rsdp(ptr.as_ptr()) });
+
+    let uart = device_tree
+        .and_then(|fdt| fdt.uart())
+        .or_else(|| acpi.and_then(|acpi| acpi.uart()));
+    let mut serial = unsafe { Pl011::new(uart.unwrap_or(serial::VIRT_UART)) };
+    serial.init();
+    *SERIAL.lock_irqsave() = Some(serial);
+
+    logger::init(log::LevelFilter::Info);
+
+    // The firmware may have placed the device tree in boot services data, which is now free.
+    let reserved = device_tree.map(|fdt| fdt.as_bytes());
+    memory::init(&args.memory_map, args.frame_buffer.buf, reserved.as_slice());
+    smp::init();
+
+    if let Some(fdt) = device_tree {
+        fdt.memory().for_each(|reg| {
+            log::info!("Memory: {:#x} - {:#x}", reg.address, reg.address + reg.size)
+        });
+    }
+    if let Some(acpi) = acpi {
+        acpi.tables().for_each(|table| {
+            log::info!(
+                "ACPI: {}",
+                core::str::from_utf8(table.signature()).unwrap_or("????")
+            )
+        });
+    }
+    if device_tree.is_none() && acpi.is_none() {
+        log::warn!("No firmware tables found, assuming QEMU's virt board");
     }
 
-    let frame_buffer = unsafe { FRAME_BUFFER.as_mut().unwrap() };
+    let frame_buffer = FrameBuffer::from(args.frame_buffer);
+    let (width, height, pixel_format) = (
+        frame_buffer.width(),
+        frame_buffer.height(),
+        frame_buffer.pixel_format(),
+    );
+    let mut layers = LayerManager::new(width, height, pixel_format);
+
+    let mut background = Bitmap::new(width, height, pixel_format);
+    background.fill(Colors::white());
+    background.fill_in(Region::new((100, 100).into(), 200, 100), Colors::green());
+    background.write_chars((0, 50).into(), '!'..='~', Colors::black());
+    background.write_string((0, 66).into(), "Hello, world!", Colors::blue());
+    let background = layers.create(background);
+    layers.show(background);
+
+    let mut console = Bitmap::new(console::WIDTH, console::HEIGHT, pixel_format);
+    console.fill(Colors::white());
+    let console = layers.create(console);
+    layers.move_to(console, (0, 82).into());
+    layers.show(console);
+
+    let cursor = Cursor::new(&mut layers, pixel_format, (200, 200).into(), width, height);
+    let mut windows = WindowManager::new(cursor.layer(), pixel_format);
+    let hello = windows.create(&mut layers, "Hello Window", (300, 100).into(), 152, 44);
+    layers.draw(hello, |bitmap| {
+        ["Welcome to", "MikanOS world!"]
+            .iter()
+            .enumerate()
+            .for_each(|(i, line)| {
+                line.chars().enumerate().for_each(|(j, c)| {
+                    let position = (
+                        window::CLIENT_X + 8 + j * FONT_WIDTH,
+                        window::CLIENT_Y + 4 + i * FONT_HEIGHT,
+                    );
+                    bitmap.write_ascii(position.into(), c, Colors::black());
+                })
+            });
+    });
+    let counter = windows.create(&mut layers, "Counter", (100, 300).into(), 152, 20);
 
-    frame_buffer.fill(Colors::white());
-    frame_buffer.fill_in(Region::new((100, 100).into(), 200, 100), Colors::green());
-    frame_buffer.write_chars((0, 50).into(), '!'..='~', Colors::black());
-    frame_buffer.write_string((0, 66).into(), "Hello, world!", Colors::blue());
+    *CURSOR.lock() = Some(cursor);
+    *WINDOWS.lock() = Some(windows);
+    COUNTER_WINDOW.set(counter).ok();
+    *CONSOLE.lock_irqsave() = Some(Console::new().with_color(Colors::black()));
+    CONSOLE_LAYER.set(console).ok();
+    *FRAME_BUFFER.lock_irqsave() = Some(frame_buffer);
+    *LAYERS.lock_irqsave() = Some(layers);
+
+    composite(|layers| {
+        let mut writer = CONSOLE.lock();
+        let writer = writer.as_mut().unwrap();
+        logger::replay(&mut writer.on(layers.bitmap(console).unwrap()));
+        layers.invalidate(console, writer.region());
+    });
+
+    exceptions::init();
+    interrupts::init(
+        device_tree
+            .and_then(|fdt| GicConfig::from_device_tree(&fdt))
+            .or_else(|| acpi.and_then(|acpi| GicConfig::from_madt(&acpi.madt()?)))
+            .unwrap_or_else(GicConfig::qemu_virt),
+    );
+    panic::init_cpu();
+    timer::init(
+        device_tree
+            .and_then(|fdt| fdt.timer_interrupts())
+            .or_else(|| acpi.and_then(|acpi| acpi.timer_interrupts()))
+            .map_or(timer::VIRT_INTID, |t| t.non_secure_physical),
+    );
+    task::init();
+    interrupts::unmask();
+
+    let conduit = device_tree
+        .and_then(|fdt| fdt.psci())
+        .or_else(|| acpi.and_then(|acpi| acpi.psci()));
+    if let Some(conduit) = conduit {
+        let cpus = match device_tree {
+            Some(fdt) => fdt.cpus().collect::<Vec<_>>(),
+            None => acpi
+                .and_then(|acpi| acpi.madt())
+                .map(|madt| {
+                    madt.cpu_interfaces()
+                        .filter(|gicc| gicc.is_enabled())
+                        .map(|gicc| gicc.mpidr)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        smp::start_secondaries(conduit, cpus);
+    }
+
+    let ecam = device_tree
+        .and_then(|fdt| fdt.pci_ecam())
+        .or_else(|| acpi.and_then(|acpi| acpi.pci_ecam().next()));
+    if let Some(ecam) = ecam {
+        let memory_window = device_tree
+            .and_then(|fdt| fdt.pci_memory_window())
+            .map(|reg| (reg.address, reg.size));
+        pci::init(ecam, memory_window);
+        pci::print_devices();
+        usb::init();
+    }
+    match device_tree {
+        Some(fdt) => virtio::init(fdt.virtio_mmio()),
+        None => virtio::init(virtio::mmio::qemu_virt()),
+    }
 
     println!("1 + 2 = {}", 1 + 2);
     println!(
@@ -60,7 +283,106 @@ extern "C" fn kernel_main(args: KernelArgs) -> ! {
 
     (0..30).for_each(|i| println!("line {}", i));
 
+    let squares = (0..10).map(|i| i * i).collect::<Vec<_>>();
+    println!("Squares on the heap: {:?}", squares);
+
+    // Counted before printing, which may grow the heap with frames.
+    let free = memory::FRAME_ALLOCATOR.lock_irqsave().free_frames();
+    log::info!(
+        "Free memory: {} MiB",
+        free * memory::FRAME_SIZE / 1024 / 1024
+    );
+
+    log::info!("Booted in {:?}", timer::uptime());
+    timer::set_timeout(1000, timer::post_timeout);
+    task::spawn(count, 0, task::DEFAULT_LEVEL);
+    task::set_level(task::MAIN, task::MAX_LEVEL);
+
+    run()
+}
+
+/// Main loop handling messages from interrupt handlers, blocked while there are none.
+///
+/// The main task runs at the highest level, so that the messages are handled as soon as they
+/// arrive.
+fn run() -> ! {
+    let mut dropped = 0;
     loop {
-        aarch64::instructions::halt();
+        match task::receive() {
+            Message::Timer(id) => println!("Timer {:?} expired at {:?}", id, timer::uptime()),
+            Message::Keyboard(event) => keyboard::handle(event).into_iter().for_each(type_key),
+            Message::KeyRepeat(id) => keyboard::repeat(id).into_iter().for_each(type_key),
+            Message::Mouse(event) => {
+                move_cursor(event.buttons, |c, l| c.move_by(l, event.dx, event.dy))
+            }
+            Message::Pointer(event) => {
+                move_cursor(event.buttons, |c, l| c.point_at(l, event.x, event.y))
+            }
+            Message::Xhci => usb::process_events(),
+            Message::Virtio => virtio::process_events(),
+        }
+
+        if message::dropped() != dropped {
+            dropped = message::dropped();
+            log::warn!("{} messages have been dropped", dropped);
+        }
+    }
+}
+
+/// Counts up in the counter window as fast as it can, while the other tasks keep running.
+fn count(_: u64) {
+    let Some(&layer) = COUNTER_WINDOW.get() else {
+        return;
+    };
+
+    let mut count = 0u64;
+    loop {
+        let text = format!("{:010}", count);
+        composite(|layers| {
+            layers.draw(layer, |bitmap| {
+                let position = (window::CLIENT_X + 8, window::CLIENT_Y + 2);
+                bitmap.fill_in(
+                    Region::new(position.into(), text.len() * FONT_WIDTH, FONT_HEIGHT),
+                    Colors::light_gray(),
+                );
+                text.chars().enumerate().for_each(|(i, c)| {
+                    let position = (position.0 + i * FONT_WIDTH, position.1);
+                    bitmap.write_ascii(position.into(), c, Colors::black());
+                });
+            })
+        });
+        count += 1;
+    }
+}
+
+/// Echoes the key typed to the console, unless it is a hotkey.
+fn type_key(key: Key) {
+    match key {
+        // Ctrl+Alt+Space switches the keyboard layout.
+        Key {
+            code: KeyCode::Space,
+            modifiers,
+            ..
+        } if modifiers.ctrl && modifiers.alt => {
+            let layout = keyboard::layout().next();
+            keyboard::set_layout(layout);
+            log::info!("Keyboard layout: {:?}", layout);
+        }
+        Key { char: Some(c), .. } if c == '\n' || !c.is_control() => print(format_args!("{}", c)),
+        _ => {}
+    }
+}
+
+/// Moves the cursor, then lets the windows handle the buttons at where it is.
+fn move_cursor<F>(buttons: u8, f: F)
+where
+    F: FnOnce(&mut Cursor, &mut LayerManager),
+{
+    let (mut cursor, mut windows) = (CURSOR.lock(), WINDOWS.lock());
+    if let (Some(cursor), Some(windows)) = (cursor.as_mut(), windows.as_mut()) {
+        composite(|layers| {
+            f(cursor, layers);
+            windows.handle_pointer(layers, cursor.position(), buttons);
+        });
     }
 }
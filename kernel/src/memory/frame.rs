@@ -0,0 +1,249 @@
+use mikan_core::{MemoryDescriptor, MemoryType, PAGE_SIZE};
+
+pub(crate) const FRAME_SIZE: usize = PAGE_SIZE;
+
+const BITS_PER_LINE: usize = u64::BITS as usize;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct FrameId(usize);
+
+impl FrameId {
+    #[inline]
+    pub(crate) const fn containing(address: usize) -> Self {
+        Self(address / FRAME_SIZE)
+    }
+
+    #[inline]
+    pub(crate) const fn id(self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    pub(crate) const fn address(self) -> usize {
+        self.0 * FRAME_SIZE
+    }
+}
+
+/// Returns true if the region can be handed out after exiting boot services.
+#[inline]
+fn is_available(descriptor: &MemoryDescriptor) -> bool {
+    matches!(
+        descriptor.ty,
+        MemoryType::CONVENTIONAL | MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA
+    )
+}
+
+/// Physical frame allocator managing `LINES * 64` frames with a bit per frame.
+pub(crate) struct BitmapFrameAllocator<const LINES: usize> {
+    bitmap: [u64; LINES],
+    begin: FrameId,
+    end: FrameId,
+}
+
+impl<const LINES: usize> BitmapFrameAllocator<LINES> {
+    pub(crate) const FRAMES: usize = LINES * BITS_PER_LINE;
+
+    /// Creates an allocator without any frames, call `load` to make frames available.
+    pub(crate) const fn new() -> Self {
+        Self {
+            bitmap: [0; LINES],
+            begin: FrameId(0),
+            end: FrameId(0),
+        }
+    }
+
+    /// Marks the available regions in the memory map as free, and everything else as allocated.
+    pub(crate) fn load<'a, I>(&mut self, descriptors: I)
+    where
+        I: IntoIterator<Item = &'a MemoryDescriptor>,
+    {
+        self.bitmap.fill(u64::MAX);
+
+        let (begin, end) = descriptors.into_iter().filter(|d| is_available(d)).fold(
+            (Self::FRAMES, 0),
+            |(begin, end), d| {
+                let first = FrameId::containing(d.phys_start as usize)
+                    .id()
+                    .min(Self::FRAMES);
+                let last = (first + d.page_count as usize).min(Self::FRAMES);
+
+                (first..last).for_each(|i| self.set_bit(FrameId(i), false));
+                (begin.min(first), end.max(last))
+            },
+        );
+
+        self.begin = FrameId(begin.min(end));
+        self.end = FrameId(end);
+    }
+
+    /// Marks all frames overlapping the region as allocated, so that they are never handed out.
+    pub(crate) fn reserve(&mut self, address: usize, size: usize) {
+        let first = FrameId::containing(address).id();
+        let last = (address + size).div_ceil(FRAME_SIZE).min(Self::FRAMES);
+
+        (first..last).for_each(|i| self.set_bit(FrameId(i), true));
+    }
+
+    pub(crate) fn allocate(&mut self) -> Option<FrameId> {
+        self.allocate_contiguous(1)
+    }
+
+    /// Allocates `count` physically contiguous frames and returns the first one, or `None` if
+    /// `count` is zero.
+    pub(crate) fn allocate_contiguous(&mut self, count: usize) -> Option<FrameId> {
+        if count == 0 {
+            return None;
+        }
+
+        let mut start = self.begin.id();
+        loop {
+            let mut i = 0;
+            while i < count {
+                if start + i >= self.end.id() {
+                    return None;
+                }
+                if self.get_bit(FrameId(start + i)) {
+                    break;
+                }
+                i += 1;
+            }
+
+            if i == count {
+                (start..start + count).for_each(|i| self.set_bit(FrameId(i), true));
+                return Some(FrameId(start));
+            }
+
+            start += i + 1;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn free(&mut self, frame: FrameId) {
+        self.free_contiguous(frame, 1)
+    }
+
+    pub(crate) fn free_contiguous(&mut self, frame: FrameId, count: usize) {
+        (frame.id()..frame.id() + count).for_each(|i| {
+            debug_assert!(self.get_bit(FrameId(i)), "double free of frame {}", i);
+            self.set_bit(FrameId(i), false)
+        });
+    }
+
+    /// Counts the frames that are not allocated yet.
+    pub(crate) fn free_frames(&self) -> usize {
+        (self.begin.id()..self.end.id())
+            .filter(|&i| !self.get_bit(FrameId(i)))
+            .count()
+    }
+
+    #[inline]
+    fn get_bit(&self, frame: FrameId) -> bool {
+        let (line, bit) = (frame.id() / BITS_PER_LINE, frame.id() % BITS_PER_LINE);
+        self.bitmap[line] & (1 << bit) != 0
+    }
+
+    #[inline]
+    fn set_bit(&mut self, frame: FrameId, allocated: bool) {
+        let (line, bit) = (frame.id() / BITS_PER_LINE, frame.id() % BITS_PER_LINE);
+        if allocated {
+            self.bitmap[line] |= 1 << bit;
+        } else {
+            self.bitmap[line] &= !(1 << bit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Allocator = BitmapFrameAllocator<4>;
+
+    fn descriptor(ty: MemoryType, first: usize, count: usize) -> MemoryDescriptor {
+        MemoryDescriptor {
+            ty,
+            phys_start: (first * FRAME_SIZE) as u64,
+            page_count: count as u64,
+            attribute: 0,
+        }
+    }
+
+    fn allocator(descriptors: &[MemoryDescriptor]) -> Allocator {
+        let mut allocator = Allocator::new();
+        allocator.load(descriptors);
+        allocator
+    }
+
+    #[test]
+    fn load_marks_available_regions_free() {
+        let allocator = allocator(&[
+            descriptor(MemoryType::LOADER_DATA, 0, 4),
+            descriptor(MemoryType::CONVENTIONAL, 4, 8),
+            descriptor(MemoryType::BOOT_SERVICES_DATA, 12, 4),
+            descriptor(MemoryType::RUNTIME_SERVICES_DATA, 16, 4),
+            descriptor(MemoryType::BOOT_SERVICES_CODE, 24, 8),
+        ]);
+
+        assert_eq!(8 + 4 + 8, allocator.free_frames());
+        assert!(allocator.get_bit(FrameId(0)));
+        assert!(!allocator.get_bit(FrameId(4)));
+        assert!(allocator.get_bit(FrameId(16)));
+        assert!(allocator.get_bit(FrameId(20)));
+        assert!(!allocator.get_bit(FrameId(31)));
+    }
+
+    #[test]
+    fn load_ignores_frames_beyond_capacity() {
+        let allocator = allocator(&[descriptor(MemoryType::CONVENTIONAL, 250, 100)]);
+
+        assert_eq!(Allocator::FRAMES - 250, allocator.free_frames());
+    }
+
+    #[test]
+    fn allocate_and_free() {
+        let mut allocator = allocator(&[descriptor(MemoryType::CONVENTIONAL, 10, 2)]);
+
+        assert_eq!(Some(FrameId(10)), allocator.allocate());
+        assert_eq!(Some(FrameId(11)), allocator.allocate());
+        assert_eq!(None, allocator.allocate());
+
+        allocator.free(FrameId(10));
+        assert_eq!(Some(FrameId(10)), allocator.allocate());
+    }
+
+    #[test]
+    fn allocate_contiguous_skips_fragments() {
+        let mut allocator = allocator(&[
+            descriptor(MemoryType::CONVENTIONAL, 0, 3),
+            descriptor(MemoryType::CONVENTIONAL, 4, 8),
+        ]);
+
+        assert_eq!(Some(FrameId(4)), allocator.allocate_contiguous(4));
+        assert_eq!(Some(FrameId(0)), allocator.allocate_contiguous(3));
+        assert_eq!(None, allocator.allocate_contiguous(5));
+        assert_eq!(Some(FrameId(8)), allocator.allocate_contiguous(4));
+
+        allocator.free_contiguous(FrameId(4), 4);
+        assert_eq!(Some(FrameId(4)), allocator.allocate_contiguous(4));
+    }
+
+    #[test]
+    fn allocate_no_frames() {
+        let mut allocator = allocator(&[descriptor(MemoryType::CONVENTIONAL, 0, 8)]);
+
+        assert_eq!(None, allocator.allocate_contiguous(0));
+        assert_eq!(8, allocator.free_frames());
+    }
+
+    #[test]
+    fn reserve_overlapping_frames() {
+        let mut allocator = allocator(&[descriptor(MemoryType::CONVENTIONAL, 0, 8)]);
+
+        allocator.reserve(FRAME_SIZE + 1, FRAME_SIZE);
+
+        assert_eq!(6, allocator.free_frames());
+        assert!(allocator.get_bit(FrameId(1)));
+        assert!(allocator.get_bit(FrameId(2)));
+        assert_eq!(Some(FrameId(3)), allocator.allocate_contiguous(5));
+    }
+}
@@ -0,0 +1,280 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::{null_mut, NonNull};
+
+use super::{FRAME_ALLOCATOR, FRAME_SIZE};
+use crate::interrupts;
+use crate::sync::SpinLock;
+
+/// Number of frames to take from the frame allocator at least when the heap runs out.
+#[cfg_attr(test, allow(dead_code))]
+const GROWTH_FRAMES: usize = 64;
+
+/// Header written at the beginning of every free block.
+struct Node {
+    size: usize,
+    next: *mut Node,
+}
+
+const NODE_SIZE: usize = size_of::<Node>();
+const NODE_ALIGN: usize = align_of::<Node>();
+
+#[inline]
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+/// Returns the size and the alignment actually used for the layout, so that every freed block
+/// can hold a node.
+#[inline]
+fn normalize(layout: Layout) -> (usize, usize) {
+    (
+        align_up(layout.size().max(NODE_SIZE), NODE_ALIGN),
+        layout.align().max(NODE_ALIGN),
+    )
+}
+
+/// First-fit heap keeping free blocks in a list sorted by their addresses.
+pub(crate) struct LinkedListHeap {
+    head: Node,
+}
+
+/// The free blocks are reached only through the heap, wherever it is moved to.
+unsafe impl Send for LinkedListHeap {}
+
+impl LinkedListHeap {
+    pub(crate) const fn empty() -> Self {
+        Self {
+            head: Node {
+                size: 0,
+                next: null_mut(),
+            },
+        }
+    }
+
+    /// Hands a region of memory over to the heap.
+    ///
+    /// # Safety
+    /// The region must be valid, writable and not used by anything else.
+    pub(crate) unsafe fn add_region(&mut self, address: usize, size: usize) {
+        let start = align_up(address, NODE_ALIGN);
+        let end = (address + size) & !(NODE_ALIGN - 1);
+        if end > start && end - start >= NODE_SIZE {
+            self.insert(start, end - start);
+        }
+    }
+
+    pub(crate) fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = normalize(layout);
+
+        let mut prev: *mut Node = &mut self.head;
+        unsafe {
+            while let Some(node) = (*prev).next.as_mut() {
+                let start = node as *mut Node as usize;
+                let end = start + node.size;
+
+                let mut address = align_up(start, align);
+                if address != start && address - start < NODE_SIZE {
+                    address = align_up(start + NODE_SIZE, align);
+                }
+
+                let tail = end.checked_sub(address + size);
+                if matches!(tail, Some(tail) if tail == 0 || tail >= NODE_SIZE) {
+                    (*prev).next = node.next;
+                    if address > start {
+                        self.insert(start, address - start);
+                    }
+                    if address + size < end {
+                        self.insert(address + size, end - address - size);
+                    }
+
+                    return NonNull::new(address as *mut u8);
+                }
+
+                prev = node;
+            }
+        }
+
+        None
+    }
+
+    /// # Safety
+    /// The pointer must have been allocated from this heap with the same layout.
+    pub(crate) unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (size, _) = normalize(layout);
+        self.insert(ptr.as_ptr() as usize, size);
+    }
+
+    /// Counts the bytes in the free list.
+    #[allow(dead_code)]
+    pub(crate) fn free_bytes(&self) -> usize {
+        let mut bytes = 0;
+        let mut node = self.head.next;
+        while let Some(n) = unsafe { node.as_ref() } {
+            bytes += n.size;
+            node = n.next;
+        }
+
+        bytes
+    }
+
+    /// Inserts a free block keeping the list sorted, merging it with adjacent blocks.
+    unsafe fn insert(&mut self, address: usize, size: usize) {
+        let head: *mut Node = &mut self.head;
+
+        let mut prev = head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < address {
+            prev = (*prev).next;
+        }
+
+        let next = (*prev).next;
+        let node = address as *mut Node;
+        node.write(Node { size, next });
+        (*prev).next = node;
+
+        if !next.is_null() && address + size == next as usize {
+            (*node).size += (*next).size;
+            (*node).next = (*next).next;
+        }
+
+        if prev != head && prev as usize + (*prev).size == address {
+            (*prev).size += (*node).size;
+            (*prev).next = (*node).next;
+        }
+    }
+}
+
+/// Global allocator of the kernel, growing the heap with frames from the frame allocator.
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) struct KernelAllocator {
+    heap: SpinLock<LinkedListHeap>,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl KernelAllocator {
+    pub(crate) const fn new() -> Self {
+        Self {
+            heap: SpinLock::new(LinkedListHeap::empty()),
+        }
+    }
+
+    fn grow(heap: &mut LinkedListHeap, layout: Layout) -> Option<()> {
+        let (size, align) = normalize(layout);
+        let frames = (size + align).div_ceil(FRAME_SIZE).max(GROWTH_FRAMES);
+        let frame = FRAME_ALLOCATOR.lock().allocate_contiguous(frames)?;
+
+        unsafe { heap.add_region(frame.address(), frames * FRAME_SIZE) };
+        Some(())
+    }
+}
+
+/// IRQs are masked while the heap is locked, or an interrupt handler allocating on the same core
+/// would wait for the lock forever.
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = interrupts::without_interrupts(|| {
+            let mut heap = self.heap.lock();
+            let heap = &mut *heap;
+            heap.allocate(layout)
+                .or_else(|| Self::grow(heap, layout).and_then(|_| heap.allocate(layout)))
+        });
+
+        // Logged with the heap unlocked, since printing allocates as well.
+        match ptr {
+            Some(ptr) => ptr.as_ptr(),
+            None => {
+                log::error!(
+                    "Out of memory: failed to allocate {} bytes aligned to {}",
+                    layout.size(),
+                    layout.align()
+                );
+                null_mut()
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            interrupts::without_interrupts(|| self.heap.lock().deallocate(ptr, layout));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 4096;
+
+    #[repr(align(4096))]
+    struct Memory([u8; SIZE]);
+
+    fn heap(memory: &mut Memory) -> LinkedListHeap {
+        let mut heap = LinkedListHeap::empty();
+        unsafe { heap.add_region(memory.0.as_mut_ptr() as usize, SIZE) };
+        heap
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn allocate_and_deallocate() {
+        let mut memory = Memory([0; SIZE]);
+        let mut heap = heap(&mut memory);
+
+        let a = heap.allocate(layout(100, 8)).unwrap();
+        let b = heap.allocate(layout(100, 8)).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(SIZE - 2 * 104, heap.free_bytes());
+
+        unsafe { heap.deallocate(a, layout(100, 8)) };
+        assert_eq!(Some(a), heap.allocate(layout(100, 8)));
+    }
+
+    #[test]
+    fn allocate_aligned() {
+        let mut memory = Memory([0; SIZE]);
+        let mut heap = heap(&mut memory);
+
+        heap.allocate(layout(8, 8)).unwrap();
+        let ptr = heap.allocate(layout(64, 256)).unwrap();
+
+        assert_eq!(0, ptr.as_ptr() as usize % 256);
+        assert_eq!(SIZE - 16 - 64, heap.free_bytes());
+    }
+
+    #[test]
+    fn merge_adjacent_blocks() {
+        let mut memory = Memory([0; SIZE]);
+        let mut heap = heap(&mut memory);
+
+        let blocks = [(); 4].map(|_| heap.allocate(layout(1024, 8)).unwrap());
+        assert_eq!(None, heap.allocate(layout(1, 1)));
+
+        unsafe {
+            heap.deallocate(blocks[1], layout(1024, 8));
+            heap.deallocate(blocks[3], layout(1024, 8));
+            heap.deallocate(blocks[2], layout(1024, 8));
+        }
+
+        assert_eq!(Some(blocks[1]), heap.allocate(layout(3072, 8)));
+    }
+
+    #[test]
+    fn add_region_to_exhausted_heap() {
+        let mut memory = Memory([0; SIZE]);
+        let mut extra = Memory([0; SIZE]);
+        let mut heap = heap(&mut memory);
+
+        assert!(heap.allocate(layout(SIZE, 8)).is_some());
+        assert_eq!(None, heap.allocate(layout(SIZE, 8)));
+
+        unsafe { heap.add_region(extra.0.as_mut_ptr() as usize, SIZE) };
+        assert_eq!(
+            NonNull::new(extra.0.as_mut_ptr()),
+            heap.allocate(layout(SIZE, 8))
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use alloc::boxed::Box;
+use core::alloc::Layout;
+use mikan_core::{MemoryMap, KERNEL_ADDRESS};
+
+use crate::sync::SpinLock;
+
+pub(crate) mod frame;
+pub(crate) mod heap;
+pub(crate) mod paging;
+
+pub(crate) use frame::{BitmapFrameAllocator, FRAME_SIZE};
+pub(crate) use heap::KernelAllocator;
+
+/// Upper bound of the physical memory managed by the kernel.
+const MAX_PHYSICAL_MEMORY: usize = 16 * 1024 * 1024 * 1024;
+
+pub(crate) type FrameAllocator = BitmapFrameAllocator<{ MAX_PHYSICAL_MEMORY / FRAME_SIZE / 64 }>;
+
+/// Taken by the heap with IRQs masked, so that it must be locked with `lock_irqsave` elsewhere.
+pub(crate) static FRAME_ALLOCATOR: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator::new());
+
+#[cfg_attr(not(test), global_allocator)]
+#[cfg_attr(test, allow(dead_code))]
+static ALLOCATOR: KernelAllocator = KernelAllocator::new();
+
+extern "C" {
+    /// End of the kernel image, page aligned by `kernel.ld`.
+    static _end: u8;
+}
+
+/// Builds the frame allocator from the memory map handed over by the loader.
+///
+/// The kernel image, the frame buffer, the region holding the current stack (which the firmware
+/// allocated as boot services data) and the other regions still in use are reserved so that they
+/// are never handed out. Then the kernel switches to its own page tables, taking the frames of the
+/// tables from the allocator.
+pub(crate) fn init(memory_map: &MemoryMap, frame_buffer: &[u8], reserved: &[&[u8]]) {
+    let mut allocator = FRAME_ALLOCATOR.lock_irqsave();
+    allocator.load(memory_map.iter());
+
+    let kernel_end = unsafe { &_end as *const u8 as usize };
+    allocator.reserve(
+        KERNEL_ADDRESS as usize,
+        kernel_end - KERNEL_ADDRESS as usize,
+    );
+
+    allocator.reserve(frame_buffer.as_ptr() as usize, frame_buffer.len());
+    reserved
+        .iter()
+        .for_each(|region| allocator.reserve(region.as_ptr() as usize, region.len()));
+
+    let stack = 0u8;
+    let stack = &stack as *const u8 as u64;
+    if let Some(d) = memory_map
+        .iter()
+        .find(|d| (d.phys_start..d.phys_end()).contains(&stack))
+    {
+        allocator.reserve(
+            d.phys_start as usize,
+            (d.phys_end() - d.phys_start) as usize,
+        );
+    }
+    drop(allocator);
+
+    paging::init(memory_map, frame_buffer);
+}
+
+/// Allocates a zeroed value on the heap, keeping its alignment for the devices sharing it.
+pub(crate) fn zeroed<T>() -> Box<T> {
+    let layout = Layout::new::<T>();
+    unsafe {
+        let ptr = alloc::alloc::alloc_zeroed(layout) as *mut T;
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        Box::from_raw(ptr)
+    }
+}
@@ -0,0 +1,603 @@
+//! Stage-1 translation of the 4 KiB granule, identity mapping the physical memory with the
+//! attributes each region needs.
+
+use core::arch::asm;
+use mikan_core::{MemoryMap, KERNEL_ADDRESS};
+
+use super::{FRAME_ALLOCATOR, FRAME_SIZE};
+
+const ENTRIES: usize = 512;
+const LEVELS: usize = 4;
+
+/// Bits of the virtual addresses, translated from level 0 in both halves.
+const VA_BITS: u64 = 48;
+
+/// How far the physical address space is mapped for devices, which covers the high PCI windows of
+/// QEMU's `virt` board.
+const DEVICE_LIMIT: usize = 1 << 40;
+
+/// Set in the regions of the UEFI memory map which can be cached, i.e. the memory.
+const EFI_MEMORY_WB: u64 = 0x8;
+
+const DESCRIPTOR_VALID: u64 = 1 << 0;
+/// Set in table descriptors, and in page descriptors at the last level unlike block descriptors.
+const DESCRIPTOR_TABLE: u64 = 1 << 1;
+const DESCRIPTOR_ATTR_INDEX_SHIFT: u64 = 2;
+const DESCRIPTOR_READ_ONLY: u64 = 1 << 7;
+const DESCRIPTOR_INNER_SHAREABLE: u64 = 0b11 << 8;
+const DESCRIPTOR_ACCESS: u64 = 1 << 10;
+const DESCRIPTOR_PXN: u64 = 1 << 53;
+const DESCRIPTOR_UXN: u64 = 1 << 54;
+const DESCRIPTOR_ADDRESS: u64 = 0x0000_FFFF_FFFF_F000;
+
+/// Attributes of `MAIR_EL1` from the index 4, leaving the ones the firmware set at 0 to 3 so that
+/// its translation cached in the TLBs stays valid until flushed.
+const MAIR_INDEX_NORMAL: u64 = 4;
+const MAIR_INDEX_NON_CACHEABLE: u64 = 5;
+const MAIR_INDEX_DEVICE: u64 = 6;
+const MAIR_NORMAL: u64 = 0xFF;
+const MAIR_NON_CACHEABLE: u64 = 0x44;
+const MAIR_DEVICE_NGNRE: u64 = 0x04;
+const MAIR_FIRMWARE: u64 = 0xFFFF_FFFF;
+
+const TCR_T0SZ_SHIFT: u64 = 0;
+const TCR_IRGN0_WB: u64 = 0b01 << 8;
+const TCR_ORGN0_WB: u64 = 0b01 << 10;
+const TCR_SH0_INNER: u64 = 0b11 << 12;
+const TCR_TG0_4K: u64 = 0b00 << 14;
+const TCR_T1SZ_SHIFT: u64 = 16;
+const TCR_IRGN1_WB: u64 = 0b01 << 24;
+const TCR_ORGN1_WB: u64 = 0b01 << 26;
+const TCR_SH1_INNER: u64 = 0b11 << 28;
+const TCR_TG1_4K: u64 = 0b10 << 30;
+const TCR_IPS_SHIFT: u64 = 32;
+
+/// `ID_AA64MMFR0_EL1.PARange` of 48 bits, beyond which the descriptors need another format.
+const PA_RANGE_48: u64 = 0b101;
+
+extern "C" {
+    /// End of the read-only part of the kernel image, page aligned by `kernel.ld`.
+    static __text_end: u8;
+}
+
+/// Cacheability of the memory, which indexes the attributes in `MAIR_EL1`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum MemoryKind {
+    Normal,
+    /// Normal but not cached, to combine the writes to the frame buffer.
+    NonCacheable,
+    /// Device-nGnRE, for the registers of devices.
+    Device,
+}
+
+impl MemoryKind {
+    const fn index(self) -> u64 {
+        match self {
+            Self::Normal => MAIR_INDEX_NORMAL,
+            Self::NonCacheable => MAIR_INDEX_NON_CACHEABLE,
+            Self::Device => MAIR_INDEX_DEVICE,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn from_index(index: u64) -> Option<Self> {
+        match index {
+            MAIR_INDEX_NORMAL => Some(Self::Normal),
+            MAIR_INDEX_NON_CACHEABLE => Some(Self::NonCacheable),
+            MAIR_INDEX_DEVICE => Some(Self::Device),
+            _ => None,
+        }
+    }
+}
+
+/// How the kernel may access a mapped region. Nothing is accessible from EL0.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Attributes {
+    pub(crate) memory: MemoryKind,
+    pub(crate) writable: bool,
+    pub(crate) executable: bool,
+}
+
+impl Attributes {
+    pub(crate) const TEXT: Self = Self::new(MemoryKind::Normal, false, true);
+    pub(crate) const DATA: Self = Self::new(MemoryKind::Normal, true, false);
+    pub(crate) const FRAME_BUFFER: Self = Self::new(MemoryKind::NonCacheable, true, false);
+    pub(crate) const DEVICE: Self = Self::new(MemoryKind::Device, true, false);
+
+    const fn new(memory: MemoryKind, writable: bool, executable: bool) -> Self {
+        Self {
+            memory,
+            writable,
+            executable,
+        }
+    }
+
+    /// Bits of the block or page descriptors but the address and the type.
+    fn descriptor(self) -> u64 {
+        let mut descriptor =
+            self.memory.index() << DESCRIPTOR_ATTR_INDEX_SHIFT | DESCRIPTOR_ACCESS | DESCRIPTOR_UXN;
+        if self.memory != MemoryKind::Device {
+            descriptor |= DESCRIPTOR_INNER_SHAREABLE;
+        }
+        if !self.writable {
+            descriptor |= DESCRIPTOR_READ_ONLY;
+        }
+        if !self.executable {
+            descriptor |= DESCRIPTOR_PXN;
+        }
+        descriptor
+    }
+
+    #[allow(dead_code)]
+    fn from_descriptor(descriptor: u64) -> Option<Self> {
+        Some(Self {
+            memory: MemoryKind::from_index((descriptor >> DESCRIPTOR_ATTR_INDEX_SHIFT) & 0b111)?,
+            writable: descriptor & DESCRIPTOR_READ_ONLY == 0,
+            executable: descriptor & DESCRIPTOR_PXN == 0,
+        })
+    }
+}
+
+#[repr(C, align(4096))]
+pub(crate) struct PageTable {
+    entries: [u64; ENTRIES],
+}
+
+impl PageTable {
+    pub(crate) const fn empty() -> Self {
+        Self {
+            entries: [0; ENTRIES],
+        }
+    }
+}
+
+/// Bytes an entry of the table at the level maps.
+#[inline]
+const fn entry_size(level: usize) -> usize {
+    1 << (12 + 9 * (LEVELS - 1 - level))
+}
+
+#[inline]
+const fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+/// Translation tables from the root at level 0, whose tables are in identity mapped frames.
+///
+/// The tables are built before switched to, as changing the entries in use would need
+/// break-before-make and TLB maintenance.
+pub(crate) struct AddressSpace {
+    root: *mut PageTable,
+}
+
+impl AddressSpace {
+    /// Creates an empty address space, allocating the tables with `allocate` which returns the
+    /// address of a frame.
+    pub(crate) fn new<A>(allocate: &mut A) -> Option<Self>
+    where
+        A: FnMut() -> Option<usize>,
+    {
+        Some(Self {
+            root: Self::allocate_table(allocate)?,
+        })
+    }
+
+    /// Address of the root table, to be set to `TTBR0_EL1` or `TTBR1_EL1`.
+    #[inline]
+    pub(crate) fn root(&self) -> usize {
+        self.root as usize
+    }
+
+    /// Maps the virtual addresses to the physical ones, with blocks as large as aligned and pages
+    /// for the rest. Blocks mapped before are split where the mapping overrides them.
+    ///
+    /// Returns `None` if the addresses are not aligned to pages, or out of frames for the tables.
+    pub(crate) fn map<A>(
+        &mut self,
+        address: usize,
+        physical: usize,
+        size: usize,
+        attributes: Attributes,
+        allocate: &mut A,
+    ) -> Option<()>
+    where
+        A: FnMut() -> Option<usize>,
+    {
+        let end = address.checked_add(size)?;
+        if !(address | physical | size).is_multiple_of(FRAME_SIZE) || end > 1 << VA_BITS {
+            return None;
+        }
+
+        unsafe { Self::map_in(self.root, 0, address, physical, end, attributes, allocate) }
+    }
+
+    /// Looks up the physical address and the attributes the address translates to.
+    #[allow(dead_code)]
+    pub(crate) fn translate(&self, address: usize) -> Option<(usize, Attributes)> {
+        let mut table = self.root;
+        for level in 0..LEVELS {
+            let size = entry_size(level);
+            let entry = unsafe { (*table).entries[(address / size) % ENTRIES] };
+            if entry & DESCRIPTOR_VALID == 0 {
+                return None;
+            }
+            if level < LEVELS - 1 && entry & DESCRIPTOR_TABLE != 0 {
+                table = (entry & DESCRIPTOR_ADDRESS) as *mut PageTable;
+                continue;
+            }
+            // Level 0 has no blocks, and the last level has no tables.
+            if level == 0 || entry & DESCRIPTOR_TABLE == 0 && level == LEVELS - 1 {
+                return None;
+            }
+
+            let base = (entry & DESCRIPTOR_ADDRESS) as usize & !(size - 1);
+            return Some((base | (address % size), Attributes::from_descriptor(entry)?));
+        }
+
+        None
+    }
+
+    fn allocate_table<A>(allocate: &mut A) -> Option<*mut PageTable>
+    where
+        A: FnMut() -> Option<usize>,
+    {
+        let table = allocate()? as *mut PageTable;
+        unsafe { table.write(PageTable::empty()) };
+        Some(table)
+    }
+
+    unsafe fn map_in<A>(
+        table: *mut PageTable,
+        level: usize,
+        mut address: usize,
+        mut physical: usize,
+        end: usize,
+        attributes: Attributes,
+        allocate: &mut A,
+    ) -> Option<()>
+    where
+        A: FnMut() -> Option<usize>,
+    {
+        let size = entry_size(level);
+        while address < end {
+            let len = ((address & !(size - 1)) + size).min(end) - address;
+            let entry = &mut (*table).entries[(address / size) % ENTRIES];
+
+            let is_table = level < LEVELS - 1 && *entry & DESCRIPTOR_TABLE != 0;
+            if level > 0 && len == size && physical.is_multiple_of(size) && !is_table {
+                let ty = match level == LEVELS - 1 {
+                    true => DESCRIPTOR_TABLE,
+                    false => 0,
+                };
+                *entry = physical as u64 | attributes.descriptor() | ty | DESCRIPTOR_VALID;
+            } else {
+                let next = Self::next_table(entry, level, allocate)?;
+                Self::map_in(
+                    next,
+                    level + 1,
+                    address,
+                    physical,
+                    address + len,
+                    attributes,
+                    allocate,
+                )?;
+            }
+
+            address += len;
+            physical += len;
+        }
+
+        Some(())
+    }
+
+    /// Returns the table the entry points to, creating it if the entry is invalid, or splitting
+    /// the block it maps into the entries of the new table.
+    unsafe fn next_table<A>(
+        entry: &mut u64,
+        level: usize,
+        allocate: &mut A,
+    ) -> Option<*mut PageTable>
+    where
+        A: FnMut() -> Option<usize>,
+    {
+        if *entry & (DESCRIPTOR_VALID | DESCRIPTOR_TABLE) == DESCRIPTOR_VALID | DESCRIPTOR_TABLE {
+            return Some((*entry & DESCRIPTOR_ADDRESS) as *mut PageTable);
+        }
+
+        let table = Self::allocate_table(allocate)?;
+        if *entry & DESCRIPTOR_VALID != 0 {
+            let base = *entry & DESCRIPTOR_ADDRESS;
+            let attributes = *entry & !DESCRIPTOR_ADDRESS;
+            let ty = match level + 1 == LEVELS - 1 {
+                true => DESCRIPTOR_TABLE,
+                false => 0,
+            };
+            let size = entry_size(level + 1) as u64;
+            (*table)
+                .entries
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, e)| *e = (base + i as u64 * size) | attributes | ty);
+        }
+
+        *entry = table as u64 | DESCRIPTOR_TABLE | DESCRIPTOR_VALID;
+        Some(table)
+    }
+}
+
+/// Bits of the physical addresses the core supports, encoded as in `TCR_EL1.IPS`.
+fn physical_address_range() -> u64 {
+    let mmfr0: u64;
+    unsafe { asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0, options(nomem, nostack)) };
+    (mmfr0 & 0xF).min(PA_RANGE_48)
+}
+
+#[inline]
+fn physical_address_bits(range: u64) -> u32 {
+    [32, 36, 40, 42, 44, 48][range as usize]
+}
+
+/// Switches from the translation of the firmware to the identity mapping of the tables.
+///
+/// The upper half has no mapping yet, but gets its own empty tables so that nothing of the
+/// firmware remains reachable.
+pub(crate) fn init(memory_map: &MemoryMap, frame_buffer: &[u8]) {
+    let mut tables = 0;
+    let mut allocate = || {
+        let frame = FRAME_ALLOCATOR.lock_irqsave().allocate()?;
+        tables += 1;
+        Some(frame.address())
+    };
+
+    let range = physical_address_range();
+    let text_end = unsafe { &__text_end as *const u8 as usize };
+    let kernel_end = align_up(unsafe { &super::_end as *const u8 as usize }, FRAME_SIZE);
+    let frame_buffer = (
+        frame_buffer.as_ptr() as usize & !(FRAME_SIZE - 1),
+        align_up(frame_buffer.as_ptr_range().end as usize, FRAME_SIZE),
+    );
+
+    // The devices are wherever the memory is not, so the memory overrides them.
+    let regions = [(0, DEVICE_LIMIT.min(1 << physical_address_bits(range)))]
+        .into_iter()
+        .map(|region| (region, Attributes::DEVICE))
+        .chain(
+            memory_map
+                .iter()
+                .filter(|d| d.attribute & EFI_MEMORY_WB != 0)
+                .map(|d| {
+                    let region = (d.phys_start as usize, d.phys_end() as usize);
+                    (region, Attributes::DATA)
+                }),
+        )
+        .chain([
+            (frame_buffer, Attributes::FRAME_BUFFER),
+            ((KERNEL_ADDRESS as usize, text_end), Attributes::TEXT),
+            ((text_end, kernel_end), Attributes::DATA),
+        ]);
+
+    let Some((lower, upper)) = AddressSpace::new(&mut allocate).and_then(|mut lower| {
+        regions
+            .into_iter()
+            .try_for_each(|((start, end), attributes)| {
+                lower.map(start, start, end - start, attributes, &mut allocate)
+            })?;
+        Some((lower, AddressSpace::new(&mut allocate)?))
+    }) else {
+        log::error!("Paging: failed to build the tables, staying on the firmware's");
+        return;
+    };
+
+    unsafe { switch(lower.root(), upper.root(), range) };
+
+    log::info!(
+        "Paging: {}-bit physical addresses, {} tables, kernel text at {:#x} - {:#x}",
+        physical_address_bits(range),
+        tables,
+        KERNEL_ADDRESS,
+        text_end
+    );
+}
+
+/// # Safety
+/// The tables must map the running code, the stack and everything in use where they are now.
+unsafe fn switch(lower: usize, upper: usize, range: u64) {
+    let mair: u64;
+    asm!("mrs {}, mair_el1", out(reg) mair, options(nomem, nostack));
+    let mair = mair & MAIR_FIRMWARE
+        | MAIR_NORMAL << (MAIR_INDEX_NORMAL * 8)
+        | MAIR_NON_CACHEABLE << (MAIR_INDEX_NON_CACHEABLE * 8)
+        | MAIR_DEVICE_NGNRE << (MAIR_INDEX_DEVICE * 8);
+
+    let tcr = (64 - VA_BITS) << TCR_T0SZ_SHIFT
+        | TCR_IRGN0_WB
+        | TCR_ORGN0_WB
+        | TCR_SH0_INNER
+        | TCR_TG0_4K
+        | (64 - VA_BITS) << TCR_T1SZ_SHIFT
+        | TCR_IRGN1_WB
+        | TCR_ORGN1_WB
+        | TCR_SH1_INNER
+        | TCR_TG1_4K
+        | range << TCR_IPS_SHIFT;
+
+    asm!(
+        // The tables must be written before the walker reads them.
+        "dsb ishst",
+        "msr mair_el1, {mair}",
+        "msr tcr_el1, {tcr}",
+        "msr ttbr0_el1, {lower}",
+        "msr ttbr1_el1, {upper}",
+        "isb",
+        "tlbi vmalle1",
+        "dsb nsh",
+        "isb",
+        mair = in(reg) mair,
+        tcr = in(reg) tcr,
+        lower = in(reg) lower,
+        upper = in(reg) upper,
+        options(nostack),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    const GIB: usize = 1 << 30;
+    const MIB: usize = 1 << 20;
+
+    struct Tables(Vec<Box<PageTable>>);
+
+    impl Tables {
+        fn allocator(&mut self) -> impl FnMut() -> Option<usize> + '_ {
+            || {
+                let mut table = Box::new(PageTable::empty());
+                let address = table.as_mut() as *mut PageTable as usize;
+                self.0.push(table);
+                Some(address)
+            }
+        }
+    }
+
+    #[test]
+    fn map_with_blocks() {
+        let mut tables = Tables(Vec::new());
+        let mut allocate = tables.allocator();
+        let mut space = AddressSpace::new(&mut allocate).unwrap();
+
+        space
+            .map(GIB, GIB, GIB + 2 * MIB, Attributes::DATA, &mut allocate)
+            .unwrap();
+        drop(allocate);
+
+        // The root, a table at level 1 and another at level 2 for the 2 MiB block after the 1 GiB.
+        assert_eq!(3, tables.0.len());
+        assert_eq!(
+            Some((GIB + 0x1234, Attributes::DATA)),
+            space.translate(GIB + 0x1234)
+        );
+        assert_eq!(
+            Some((2 * GIB + MIB, Attributes::DATA)),
+            space.translate(2 * GIB + MIB)
+        );
+        assert_eq!(None, space.translate(2 * GIB + 2 * MIB));
+        assert_eq!(None, space.translate(0));
+    }
+
+    #[test]
+    fn map_with_pages() {
+        let mut tables = Tables(Vec::new());
+        let mut allocate = tables.allocator();
+        let mut space = AddressSpace::new(&mut allocate).unwrap();
+
+        space
+            .map(
+                0x4000_1000,
+                0x8000_1000,
+                0x2000,
+                Attributes::TEXT,
+                &mut allocate,
+            )
+            .unwrap();
+
+        assert_eq!(None, space.translate(0x4000_0fff));
+        assert_eq!(
+            Some((0x8000_2abc, Attributes::TEXT)),
+            space.translate(0x4000_2abc)
+        );
+        assert_eq!(None, space.translate(0x4000_3000));
+    }
+
+    #[test]
+    fn split_blocks_to_override() {
+        let mut tables = Tables(Vec::new());
+        let mut allocate = tables.allocator();
+        let mut space = AddressSpace::new(&mut allocate).unwrap();
+
+        space
+            .map(0, 0, 4 * GIB, Attributes::DEVICE, &mut allocate)
+            .unwrap();
+        space
+            .map(
+                GIB + 0x5000,
+                GIB + 0x5000,
+                0x1000,
+                Attributes::FRAME_BUFFER,
+                &mut allocate,
+            )
+            .unwrap();
+
+        assert_eq!(
+            Some((GIB + 0x5000, Attributes::FRAME_BUFFER)),
+            space.translate(GIB + 0x5000)
+        );
+        assert_eq!(
+            Some((GIB + 0x4fff, Attributes::DEVICE)),
+            space.translate(GIB + 0x4fff)
+        );
+        assert_eq!(
+            Some((GIB + 0x6000, Attributes::DEVICE)),
+            space.translate(GIB + 0x6000)
+        );
+        assert_eq!(
+            Some((2 * GIB - 1, Attributes::DEVICE)),
+            space.translate(2 * GIB - 1)
+        );
+        assert_eq!(
+            Some((3 * GIB, Attributes::DEVICE)),
+            space.translate(3 * GIB)
+        );
+    }
+
+    #[test]
+    fn encode_attributes() {
+        let text = Attributes::TEXT.descriptor();
+        assert_ne!(0, text & DESCRIPTOR_READ_ONLY);
+        assert_eq!(0, text & DESCRIPTOR_PXN);
+
+        let data = Attributes::DATA.descriptor();
+        assert_eq!(0, data & DESCRIPTOR_READ_ONLY);
+        assert_ne!(0, data & DESCRIPTOR_PXN);
+        assert_eq!(
+            DESCRIPTOR_INNER_SHAREABLE,
+            data & DESCRIPTOR_INNER_SHAREABLE
+        );
+
+        [
+            Attributes::TEXT,
+            Attributes::DATA,
+            Attributes::FRAME_BUFFER,
+            Attributes::DEVICE,
+        ]
+        .into_iter()
+        .for_each(|a| {
+            assert_ne!(0, a.descriptor() & DESCRIPTOR_ACCESS);
+            assert_ne!(0, a.descriptor() & DESCRIPTOR_UXN);
+            assert_eq!(Some(a), Attributes::from_descriptor(a.descriptor()));
+        });
+    }
+
+    #[test]
+    fn reject_misaligned() {
+        let mut tables = Tables(Vec::new());
+        let mut allocate = tables.allocator();
+        let mut space = AddressSpace::new(&mut allocate).unwrap();
+
+        assert_eq!(
+            None,
+            space.map(0x800, 0x800, 0x1000, Attributes::DATA, &mut allocate)
+        );
+        assert_eq!(
+            None,
+            space.map(0, 0x1000, 0x1001, Attributes::DATA, &mut allocate)
+        );
+        assert_eq!(
+            None,
+            space.map(1 << VA_BITS, 0, 0x1000, Attributes::DATA, &mut allocate)
+        );
+    }
+}
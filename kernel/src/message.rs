@@ -0,0 +1,65 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fifo::Fifo;
+use crate::task;
+use crate::timer::TimerId;
+
+const CAPACITY: usize = 256;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct KeyEvent {
+    /// Modifier bits, in the layout of a HID boot protocol report.
+    pub(crate) modifiers: u8,
+    /// HID usage ID of the key.
+    pub(crate) usage: u8,
+    pub(crate) pressed: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct MouseEvent {
+    pub(crate) buttons: u8,
+    pub(crate) dx: i32,
+    pub(crate) dy: i32,
+}
+
+/// Position of an absolute pointing device such as a tablet, scaled so that `u16::MAX` is the
+/// right or the bottom edge.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct PointerEvent {
+    pub(crate) buttons: u8,
+    pub(crate) x: u16,
+    pub(crate) y: u16,
+}
+
+/// Event delivered from interrupt handlers to the queues of tasks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Message {
+    Timer(TimerId),
+    Keyboard(KeyEvent),
+    /// The repeat timer of the key held down has expired.
+    KeyRepeat(TimerId),
+    Mouse(MouseEvent),
+    Pointer(PointerEvent),
+    /// The xHCI controller has posted events.
+    Xhci,
+    /// Some virtio devices have used their buffers.
+    Virtio,
+}
+
+/// Queue of the messages sent to a task.
+pub(crate) type Queue = Fifo<Message, CAPACITY>;
+
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends the message to the main task, dropping it if the queue of the task is full.
+pub(crate) fn post(message: Message) {
+    if task::send(task::MAIN, message).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of messages dropped so far because the queue of the main task was full.
+#[inline]
+pub(crate) fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,38 @@
+use core::ptr::{read_volatile, write_volatile};
+
+/// Window of memory-mapped registers starting at a base address.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Mmio {
+    base: usize,
+}
+
+impl Mmio {
+    /// # Safety
+    /// `base` must be the address of device registers which are identity mapped.
+    pub(crate) const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    pub(crate) fn offset(&self, offset: usize) -> Self {
+        Self {
+            base: self.base + offset,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn read<T>(&self, offset: usize) -> T
+    where
+        T: Copy,
+    {
+        unsafe { read_volatile((self.base + offset) as *const T) }
+    }
+
+    #[inline]
+    pub(crate) fn write<T>(&self, offset: usize, value: T)
+    where
+        T: Copy,
+    {
+        unsafe { write_volatile((self.base + offset) as *mut T, value) }
+    }
+}
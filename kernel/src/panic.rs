@@ -0,0 +1,153 @@
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::backtrace::{self, Backtrace};
+use crate::graphics::text::{FONT_HEIGHT, FONT_WIDTH};
+use crate::graphics::{Canvas, Colors, Position, Region};
+use crate::interrupts::{self, IntId, Trigger};
+use crate::{smp, timer, TextWriter};
+
+const BANNER_COLUMNS: usize = 128;
+
+/// SGI stopping the other cores when one panics, above every other interrupt.
+const STOP_SGI: IntId = 0;
+const STOP_PRIORITY: u8 = 0x10;
+
+/// How long to wait for the other cores to stop, as those with IRQs masked never do.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// Number of cores stopped by the panicking one.
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Line of text truncated at a fixed length, as the heap may not be usable while panicking.
+struct Line {
+    buf: [u8; BANNER_COLUMNS],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [0; BANNER_COLUMNS],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        s.chars()
+            .filter(|c| c.is_ascii() && !c.is_ascii_control())
+            .take(BANNER_COLUMNS - self.len)
+            .for_each(|c| {
+                self.buf[self.len] = c as u8;
+                self.len += 1;
+            });
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(test), panic_handler)]
+#[allow(dead_code)]
+fn panic(info: &PanicInfo) -> ! {
+    take_over();
+    draw_banner(info);
+
+    println!("*** KERNEL PANIC ***");
+    println!("{}", info.message());
+    if let Some(location) = info.location() {
+        println!(
+            "  at {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+    backtrace::print(Backtrace::capture());
+
+    park()
+}
+
+/// Draws the message in a red banner at the top of the screen, where it is hardly missed.
+fn draw_banner(info: &PanicInfo) {
+    let mut frame_buffer = crate::FRAME_BUFFER.lock();
+    let Some(frame_buffer) = frame_buffer.as_mut() else {
+        return;
+    };
+
+    let columns = (frame_buffer.width() / FONT_WIDTH).min(BANNER_COLUMNS);
+    frame_buffer.fill_in(
+        Region::new(Position::zero(), frame_buffer.width(), FONT_HEIGHT * 3),
+        Colors::red(),
+    );
+
+    let mut message = Line::new();
+    write!(message, "Kernel panic: {}", info.message()).ok();
+    let mut location = Line::new();
+    if let Some(l) = info.location() {
+        write!(location, "  at {}:{}:{}", l.file(), l.line(), l.column()).ok();
+    }
+
+    [message, location]
+        .iter()
+        .enumerate()
+        .for_each(|(i, line)| {
+            let text = line.as_str();
+            frame_buffer.write_string(
+                (0, i * FONT_HEIGHT).into(),
+                &text[..text.len().min(columns)],
+                Colors::white(),
+            );
+        });
+}
+
+/// Stops the other cores and takes the output over to report a fatal error, or stops the calling
+/// core if another one is already reporting.
+pub(crate) fn take_over() {
+    interrupts::mask();
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        stop(STOP_SGI);
+    }
+
+    // The locks of the output are broken only when nobody else may be using them, unless a core
+    // is stuck with IRQs masked. The core itself may have failed in the middle of printing.
+    stop_others();
+    unsafe { crate::break_output_locks() };
+}
+
+/// Lets the calling core be stopped when another one panics.
+pub(crate) fn init_cpu() {
+    interrupts::register(STOP_SGI, Trigger::Edge, STOP_PRIORITY, stop);
+}
+
+/// Interrupts the other cores to stop them, waiting for them for a while.
+fn stop_others() {
+    interrupts::send_to_others(STOP_SGI);
+
+    let deadline = timer::uptime() + STOP_TIMEOUT;
+    while STOPPED.load(Ordering::Acquire) < smp::online() - 1 && timer::uptime() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Handler of the SGI, which never returns to the interrupted code.
+fn stop(_: IntId) {
+    STOPPED.fetch_add(1, Ordering::Release);
+    park()
+}
+
+/// Stops the calling core for good.
+pub(crate) fn park() -> ! {
+    interrupts::mask();
+    loop {
+        aarch64::instructions::halt();
+    }
+}
@@ -0,0 +1,115 @@
+use super::ConfigSpace;
+
+pub(crate) const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub(crate) const CAP_MSI: u8 = 0x05;
+pub(crate) const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+pub(crate) const CAP_PCI_EXPRESS: u8 = 0x10;
+pub(crate) const CAP_MSIX: u8 = 0x11;
+
+/// Stops following the list after this many entries, in case it loops.
+const MAX_CAPABILITIES: usize = 48;
+
+/// An entry in the capability list of the config space.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Capability {
+    pub(crate) id: u8,
+    pub(crate) offset: u8,
+}
+
+impl Capability {
+    pub(crate) fn name(&self) -> &'static str {
+        match self.id {
+            CAP_POWER_MANAGEMENT => "Power Management",
+            CAP_MSI => "MSI",
+            CAP_VENDOR_SPECIFIC => "Vendor Specific",
+            CAP_PCI_EXPRESS => "PCI Express",
+            CAP_MSIX => "MSI-X",
+            _ => "Unknown",
+        }
+    }
+}
+
+pub(crate) struct Capabilities {
+    config: ConfigSpace,
+    next: u8,
+    count: usize,
+}
+
+impl Capabilities {
+    pub(crate) fn new(config: ConfigSpace, pointer: u8) -> Self {
+        Self {
+            config,
+            next: pointer,
+            count: 0,
+        }
+    }
+}
+
+impl Iterator for Capabilities {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The bottom two bits are reserved, and the list lives after the standard header.
+        let offset = self.next & !0b11;
+        if offset < 0x40 || self.count >= MAX_CAPABILITIES {
+            return None;
+        }
+
+        let header = self.config.read::<u16>(offset as usize);
+        self.next = (header >> 8) as u8;
+        self.count += 1;
+
+        Some(Capability {
+            id: header as u8,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_space(buf: &mut [u32; 1024]) -> ConfigSpace {
+        unsafe { ConfigSpace::new(buf.as_mut_ptr() as usize) }
+    }
+
+    #[test]
+    fn follow_capability_list() {
+        let mut buf = [0u32; 1024];
+        buf[0x40 / 4] = 0x50 << 8 | CAP_POWER_MANAGEMENT as u32;
+        buf[0x50 / 4] = 0x70 << 8 | CAP_MSI as u32;
+        buf[0x70 / 4] = CAP_MSIX as u32;
+
+        let capabilities = Capabilities::new(config_space(&mut buf), 0x40).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Capability {
+                    id: CAP_POWER_MANAGEMENT,
+                    offset: 0x40
+                },
+                Capability {
+                    id: CAP_MSI,
+                    offset: 0x50
+                },
+                Capability {
+                    id: CAP_MSIX,
+                    offset: 0x70
+                },
+            ],
+            capabilities
+        );
+    }
+
+    #[test]
+    fn stop_at_looping_list() {
+        let mut buf = [0u32; 1024];
+        buf[0x40 / 4] = 0x40 << 8 | CAP_VENDOR_SPECIFIC as u32;
+
+        assert_eq!(
+            MAX_CAPABILITIES,
+            Capabilities::new(config_space(&mut buf), 0x40).count()
+        );
+    }
+}
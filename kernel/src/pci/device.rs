@@ -0,0 +1,364 @@
+use core::fmt::{Display, Formatter};
+
+use crate::mmio::Mmio;
+
+use super::capability::Capabilities;
+use super::{Address, BarAllocator};
+
+const VENDOR_ID: usize = 0x00;
+const DEVICE_ID: usize = 0x02;
+const COMMAND: usize = 0x04;
+const STATUS: usize = 0x06;
+const CLASS_CODE: usize = 0x08;
+const HEADER_TYPE: usize = 0x0E;
+const BAR0: usize = 0x10;
+const CAPABILITIES_POINTER: usize = 0x34;
+const INTERRUPT_PIN: usize = 0x3D;
+
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 1 << 7;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_MEMORY_64: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// The 4 KiB config space of a function.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ConfigSpace {
+    registers: Mmio,
+    base: usize,
+}
+
+impl ConfigSpace {
+    /// # Safety
+    /// `base` must be the address of the config space of a function in an ECAM window.
+    pub(crate) unsafe fn new(base: usize) -> Self {
+        Self {
+            registers: Mmio::new(base),
+            base,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Absent functions read as all ones.
+    pub(crate) fn is_present(&self) -> bool {
+        self.read::<u16>(VENDOR_ID) != 0xFFFF
+    }
+
+    #[inline]
+    pub(crate) fn read<T>(&self, offset: usize) -> T
+    where
+        T: Copy,
+    {
+        self.registers.read(offset)
+    }
+
+    #[inline]
+    pub(crate) fn write<T>(&self, offset: usize, value: T)
+    where
+        T: Copy,
+    {
+        self.registers.write(offset, value)
+    }
+}
+
+/// The base class, the subclass and the programming interface.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ClassCode {
+    pub(crate) base: u8,
+    pub(crate) sub: u8,
+    pub(crate) interface: u8,
+}
+
+impl ClassCode {
+    pub(crate) const fn new(base: u8, sub: u8, interface: u8) -> Self {
+        Self {
+            base,
+            sub,
+            interface,
+        }
+    }
+
+    /// Human readable name of the class, as precise as known.
+    pub(crate) fn name(&self) -> &'static str {
+        match (self.base, self.sub, self.interface) {
+            (0x01, 0x00, _) => "SCSI storage controller",
+            (0x01, 0x06, _) => "SATA controller",
+            (0x01, 0x08, _) => "Non-Volatile memory controller",
+            (0x01, _, _) => "Mass storage controller",
+            (0x02, 0x00, _) => "Ethernet controller",
+            (0x02, _, _) => "Network controller",
+            (0x03, _, _) => "Display controller",
+            (0x04, _, _) => "Multimedia controller",
+            (0x06, 0x00, _) => "Host bridge",
+            (0x06, 0x04, _) => "PCI bridge",
+            (0x06, _, _) => "Bridge",
+            (0x07, _, _) => "Communication controller",
+            (0x08, _, _) => "System peripheral",
+            (0x09, _, _) => "Input device controller",
+            (0x0C, 0x03, 0x00) => "USB controller (UHCI)",
+            (0x0C, 0x03, 0x10) => "USB controller (OHCI)",
+            (0x0C, 0x03, 0x20) => "USB controller (EHCI)",
+            (0x0C, 0x03, 0x30) => "USB controller (xHCI)",
+            (0x0C, 0x03, _) => "USB controller",
+            (0x0C, _, _) => "Serial bus controller",
+            (0xFF, _, _) => "Unassigned class",
+            _ => "Unknown class",
+        }
+    }
+}
+
+impl Display for ClassCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}", self.base, self.sub, self.interface)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum BarKind {
+    Io,
+    Memory32,
+    Memory64,
+}
+
+/// A base address register, whose size is zero if not implemented.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Bar {
+    pub(crate) kind: BarKind,
+    pub(crate) address: u64,
+    pub(crate) size: u64,
+    pub(crate) prefetchable: bool,
+}
+
+impl Bar {
+    /// Decodes the register and the one following it, with the values read back after writing
+    /// all ones, which have zeroes in the bits of the size.
+    pub(crate) fn decode(low: u32, high: u32, low_mask: u32, high_mask: u32) -> Self {
+        if low & BAR_IO != 0 {
+            return Self {
+                kind: BarKind::Io,
+                address: (low & !0b11) as u64,
+                size: (!(low_mask & !0b11)).wrapping_add(1) as u64,
+                prefetchable: false,
+            };
+        }
+
+        let prefetchable = low & BAR_PREFETCHABLE != 0;
+        match low & BAR_MEMORY_64 != 0 {
+            true => Self {
+                kind: BarKind::Memory64,
+                address: (high as u64) << 32 | (low & !0xF) as u64,
+                size: (!((high_mask as u64) << 32 | (low_mask & !0xF) as u64)).wrapping_add(1),
+                prefetchable,
+            },
+            false => Self {
+                kind: BarKind::Memory32,
+                address: (low & !0xF) as u64,
+                size: (!(low_mask & !0xF)).wrapping_add(1) as u64,
+                prefetchable,
+            },
+        }
+    }
+}
+
+/// A function on the buses, called a device after the convention of `lspci`.
+#[derive(Debug)]
+pub(crate) struct Device {
+    pub(crate) address: Address,
+    pub(crate) vendor_id: u16,
+    pub(crate) device_id: u16,
+    pub(crate) class: ClassCode,
+    pub(crate) header_type: u8,
+    config: ConfigSpace,
+    bars: [Option<Bar>; 6],
+}
+
+impl Device {
+    pub(crate) fn new(address: Address, config: ConfigSpace) -> Self {
+        let class = config.read::<u32>(CLASS_CODE);
+
+        Self {
+            address,
+            vendor_id: config.read(VENDOR_ID),
+            device_id: config.read(DEVICE_ID),
+            class: ClassCode::new((class >> 24) as u8, (class >> 16) as u8, (class >> 8) as u8),
+            header_type: config.read(HEADER_TYPE),
+            config,
+            bars: [None; 6],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn config(&self) -> &ConfigSpace {
+        &self.config
+    }
+
+    #[inline]
+    pub(crate) fn is_multi_function(&self) -> bool {
+        self.header_type & HEADER_TYPE_MULTI_FUNCTION != 0
+    }
+
+    /// Bridges have only two BARs, followed by the bus numbers.
+    fn bar_count(&self) -> usize {
+        match self.header_type & !HEADER_TYPE_MULTI_FUNCTION {
+            HEADER_TYPE_BRIDGE => 2,
+            _ => 6,
+        }
+    }
+
+    /// Decoded BARs with their indices, where a 64-bit one takes two.
+    pub(crate) fn bars(&self) -> impl Iterator<Item = (usize, Bar)> + '_ {
+        self.bars
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bar)| Some((i, (*bar)?)))
+    }
+
+    pub(crate) fn bar(&self, index: usize) -> Option<Bar> {
+        *self.bars.get(index)?
+    }
+
+    /// Sizes the BARs by writing all ones, with decoding disabled meanwhile.
+    pub(crate) fn probe_bars(&mut self) {
+        let command = self.config.read::<u16>(COMMAND);
+        self.config.write(COMMAND, command & !COMMAND_MEMORY_SPACE);
+
+        let mut i = 0;
+        while i < self.bar_count() {
+            let low = self.read_bar_and_mask(i);
+            let is_64 = low.0 & (BAR_IO | BAR_MEMORY_64) == BAR_MEMORY_64;
+            let high = match is_64 && i + 1 < self.bar_count() {
+                true => self.read_bar_and_mask(i + 1),
+                false => (0, 0),
+            };
+
+            self.bars[i] = Some(Bar::decode(low.0, high.0, low.1, high.1));
+            i += if is_64 { 2 } else { 1 };
+        }
+
+        self.config.write(COMMAND, command);
+    }
+
+    fn read_bar_and_mask(&self, index: usize) -> (u32, u32) {
+        let offset = BAR0 + index * 4;
+        let value = self.config.read::<u32>(offset);
+        self.config.write(offset, u32::MAX);
+        let mask = self.config.read::<u32>(offset);
+        self.config.write(offset, value);
+        (value, mask)
+    }
+
+    /// Assigns addresses to the memory BARs left unassigned by the firmware, then enables memory
+    /// decoding. I/O BARs are ignored since no port I/O is used on aarch64.
+    pub(crate) fn assign_bars(&mut self, allocator: &mut BarAllocator) {
+        (0..self.bars.len()).for_each(|i| {
+            let Some(bar) = self.bars[i].as_mut() else {
+                return;
+            };
+            if bar.kind == BarKind::Io || bar.size == 0 || bar.address != 0 {
+                return;
+            }
+
+            let Some(address) = allocator.allocate(bar.size as usize) else {
+                log::warn!(
+                    "PCI: {} BAR{}: no space for {} bytes",
+                    self.address,
+                    i,
+                    bar.size
+                );
+                return;
+            };
+
+            bar.address = address as u64;
+            let offset = BAR0 + i * 4;
+            let flags = self.config.read::<u32>(offset) & 0xF;
+            self.config.write(offset, address as u32 | flags);
+            if bar.kind == BarKind::Memory64 {
+                self.config.write(offset + 4, (address as u64 >> 32) as u32);
+            }
+        });
+
+        if self
+            .bars()
+            .any(|(_, bar)| bar.kind != BarKind::Io && bar.size > 0)
+        {
+            let command = self.config.read::<u16>(COMMAND);
+            self.config.write(COMMAND, command | COMMAND_MEMORY_SPACE);
+        }
+    }
+
+    /// Lets the function access memory by DMA, and send MSIs.
+    pub(crate) fn enable_bus_master(&self) {
+        let command = self.config.read::<u16>(COMMAND);
+        self.config.write(COMMAND, command | COMMAND_BUS_MASTER);
+    }
+
+    /// The legacy interrupt pin from INTA (1) to INTD (4), or zero if none.
+    pub(crate) fn interrupt_pin(&self) -> u8 {
+        self.config.read(INTERRUPT_PIN)
+    }
+
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        let pointer = match self.config.read::<u16>(STATUS) & STATUS_CAPABILITIES {
+            0 => 0,
+            _ => self.config.read::<u8>(CAPABILITIES_POINTER),
+        };
+        Capabilities::new(self.config, pointer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_memory32_bar() {
+        assert_eq!(
+            Bar {
+                kind: BarKind::Memory32,
+                address: 0x1000_0000,
+                size: 0x4000,
+                prefetchable: false,
+            },
+            Bar::decode(0x1000_0000, 0, 0xFFFF_C000, 0)
+        );
+    }
+
+    #[test]
+    fn decode_memory64_bar() {
+        assert_eq!(
+            Bar {
+                kind: BarKind::Memory64,
+                address: 0x80_0000_0000,
+                size: 0x1_0000_0000,
+                prefetchable: true,
+            },
+            Bar::decode(0x0000_000C, 0x80, 0x0000_000C, 0xFFFF_FFFF)
+        );
+    }
+
+    #[test]
+    fn decode_io_bar() {
+        assert_eq!(
+            Bar {
+                kind: BarKind::Io,
+                address: 0xC000,
+                size: 0x20,
+                prefetchable: false,
+            },
+            Bar::decode(0xC001, 0, 0xFFFF_FFE1, 0)
+        );
+    }
+
+    #[test]
+    fn decode_unimplemented_bar() {
+        assert_eq!(0, Bar::decode(0, 0, 0, 0).size);
+    }
+}
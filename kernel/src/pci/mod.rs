@@ -0,0 +1,337 @@
+//! PCI Express functions behind the host bridge, accessed through ECAM.
+
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use crate::interrupts::IntId;
+use crate::sync::Once;
+
+pub(crate) mod capability;
+pub(crate) mod device;
+
+pub(crate) use device::{BarKind, ClassCode, ConfigSpace, Device};
+
+/// 32-bit memory space of the host bridge on QEMU's `virt` board, from which BARs are assigned.
+const VIRT_MEMORY_WINDOW: (usize, usize) = (0x1000_0000, 0x2EFF_0000);
+
+/// INTID of INTA on QEMU's `virt` board, followed by INTB to INTD.
+const VIRT_INTX_BASE: IntId = 35;
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+/// Functions found by `init`, which are only read afterwards.
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// The ECAM window of a PCIe host bridge, which maps the config space of the buses in the range.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct EcamWindow {
+    /// Address of the config space of `bus_start`.
+    pub(crate) base: usize,
+    pub(crate) size: usize,
+    pub(crate) bus_start: u8,
+    pub(crate) bus_end: u8,
+}
+
+impl EcamWindow {
+    /// Config space of the function, or `None` if the bus is not in the window.
+    pub(crate) fn config_space(&self, address: Address) -> Option<ConfigSpace> {
+        if !(self.bus_start..=self.bus_end).contains(&address.bus) {
+            return None;
+        }
+
+        let offset = ((address.bus - self.bus_start) as usize) << 20
+            | (address.device as usize) << 15
+            | (address.function as usize) << 12;
+
+        (offset < self.size).then(|| unsafe { ConfigSpace::new(self.base + offset) })
+    }
+}
+
+/// Location of a function on the buses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct Address {
+    pub(crate) bus: u8,
+    pub(crate) device: u8,
+    pub(crate) function: u8,
+}
+
+impl Address {
+    pub(crate) fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// Hands out bus addresses for BARs from a window, aligning each of them to its own size and
+/// keeping clear of the ranges reserved, e.g. by the BARs the firmware has assigned.
+pub(crate) struct BarAllocator {
+    next: usize,
+    end: usize,
+    /// Start and end of the reserved ranges.
+    reserved: Vec<(usize, usize)>,
+}
+
+impl BarAllocator {
+    pub(crate) fn new(base: usize, size: usize) -> Self {
+        Self {
+            next: base,
+            end: base + size,
+            reserved: Vec::new(),
+        }
+    }
+
+    /// Keeps the range from being handed out.
+    pub(crate) fn reserve(&mut self, address: usize, size: usize) {
+        self.reserved.push((address, address + size));
+    }
+
+    pub(crate) fn allocate(&mut self, size: usize) -> Option<usize> {
+        loop {
+            let address = self.next.checked_next_multiple_of(size)?;
+            let end = address.checked_add(size)?;
+            if end > self.end {
+                return None;
+            }
+
+            match self
+                .reserved
+                .iter()
+                .find(|&&(start, stop)| start < end && address < stop)
+            {
+                Some(&(_, stop)) => self.next = stop,
+                None => {
+                    self.next = end;
+                    return Some(address);
+                }
+            }
+        }
+    }
+}
+
+/// Finds every function in the window by brute force, since ECAM maps all buses anyway.
+pub(crate) fn scan(ecam: &EcamWindow) -> Vec<Device> {
+    let mut devices = Vec::new();
+
+    (ecam.bus_start..=ecam.bus_end).for_each(|bus| {
+        (0..DEVICES_PER_BUS).for_each(|device| {
+            let Some(first) = probe(ecam, Address::new(bus, device, 0)) else {
+                return;
+            };
+
+            let functions = match first.is_multi_function() {
+                true => FUNCTIONS_PER_DEVICE,
+                false => 1,
+            };
+
+            devices.push(first);
+            devices.extend(
+                (1..functions)
+                    .filter_map(|function| probe(ecam, Address::new(bus, device, function))),
+            );
+        })
+    });
+
+    devices
+}
+
+fn probe(ecam: &EcamWindow, address: Address) -> Option<Device> {
+    let config = ecam.config_space(address)?;
+    config.is_present().then(|| Device::new(address, config))
+}
+
+/// Enumerates the functions, sizing their BARs and assigning those which the firmware left.
+pub(crate) fn init(ecam: EcamWindow, memory_window: Option<(usize, usize)>) {
+    let (base, size) = memory_window.unwrap_or(VIRT_MEMORY_WINDOW);
+    let mut allocator = BarAllocator::new(base, size);
+
+    let mut devices = scan(&ecam);
+    devices.iter_mut().for_each(|device| device.probe_bars());
+
+    // The BARs the firmware has assigned stay where they are, so the others go around them.
+    devices
+        .iter()
+        .flat_map(|device| device.bars())
+        .filter(|(_, bar)| bar.kind != BarKind::Io && bar.size > 0 && bar.address != 0)
+        .for_each(|(_, bar)| allocator.reserve(bar.address as usize, bar.size as usize));
+    devices
+        .iter_mut()
+        .for_each(|device| device.assign_bars(&mut allocator));
+
+    log::info!(
+        "PCI: {} functions on buses {:02x}-{:02x}, ECAM at {:#x}",
+        devices.len(),
+        ecam.bus_start,
+        ecam.bus_end,
+        ecam.base
+    );
+
+    DEVICES.set(devices).ok();
+}
+
+pub(crate) fn devices() -> &'static [Device] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Finds the first function of the class.
+pub(crate) fn find_by_class(base: u8, sub: u8, interface: u8) -> Option<&'static Device> {
+    devices()
+        .iter()
+        .find(|d| d.class == ClassCode::new(base, sub, interface))
+}
+
+/// INTID of the legacy interrupt of the function, swizzled by the device number as the
+/// `interrupt-map` of QEMU's `virt` board does.
+pub(crate) fn legacy_intid(device: &Device) -> Option<IntId> {
+    let pin = device.interrupt_pin();
+    if !(1..=4).contains(&pin) {
+        return None;
+    }
+
+    Some(VIRT_INTX_BASE + (device.address.device as IntId + pin as IntId - 1) % 4)
+}
+
+/// Prints the functions like `lspci`.
+pub(crate) fn print_devices() {
+    devices().iter().for_each(|device| {
+        println!(
+            "{} {:04x}:{:04x} [{}] {}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.class.name()
+        );
+
+        device
+            .bars()
+            .filter(|(_, bar)| bar.size > 0)
+            .for_each(|(i, bar)| {
+                println!(
+                    "        BAR{}: {:?} at {:#x} ({} KiB)",
+                    i,
+                    bar.kind,
+                    bar.address,
+                    bar.size / 1024
+                )
+            });
+
+        device.capabilities().for_each(|capability| {
+            println!(
+                "        Capability {:#04x} at {:#04x}: {}",
+                capability.id,
+                capability.offset,
+                capability.name()
+            )
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake config space of a single bus, in which no functions respond until put.
+    fn fake_bus() -> Vec<u32> {
+        vec![u32::MAX; (1 << 20) / 4]
+    }
+
+    fn window_of(buf: &mut [u32]) -> EcamWindow {
+        EcamWindow {
+            base: buf.as_mut_ptr() as usize,
+            size: 1 << 20,
+            bus_start: 0,
+            bus_end: 0,
+        }
+    }
+
+    fn put_function(buf: &mut [u32], address: Address, id: u32, class: u32, header_type: u8) {
+        let base = ((address.device as usize) << 15 | (address.function as usize) << 12) / 4;
+        buf[base] = id;
+        buf[base + 2] = class << 8;
+        buf[base + 3] = (header_type as u32) << 16;
+        buf[base + 1] = 0;
+        buf[base + 13] = 0;
+    }
+
+    #[test]
+    fn config_space_of_bus_in_window() {
+        let ecam = EcamWindow {
+            base: 0x4010_0000,
+            size: 0x10_0000 * 2,
+            bus_start: 1,
+            bus_end: 2,
+        };
+
+        assert!(ecam.config_space(Address::new(0, 0, 0)).is_none());
+        assert!(ecam.config_space(Address::new(3, 0, 0)).is_none());
+        assert_eq!(
+            0x4020_0000 | 3 << 15 | 2 << 12,
+            ecam.config_space(Address::new(2, 3, 2)).unwrap().base()
+        );
+    }
+
+    #[test]
+    fn scan_functions() {
+        let mut buf = fake_bus();
+        put_function(&mut buf, Address::new(0, 0, 0), 0x0008_1B36, 0x06_00_00, 0);
+        put_function(
+            &mut buf,
+            Address::new(0, 2, 0),
+            0x000D_1B36,
+            0x0C_03_30,
+            0x80,
+        );
+        put_function(&mut buf, Address::new(0, 2, 3), 0x1000_1AF4, 0x02_00_00, 0);
+        // Ignored since function 0 of the device is not multi-function.
+        put_function(&mut buf, Address::new(0, 4, 0), 0x1000_1AF4, 0x02_00_00, 0);
+        put_function(&mut buf, Address::new(0, 4, 1), 0x1000_1AF4, 0x02_00_00, 0);
+
+        let devices = scan(&window_of(&mut buf));
+
+        assert_eq!(
+            vec![
+                Address::new(0, 0, 0),
+                Address::new(0, 2, 0),
+                Address::new(0, 2, 3),
+                Address::new(0, 4, 0),
+            ],
+            devices.iter().map(|d| d.address).collect::<Vec<_>>()
+        );
+        assert_eq!(0x1B36, devices[1].vendor_id);
+        assert_eq!(0x000D, devices[1].device_id);
+        assert_eq!(ClassCode::new(0x0C, 0x03, 0x30), devices[1].class);
+    }
+
+    #[test]
+    fn allocate_aligned_bars() {
+        let mut allocator = BarAllocator::new(0x1000_0000, 0x10_0000);
+
+        assert_eq!(Some(0x1000_0000), allocator.allocate(0x1000));
+        assert_eq!(Some(0x1000_4000), allocator.allocate(0x4000));
+        assert_eq!(Some(0x1000_8000), allocator.allocate(0x100));
+        assert_eq!(None, allocator.allocate(0x10_0000));
+        assert_eq!(Some(0x1008_0000), allocator.allocate(0x8_0000));
+    }
+
+    #[test]
+    fn allocate_around_assigned_bars() {
+        let mut allocator = BarAllocator::new(0x1000_0000, 0x10_0000);
+        allocator.reserve(0x1000_0000, 0x1000);
+        allocator.reserve(0x1000_4000, 0x4000);
+
+        assert_eq!(Some(0x1000_1000), allocator.allocate(0x1000));
+        assert_eq!(Some(0x1000_2000), allocator.allocate(0x2000));
+        assert_eq!(Some(0x1000_8000), allocator.allocate(0x1000));
+        assert_eq!(None, allocator.allocate(0x10_0000));
+    }
+}
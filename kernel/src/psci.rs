@@ -0,0 +1,122 @@
+//! Power State Coordination Interface, through which the firmware turns cores on.
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+
+const PSCI_VERSION: u32 = 0x8400_0000;
+const CPU_ON: u32 = 0xC400_0003;
+
+#[cfg(not(target_arch = "aarch64"))]
+const NOT_SUPPORTED: i32 = -1;
+
+/// Instruction calling the firmware, as described by the firmware tables.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Conduit {
+    Hvc,
+    Smc,
+}
+
+impl Conduit {
+    /// Parses `method` of the device tree node.
+    pub(crate) fn from_method(method: &str) -> Option<Self> {
+        match method {
+            "hvc" => Some(Self::Hvc),
+            "smc" => Some(Self::Smc),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by the functions, as defined in the specification.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Error {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    NotPresent,
+    Disabled,
+    InvalidAddress,
+    Other(i32),
+}
+
+impl From<i32> for Error {
+    fn from(value: i32) -> Self {
+        match value {
+            -1 => Self::NotSupported,
+            -2 => Self::InvalidParameters,
+            -3 => Self::Denied,
+            -4 => Self::AlreadyOn,
+            -5 => Self::OnPending,
+            -6 => Self::InternalFailure,
+            -7 => Self::NotPresent,
+            -8 => Self::Disabled,
+            -9 => Self::InvalidAddress,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Psci {
+    #[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+    conduit: Conduit,
+}
+
+impl Psci {
+    #[inline]
+    pub(crate) const fn new(conduit: Conduit) -> Self {
+        Self { conduit }
+    }
+
+    /// Major and minor version of the implementation.
+    pub(crate) fn version(&self) -> (u16, u16) {
+        let version = self.call(PSCI_VERSION, 0, 0, 0) as u32;
+        ((version >> 16) as u16, version as u16)
+    }
+
+    /// Starts the core at the entry point with the MMU off, passing the context ID in `x0`.
+    pub(crate) fn cpu_on(&self, mpidr: u64, entry: usize, context_id: u64) -> Result<(), Error> {
+        match self.call(CPU_ON, mpidr, entry as u64, context_id) as i32 {
+            0 => Ok(()),
+            error => Err(error.into()),
+        }
+    }
+
+    /// Calls the function following the SMC Calling Convention, which may clobber up to `x17`.
+    #[cfg(target_arch = "aarch64")]
+    fn call(&self, function: u32, a1: u64, a2: u64, a3: u64) -> u64 {
+        let result: u64;
+        unsafe {
+            match self.conduit {
+                Conduit::Hvc => asm!(
+                    "hvc #0",
+                    inlateout("x0") function as u64 => result,
+                    in("x1") a1,
+                    in("x2") a2,
+                    in("x3") a3,
+                    clobber_abi("C"),
+                    options(nostack),
+                ),
+                Conduit::Smc => asm!(
+                    "smc #0",
+                    inlateout("x0") function as u64 => result,
+                    in("x1") a1,
+                    in("x2") a2,
+                    in("x3") a3,
+                    clobber_abi("C"),
+                    options(nostack),
+                ),
+            }
+        }
+        result
+    }
+
+    /// No firmware to call on other architectures, where only the tests are built.
+    #[cfg(not(target_arch = "aarch64"))]
+    fn call(&self, _function: u32, _a1: u64, _a2: u64, _a3: u64) -> u64 {
+        NOT_SUPPORTED as u64
+    }
+}
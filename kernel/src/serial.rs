@@ -0,0 +1,82 @@
+use core::fmt::Write;
+
+use crate::mmio::Mmio;
+
+/// Address of the PL011 UART on QEMU's `-machine virt` board.
+pub(crate) const VIRT_UART: usize = 0x0900_0000;
+
+/// Frequency of the reference clock fed to the UART on QEMU's `virt` board.
+const CLOCK_HZ: u32 = 24_000_000;
+const BAUD_RATE: u32 = 115_200;
+
+const UARTDR: usize = 0x00;
+const UARTFR: usize = 0x18;
+const UARTIBRD: usize = 0x24;
+const UARTFBRD: usize = 0x28;
+const UARTLCR_H: usize = 0x2C;
+const UARTCR: usize = 0x30;
+const UARTIMSC: usize = 0x38;
+const UARTICR: usize = 0x44;
+
+const FR_BUSY: u32 = 1 << 3;
+const FR_TXFF: u32 = 1 << 5;
+const LCR_H_FEN: u32 = 1 << 4;
+const LCR_H_WLEN_8: u32 = 0b11 << 5;
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+/// Driver of the ARM PrimeCell PL011 UART.
+pub(crate) struct Pl011 {
+    registers: Mmio,
+}
+
+impl Pl011 {
+    /// # Safety
+    /// `base` must be the address of a PL011 UART which is identity mapped.
+    pub(crate) unsafe fn new(base: usize) -> Self {
+        Self {
+            registers: Mmio::new(base),
+        }
+    }
+
+    /// Configures the UART for 115200 baud 8N1 with FIFOs, masking all of its interrupts.
+    pub(crate) fn init(&mut self) {
+        self.registers.write::<u32>(UARTCR, 0);
+        while self.registers.read::<u32>(UARTFR) & FR_BUSY != 0 {
+            core::hint::spin_loop();
+        }
+
+        // The divisor is a fixed-point number with 6 fractional bits: CLOCK / (16 * BAUD).
+        let divisor = (CLOCK_HZ * 4 + BAUD_RATE / 2) / BAUD_RATE;
+        self.registers.write::<u32>(UARTIBRD, divisor >> 6);
+        self.registers.write::<u32>(UARTFBRD, divisor & 0x3F);
+        self.registers
+            .write::<u32>(UARTLCR_H, LCR_H_WLEN_8 | LCR_H_FEN);
+
+        self.registers.write::<u32>(UARTIMSC, 0);
+        self.registers.write::<u32>(UARTICR, 0x7FF);
+        self.registers
+            .write::<u32>(UARTCR, CR_UARTEN | CR_TXE | CR_RXE);
+    }
+
+    pub(crate) fn write_byte(&mut self, byte: u8) {
+        while self.registers.read::<u32>(UARTFR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.registers.write::<u32>(UARTDR, byte as u32);
+    }
+}
+
+impl Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        s.bytes().for_each(|b| {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        });
+
+        Ok(())
+    }
+}
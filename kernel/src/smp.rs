@@ -0,0 +1,206 @@
+//! Bring-up of the secondary cores through PSCI, each with its own per-CPU data and stack.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::arch::{asm, global_asm};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::psci::{Conduit, Psci};
+use crate::{exceptions, interrupts, panic, task, timer};
+
+/// ID of the core running `kernel_main`.
+pub(crate) const BOOT_CPU: usize = 0;
+
+/// How long to wait for a core to come up after turning it on.
+const BOOT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Size of a line of the data cache to clean, which is the smallest on the known cores.
+const CACHE_LINE_SIZE: usize = 64;
+
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// ID to give the next core started, which is not taken back from those failing to come up.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(BOOT_CPU + 1);
+
+/// Data of each core, which `TPIDR_EL1` points to.
+#[derive(Debug)]
+pub(crate) struct PerCpu {
+    pub(crate) id: usize,
+    online: AtomicBool,
+}
+
+/// What a secondary core loads before turning its MMU on, to share the translation and the
+/// system registers of the boot core. It must be kept in sync with the assembly below.
+#[repr(C)]
+struct BootArgs {
+    mair: u64,
+    tcr: u64,
+    ttbr0: u64,
+    ttbr1: u64,
+    sctlr: u64,
+    cpacr: u64,
+    stack_top: u64,
+    cpu: u64,
+}
+
+// The MMU is off until `SCTLR_EL1` is loaded, when the addresses become virtual. As the firmware
+// left the memory identity mapped, the arguments and the code are found at the same addresses.
+global_asm!(
+    r#"
+.global __secondary_entry
+__secondary_entry:
+    ldp x1, x2, [x0, #16 * 0]
+    msr mair_el1, x1
+    msr tcr_el1, x2
+    ldp x1, x2, [x0, #16 * 1]
+    msr ttbr0_el1, x1
+    msr ttbr1_el1, x2
+    ldp x1, x2, [x0, #16 * 2]
+    msr cpacr_el1, x2
+    isb
+    tlbi vmalle1
+    dsb nsh
+    isb
+    msr sctlr_el1, x1
+    isb
+
+    ldp x1, x2, [x0, #16 * 3]
+    mov sp, x1
+    msr tpidr_el1, x2
+    mov x0, x2
+    mov x29, xzr
+    mov x30, xzr
+    b {main}
+"#,
+    main = sym secondary_main,
+);
+
+extern "C" {
+    fn __secondary_entry();
+}
+
+macro_rules! read_register {
+    ($name: literal) => {{
+        let value: u64;
+        unsafe { asm!(concat!("mrs {}, ", $name), out(reg) value, options(nomem, nostack)) };
+        value
+    }};
+}
+
+/// Affinity fields of `MPIDR_EL1`, which is how the firmware tables identify the core.
+#[inline]
+fn mpidr() -> u64 {
+    read_register!("mpidr_el1") & 0xFF_00FF_FFFF
+}
+
+/// Per-CPU data of the calling core, or `None` until it is set up.
+#[inline]
+pub(crate) fn this() -> Option<&'static PerCpu> {
+    let tpidr = read_register!("tpidr_el1");
+    unsafe { (tpidr as *const PerCpu).as_ref() }
+}
+
+/// ID of the calling core, which is `BOOT_CPU` until the per-CPU data is set up.
+#[inline]
+pub(crate) fn id() -> usize {
+    this().map_or(BOOT_CPU, |cpu| cpu.id)
+}
+
+/// Number of cores running the kernel.
+#[inline]
+pub(crate) fn online() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Sets up the per-CPU data of the boot core, before anything else reads `TPIDR_EL1` which the
+/// firmware may have left anything in.
+pub(crate) fn init() {
+    let cpu = Box::leak(Box::new(PerCpu {
+        id: BOOT_CPU,
+        online: AtomicBool::new(true),
+    }));
+
+    unsafe { asm!("msr tpidr_el1, {}", in(reg) cpu as *const PerCpu, options(nostack)) };
+}
+
+/// Turns on the cores of the MPIDRs but the calling one, waiting for each to come up.
+pub(crate) fn start_secondaries<I>(conduit: Conduit, cpus: I)
+where
+    I: IntoIterator<Item = u64>,
+{
+    let psci = Psci::new(conduit);
+    let (major, minor) = psci.version();
+    log::info!("PSCI {}.{} via {:?}", major, minor, conduit);
+
+    let boot = mpidr();
+    cpus.into_iter()
+        .filter(|&mpidr| mpidr != boot)
+        .for_each(|mpidr| start(&psci, NEXT_ID.fetch_add(1, Ordering::Relaxed), mpidr));
+
+    log::info!("SMP: {} cores online", online());
+}
+
+fn start(psci: &Psci, id: usize, mpidr: u64) {
+    let cpu = Box::leak(Box::new(PerCpu {
+        id,
+        online: AtomicBool::new(false),
+    }));
+    // The stack becomes the one of the idle task of the core, which never exits.
+    let stack = vec![0u128; task::STACK_SIZE / 16].leak();
+    let args = Box::leak(Box::new(BootArgs {
+        mair: read_register!("mair_el1"),
+        tcr: read_register!("tcr_el1"),
+        ttbr0: read_register!("ttbr0_el1"),
+        ttbr1: read_register!("ttbr1_el1"),
+        sctlr: read_register!("sctlr_el1"),
+        cpacr: read_register!("cpacr_el1"),
+        stack_top: stack.as_ptr_range().end as u64,
+        cpu: cpu as *const PerCpu as u64,
+    }));
+    // The core reads the arguments with its caches off, so they must reach the memory.
+    clean_data_cache(args as *const BootArgs as usize, size_of::<BootArgs>());
+
+    let entry = __secondary_entry as *const () as usize;
+    if let Err(error) = psci.cpu_on(mpidr, entry, args as *const BootArgs as u64) {
+        log::warn!("CPU {:#x} failed to start: {:?}", mpidr, error);
+        return;
+    }
+
+    let deadline = timer::uptime() + BOOT_TIMEOUT;
+    while !cpu.online.load(Ordering::Acquire) {
+        if timer::uptime() > deadline {
+            log::warn!("CPU {:#x} did not come up in {:?}", mpidr, BOOT_TIMEOUT);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+    log::info!("CPU {} is online (MPIDR {:#x})", id, mpidr);
+}
+
+/// Cleans the data cache of the range to the point of coherency.
+fn clean_data_cache(address: usize, len: usize) {
+    let start = address & !(CACHE_LINE_SIZE - 1);
+    (start..address + len)
+        .step_by(CACHE_LINE_SIZE)
+        .for_each(|line| unsafe { asm!("dc cvac, {}", in(reg) line, options(nostack)) });
+    unsafe { asm!("dsb sy", options(nostack)) };
+}
+
+/// Where the secondary cores start in Rust, on their own stacks with the MMU on.
+extern "C" fn secondary_main(cpu: &'static PerCpu) -> ! {
+    exceptions::init();
+    interrupts::init_cpu();
+    panic::init_cpu();
+    timer::init_cpu();
+    task::init_cpu();
+    cpu.online.store(true, Ordering::Release);
+
+    // Running as the idle task of the core from now on.
+    interrupts::unmask();
+    loop {
+        aarch64::instructions::halt();
+    }
+}
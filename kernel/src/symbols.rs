@@ -0,0 +1,22 @@
+use mikan_core::symbols::{Symbol, SymbolTable};
+
+/// Space reserved for the symbol table, filled by `symtab` after linking. Enough for the debug
+/// build, while `symtab` drops the longest names of whatever does not fit.
+const SYMBOLS_SIZE: usize = 1024 * 1024;
+
+#[used]
+#[link_section = ".symbols"]
+static SYMBOLS: [u8; SYMBOLS_SIZE] = [0; SYMBOLS_SIZE];
+
+/// Returns the embedded symbol table, or `None` if the post-link step did not run.
+pub(crate) fn table() -> Option<SymbolTable<'static>> {
+    // The contents are rewritten after compilation, so prevent the compiler from assuming zeroes.
+    let ptr = core::hint::black_box(SYMBOLS.as_ptr());
+    SymbolTable::parse(unsafe { core::slice::from_raw_parts(ptr, SYMBOLS_SIZE) })
+}
+
+/// Finds the function containing the address and the offset from its start.
+pub(crate) fn resolve(address: usize) -> Option<(Symbol<'static>, usize)> {
+    let symbol = table()?.lookup(address as u64)?;
+    Some((symbol, address - symbol.address as usize))
+}
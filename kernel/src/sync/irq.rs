@@ -0,0 +1,59 @@
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+use crate::interrupts;
+
+/// Guard of a lock taken with IRQs masked on the calling core, which restores them once released.
+///
+/// Data shared with interrupt handlers must be locked this way, or a handler interrupting the
+/// holder would spin on the lock forever.
+pub(crate) struct IrqGuard<G> {
+    guard: ManuallyDrop<G>,
+    masked: bool,
+}
+
+impl<G> IrqGuard<G> {
+    /// Masks IRQs, then takes the lock with the closure.
+    pub(crate) fn new<F>(lock: F) -> Self
+    where
+        F: FnOnce() -> G,
+    {
+        let masked = interrupts::are_masked();
+        interrupts::mask();
+
+        Self {
+            guard: ManuallyDrop::new(lock()),
+            masked,
+        }
+    }
+}
+
+impl<G> Deref for IrqGuard<G>
+where
+    G: Deref,
+{
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G> DerefMut for IrqGuard<G>
+where
+    G: DerefMut,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for IrqGuard<G> {
+    fn drop(&mut self) {
+        // The lock must be released before IRQs come back.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if !self.masked {
+            interrupts::unmask();
+        }
+    }
+}
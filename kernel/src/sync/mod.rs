@@ -0,0 +1,13 @@
+//! Primitives to share data between cores and tasks.
+
+mod irq;
+mod mutex;
+mod once;
+mod spin;
+mod ticket;
+
+pub(crate) use irq::IrqGuard;
+pub(crate) use mutex::Mutex;
+pub(crate) use once::Once;
+pub(crate) use spin::SpinLock;
+pub(crate) use ticket::TicketLock;
@@ -0,0 +1,97 @@
+use alloc::collections::VecDeque;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::SpinLock;
+use crate::task::{self, TaskId};
+
+/// Lock putting the tasks waiting for it to sleep, for data held long enough that spinning would
+/// waste the core.
+///
+/// It must not be taken from interrupt handlers, which cannot sleep. Until the scheduler starts,
+/// the waiters spin instead.
+pub(crate) struct Mutex<T> {
+    locked: AtomicBool,
+    /// Tasks sleeping until the holder releases the lock.
+    waiters: SpinLock<VecDeque<TaskId>>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: SpinLock::new(VecDeque::new()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+
+            // Checked again with the scheduler locked, so that the holder cannot release the lock
+            // and wake nobody before this task is asleep.
+            task::sleep_if(|id| {
+                let mut waiters = self.waiters.lock();
+                let locked = self.locked.load(Ordering::Relaxed);
+                if locked {
+                    waiters.push_back(id);
+                }
+                locked
+            });
+            spin_loop();
+        }
+    }
+
+    pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    fn unlock(&self) {
+        // IRQs are masked while the waiters are locked, or a task preempting this one on the core
+        // would spin on them with the scheduler locked.
+        let next = {
+            let mut waiters = self.waiters.lock_irqsave();
+            self.locked.store(false, Ordering::Release);
+            waiters.pop_front()
+        };
+
+        if let Some(id) = next {
+            task::wake(id);
+        }
+    }
+}
+
+pub(crate) struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
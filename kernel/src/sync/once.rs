@@ -0,0 +1,124 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// Value initialized at most once, then read without locking from any core.
+pub(crate) struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T> Sync for Once<T> where T: Send + Sync {}
+
+impl<T> Once<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, or `None` until initialized.
+    #[inline]
+    pub(crate) fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            COMPLETE => Some(unsafe { (*self.value.get()).assume_init_ref() }),
+            _ => None,
+        }
+    }
+
+    /// Returns the value, initializing it with the closure first if nobody has. Callers racing
+    /// with the initialization wait for it to complete.
+    pub(crate) fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        match self
+            .state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                unsafe { (*self.value.get()).write(f()) };
+                self.state.store(COMPLETE, Ordering::Release);
+            }
+            Err(_) => {
+                while self.state.load(Ordering::Acquire) != COMPLETE {
+                    spin_loop();
+                }
+            }
+        }
+
+        self.get().unwrap()
+    }
+
+    /// Initializes with the value, or gives it back if already initialized.
+    pub(crate) fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initialize_once() {
+        let once = Once::new();
+        assert_eq!(None, once.get());
+
+        assert_eq!(&1, once.get_or_init(|| 1));
+        assert_eq!(&1, once.get_or_init(|| 2));
+        assert_eq!(Some(&1), once.get());
+    }
+
+    #[test]
+    fn give_value_back_when_set() {
+        let once = Once::new();
+
+        assert_eq!(Ok(()), once.set(1));
+        assert_eq!(Err(2), once.set(2));
+        assert_eq!(Some(&1), once.get());
+    }
+
+    #[test]
+    fn initialize_once_from_threads() {
+        static ONCE: Once<usize> = Once::new();
+        static CALLS: AtomicU8 = AtomicU8::new(0);
+
+        let threads = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    *ONCE.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::Relaxed);
+                        i
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let values = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(1, CALLS.load(Ordering::Relaxed));
+        assert!(values.iter().all(|&v| Some(&v) == ONCE.get()));
+    }
+}
@@ -0,0 +1,120 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::IrqGuard;
+
+/// Lock spinning until the holder releases it, which works across cores.
+///
+/// `lock` leaves IRQs as they are, so that data shared with interrupt handlers must be locked with
+/// `lock_irqsave` instead.
+pub(crate) struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+
+impl<T> SpinLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> SpinLockGuard<'_, T> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Waits without writing, so that the cache line is not bounced between the cores.
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+
+        SpinLockGuard { lock: self }
+    }
+
+    /// Takes the lock with IRQs masked on the calling core until the guard is dropped.
+    pub(crate) fn lock_irqsave(&self) -> IrqGuard<SpinLockGuard<'_, T>> {
+        IrqGuard::new(|| self.lock())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard { lock: self })
+    }
+
+    /// Releases the lock whose guard has been forgotten, e.g. to hand it over to another task.
+    ///
+    /// # Safety
+    /// The lock must be held, and nothing may use the forgotten guard from now on.
+    pub(crate) unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    /// Whether someone holds the lock.
+    #[inline]
+    pub(crate) fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_other_holders() {
+        let lock = SpinLock::new(0);
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+
+        drop(guard);
+        assert_eq!(Some(1), lock.try_lock().map(|g| *g));
+    }
+
+    #[test]
+    fn count_from_threads() {
+        static COUNTER: SpinLock<usize> = SpinLock::new(0);
+
+        let threads = (0..4)
+            .map(|_| std::thread::spawn(|| (0..1000).for_each(|_| *COUNTER.lock() += 1)))
+            .collect::<Vec<_>>();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        assert_eq!(4000, *COUNTER.lock());
+    }
+}
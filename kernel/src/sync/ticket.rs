@@ -0,0 +1,142 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::IrqGuard;
+
+/// Spin lock handing itself to the waiters in the order they came, so that a core taking it in a
+/// loop does not starve the others.
+pub(crate) struct TicketLock<T> {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for TicketLock<T> where T: Send {}
+
+impl<T> TicketLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+
+        TicketLockGuard { lock: self }
+    }
+
+    /// Takes the lock with IRQs masked on the calling core until the guard is dropped.
+    pub(crate) fn lock_irqsave(&self) -> IrqGuard<TicketLockGuard<'_, T>> {
+        IrqGuard::new(|| self.lock())
+    }
+
+    /// Takes the lock only if nobody holds or waits for it.
+    #[allow(dead_code)]
+    pub(crate) fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| TicketLockGuard { lock: self })
+    }
+
+    /// Serves the next ticket for the holder whose guard has been forgotten or lost, e.g. by a
+    /// panic in the middle.
+    ///
+    /// # Safety
+    /// The lock must be held, and nothing may use the forgotten guard from now on.
+    pub(crate) unsafe fn force_unlock(&self) {
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+
+    /// Whether someone holds the lock.
+    #[inline]
+    pub(crate) fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_other_holders() {
+        let lock = TicketLock::new(0);
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+
+        drop(guard);
+        assert!(!lock.is_locked());
+        assert_eq!(Some(1), lock.try_lock().map(|g| *g));
+    }
+
+    #[test]
+    fn serve_in_order_of_tickets() {
+        static LOCK: TicketLock<Vec<usize>> = TicketLock::new(Vec::new());
+
+        let guard = LOCK.lock();
+        let threads = (0..4)
+            .map(|i| {
+                // Each thread takes its ticket before the next one starts.
+                let thread = std::thread::spawn(move || LOCK.lock().push(i));
+                while LOCK.next.load(Ordering::Relaxed) != i + 2 {
+                    spin_loop();
+                }
+                thread
+            })
+            .collect::<Vec<_>>();
+
+        drop(guard);
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(vec![0, 1, 2, 3], *LOCK.lock());
+    }
+
+    #[test]
+    fn force_unlock_lost_guard() {
+        let lock = TicketLock::new(());
+        core::mem::forget(lock.lock());
+
+        unsafe { lock.force_unlock() };
+        assert!(lock.try_lock().is_some());
+    }
+}
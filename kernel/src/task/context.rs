@@ -0,0 +1,96 @@
+use core::arch::global_asm;
+
+/// Size of `Context` in bytes, which must be kept in sync with the assembly below.
+const CONTEXT_SIZE: usize = 192;
+
+/// Registers of a task saved while it is switched out.
+///
+/// Only the callee-saved registers are kept, since a task is switched out by calling
+/// `switch_context`. `ELR_EL1` and `SPSR_EL1` are kept too, as a task may be switched out in the
+/// middle of handling an exception.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub(crate) struct Context {
+    /// `x19` to `x30`, the last two of which are the frame pointer and the link register.
+    pub(crate) x: [u64; 12],
+    /// `SP_EL1`, as the kernel runs on it.
+    pub(crate) sp: u64,
+    pub(crate) elr: u64,
+    pub(crate) spsr: u64,
+    reserved: u64,
+    /// Lower halves of `v8` to `v15`.
+    pub(crate) d: [u64; 8],
+}
+
+const _: () = assert!(core::mem::size_of::<Context>() == CONTEXT_SIZE);
+
+impl Context {
+    /// Context of a task that starts by returning to `entry` on the stack, which must be aligned
+    /// to 16 bytes.
+    pub(crate) fn new(stack_top: u64, entry: extern "C" fn() -> !) -> Self {
+        let mut context = Self {
+            sp: stack_top,
+            // EL1h with DAIF masked, until the task handles its first exception.
+            spsr: 0x3C5,
+            ..Self::default()
+        };
+        // A null frame pointer ends the backtrace at the entry.
+        context.x[10] = 0;
+        context.x[11] = entry as usize as u64;
+        context
+    }
+}
+
+global_asm!(
+    r#"
+.global __switch_context
+__switch_context:
+    stp x19, x20, [x0, #16 * 0]
+    stp x21, x22, [x0, #16 * 1]
+    stp x23, x24, [x0, #16 * 2]
+    stp x25, x26, [x0, #16 * 3]
+    stp x27, x28, [x0, #16 * 4]
+    stp x29, x30, [x0, #16 * 5]
+    mov x9, sp
+    mrs x10, elr_el1
+    stp x9, x10, [x0, #16 * 6]
+    mrs x11, spsr_el1
+    str x11, [x0, #16 * 7]
+    stp d8, d9, [x0, #16 * 8]
+    stp d10, d11, [x0, #16 * 9]
+    stp d12, d13, [x0, #16 * 10]
+    stp d14, d15, [x0, #16 * 11]
+
+    ldp x19, x20, [x1, #16 * 0]
+    ldp x21, x22, [x1, #16 * 1]
+    ldp x23, x24, [x1, #16 * 2]
+    ldp x25, x26, [x1, #16 * 3]
+    ldp x27, x28, [x1, #16 * 4]
+    ldp x29, x30, [x1, #16 * 5]
+    ldp x9, x10, [x1, #16 * 6]
+    mov sp, x9
+    msr elr_el1, x10
+    ldr x11, [x1, #16 * 7]
+    msr spsr_el1, x11
+    ldp d8, d9, [x1, #16 * 8]
+    ldp d10, d11, [x1, #16 * 9]
+    ldp d12, d13, [x1, #16 * 10]
+    ldp d14, d15, [x1, #16 * 11]
+    ret
+"#
+);
+
+extern "C" {
+    /// Saves the registers of the calling task to `from`, then resumes the task of `to`.
+    ///
+    /// It returns when another task switches back to `from`.
+    fn __switch_context(from: *mut Context, to: *const Context);
+}
+
+/// # Safety
+/// Both contexts must stay in place until the task of `from` is resumed, and IRQs must be masked
+/// so that no other switch happens in between.
+#[inline]
+pub(crate) unsafe fn switch(from: *mut Context, to: *const Context) {
+    __switch_context(from, to);
+}
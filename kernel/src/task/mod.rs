@@ -0,0 +1,691 @@
+//! Tasks running on their own kernel stacks, switched by priority levels and time slices on every
+//! core.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub(crate) mod context;
+
+use crate::message::{Message, Queue};
+use crate::sync::SpinLock;
+use crate::{interrupts, smp};
+use context::Context;
+
+/// Size of the kernel stack of a task, in bytes.
+pub(crate) const STACK_SIZE: usize = 64 * 1024;
+
+/// Ticks a task runs before the timer switches to the next one at the same level.
+const TIME_SLICE: u64 = 2;
+
+/// The highest level, which tasks handling events should run at.
+pub(crate) const MAX_LEVEL: Level = 3;
+
+/// Level of the main task, and of the others unless told.
+pub(crate) const DEFAULT_LEVEL: Level = 1;
+
+/// The task running `kernel_main`, on the stack the loader gave.
+pub(crate) const MAIN: TaskId = TaskId(0);
+
+static SCHEDULER: SpinLock<Option<Scheduler>> = SpinLock::new(None);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TaskId(u64);
+
+pub(crate) type Entry = fn(u64);
+
+/// Priority of a task up to `MAX_LEVEL`, where tasks run only while none at a higher level is
+/// ready.
+pub(crate) type Level = usize;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum State {
+    Running,
+    Ready,
+    /// Waiting to be woken up by `wake`.
+    Sleeping,
+    /// Waiting for a message to arrive.
+    Blocked,
+    Exited,
+}
+
+pub(crate) struct Task {
+    id: TaskId,
+    state: State,
+    level: Level,
+    /// Ticks of the timer the task has been running for.
+    ticks: u64,
+    /// Boxed to stay in place while switching, as the tasks are added and removed.
+    context: Box<Context>,
+    /// Lock-free so that interrupt handlers send messages without allocating.
+    messages: Box<Queue>,
+    entry: Option<(Entry, u64)>,
+    /// Kept as 16-byte units for the alignment, or `None` for the tasks on the stacks they were
+    /// given.
+    #[allow(dead_code)]
+    stack: Option<Box<[u128]>>,
+}
+
+impl Task {
+    /// Creates a task starting at the entry on a new stack, or the caller if `None`.
+    fn new(id: TaskId, level: Level, entry: Option<(Entry, u64)>) -> Self {
+        let (context, stack) = match entry {
+            Some(_) => {
+                let stack = vec![0u128; STACK_SIZE / 16].into_boxed_slice();
+                let stack_top = stack.as_ptr_range().end as u64;
+                (Context::new(stack_top, start), Some(stack))
+            }
+            None => (Context::default(), None),
+        };
+
+        Self {
+            id,
+            state: State::Ready,
+            level,
+            ticks: 0,
+            context: Box::new(context),
+            messages: Box::new(Queue::new()),
+            entry,
+            stack,
+        }
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn state(&self) -> State {
+        self.state
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn level(&self) -> Level {
+        self.level
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+/// Contexts to switch from and to, which the scheduler decides but leaves to the caller.
+type Switch = (*mut Context, *const Context);
+
+/// Task a core is running, and the one it runs when none is ready.
+#[derive(Copy, Clone, Debug)]
+struct Core {
+    cpu: usize,
+    current: TaskId,
+    idle: TaskId,
+    /// Ticks left in the time slice of the current task.
+    slice: u64,
+    /// Whether to switch tasks at the end of the IRQ handler.
+    preempting: bool,
+}
+
+impl Core {
+    fn new(cpu: usize, current: TaskId, idle: TaskId) -> Self {
+        Self {
+            cpu,
+            current,
+            idle,
+            slice: TIME_SLICE,
+            preempting: false,
+        }
+    }
+}
+
+/// Scheduler running the tasks ready at the highest level in turn on every core, or the idle task
+/// of the core when none is ready.
+pub(crate) struct Scheduler {
+    tasks: Vec<Task>,
+    /// Tasks ready to run at each level, in the order to run.
+    queues: [VecDeque<TaskId>; MAX_LEVEL + 1],
+    cores: Vec<Core>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    /// Takes the caller as the main task, which is running on the boot core.
+    pub(crate) fn new() -> Self {
+        let mut main = Task::new(MAIN, DEFAULT_LEVEL, None);
+        main.state = State::Running;
+        let mut scheduler = Self {
+            tasks: vec![main],
+            queues: Default::default(),
+            cores: Vec::new(),
+            next_id: 1,
+        };
+
+        let idle = scheduler.add(0, Some((idle, 0)));
+        scheduler.cores.push(Core::new(smp::BOOT_CPU, MAIN, idle));
+        scheduler
+    }
+
+    /// Takes the caller as the idle task of another core, which runs the tasks from now on.
+    pub(crate) fn start_core(&mut self, cpu: usize) {
+        if self.core(cpu).is_some() {
+            return;
+        }
+
+        let idle = self.add(0, None);
+        self.task_mut(idle).unwrap().state = State::Running;
+        self.cores.push(Core::new(cpu, idle, idle));
+    }
+
+    fn add(&mut self, level: Level, entry: Option<(Entry, u64)>) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(Task::new(id, level, entry));
+        id
+    }
+
+    pub(crate) fn task(&self, id: TaskId) -> Option<&Task> {
+        self.tasks.iter().find(|t| t.id == id)
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> Option<&mut Task> {
+        self.tasks.iter_mut().find(|t| t.id == id)
+    }
+
+    fn core(&self, cpu: usize) -> Option<&Core> {
+        self.cores.iter().find(|c| c.cpu == cpu)
+    }
+
+    fn core_mut(&mut self, cpu: usize) -> Option<&mut Core> {
+        self.cores.iter_mut().find(|c| c.cpu == cpu)
+    }
+
+    fn is_idle(&self, id: TaskId) -> bool {
+        self.cores.iter().any(|c| c.idle == id)
+    }
+
+    /// The task the core is running.
+    #[inline]
+    pub(crate) fn current(&self, cpu: usize) -> Option<TaskId> {
+        self.core(cpu).map(|c| c.current)
+    }
+
+    /// Adds a task ready to run at the level, freeing the stacks of the tasks exited so far.
+    pub(crate) fn spawn(&mut self, entry: Entry, arg: u64, level: Level) -> TaskId {
+        let cores = &self.cores;
+        self.tasks
+            .retain(|t| t.state != State::Exited || cores.iter().any(|c| c.current == t.id));
+
+        let id = self.add(level.min(MAX_LEVEL), Some((entry, arg)));
+        self.ready(id);
+        id
+    }
+
+    /// Queues the task to run, preempting the cores idle or running a task at a lower level.
+    fn ready(&mut self, id: TaskId) {
+        let running = self.cores.iter().any(|c| c.current == id);
+        let Some(task) = self.task_mut(id) else {
+            return;
+        };
+
+        // Suspended on another core which has not switched away yet, so it just keeps running.
+        if running {
+            task.state = State::Running;
+            return;
+        }
+
+        task.state = State::Ready;
+        let level = task.level;
+        self.queues[level].push_back(id);
+
+        let tasks = &self.tasks;
+        self.cores
+            .iter_mut()
+            .filter(|c| {
+                c.current == c.idle
+                    || tasks
+                        .iter()
+                        .find(|t| t.id == c.current)
+                        .is_none_or(|t| t.level < level)
+            })
+            .for_each(|c| c.preempting = true);
+    }
+
+    /// Moves the task to the level, preempting it if it is no longer the highest.
+    pub(crate) fn set_level(&mut self, id: TaskId, level: Level) {
+        let level = level.min(MAX_LEVEL);
+        if self.is_idle(id) {
+            return;
+        }
+        let Some(task) = self.task_mut(id) else {
+            return;
+        };
+
+        let (previous, state) = (task.level, task.state);
+        task.level = level;
+        if state == State::Ready {
+            self.queues[previous].retain(|&i| i != id);
+            self.ready(id);
+        }
+
+        let higher = self.queues[level + 1..].iter().any(|q| !q.is_empty());
+        if let Some(core) = self.cores.iter_mut().find(|c| c.current == id) {
+            core.preempting |= higher;
+        }
+    }
+
+    /// Switches the core to the next task ready at the highest level, putting the current one at
+    /// the end of the queue of its level.
+    pub(crate) fn rotate(&mut self, cpu: usize) -> Option<Switch> {
+        let Core { current, idle, .. } = *self.core(cpu)?;
+        if current != idle && self.task(current)?.state == State::Running {
+            let task = self.task_mut(current)?;
+            task.state = State::Ready;
+            let level = task.level;
+            self.queues[level].push_back(current);
+        }
+
+        let next = self
+            .queues
+            .iter_mut()
+            .rev()
+            .find_map(|q| q.pop_front())
+            .unwrap_or(idle);
+        self.switch_to(cpu, next)
+    }
+
+    /// Stops running the task until woken up, switching away if the core is running it.
+    pub(crate) fn sleep(&mut self, cpu: usize, id: TaskId) -> Option<Switch> {
+        self.suspend(cpu, id, State::Sleeping)
+    }
+
+    /// Stops running the current task of the core until a message arrives.
+    pub(crate) fn block(&mut self, cpu: usize) -> Option<Switch> {
+        let current = self.core(cpu)?.current;
+        self.suspend(cpu, current, State::Blocked)
+    }
+
+    fn suspend(&mut self, cpu: usize, id: TaskId, state: State) -> Option<Switch> {
+        if self.is_idle(id) || self.task(id)?.state == State::Exited {
+            return None;
+        }
+
+        let task = self.task_mut(id)?;
+        task.state = state;
+        let level = task.level;
+        self.queues[level].retain(|&i| i != id);
+
+        if self.current(cpu) == Some(id) {
+            return self.rotate(cpu);
+        }
+        // Running on another core, which switches away at the end of the next IRQ.
+        if let Some(core) = self.cores.iter_mut().find(|c| c.current == id) {
+            core.preempting = true;
+        }
+        None
+    }
+
+    /// Queues the sleeping task to run.
+    pub(crate) fn wake(&mut self, id: TaskId) {
+        if self.task(id).is_some_and(|t| t.state == State::Sleeping) {
+            self.ready(id);
+        }
+    }
+
+    /// Sends the message to the task, waking it up if blocked, or gives it back if the queue of
+    /// the task is full.
+    pub(crate) fn send(&mut self, id: TaskId, message: Message) -> Result<(), Message> {
+        let Some(task) = self.task(id).filter(|t| t.state != State::Exited) else {
+            return Err(message);
+        };
+
+        task.messages.push(message)?;
+        if task.state == State::Blocked {
+            self.ready(id);
+        }
+        Ok(())
+    }
+
+    /// Takes the message at the front of the queue of the current task of the core, if any.
+    pub(crate) fn receive(&mut self, cpu: usize) -> Option<Message> {
+        self.task(self.current(cpu)?)?.messages.pop()
+    }
+
+    /// Ends the current task of the core, switching to the next one for good.
+    pub(crate) fn exit(&mut self, cpu: usize) -> Option<Switch> {
+        let current = self.current(cpu)?;
+        self.task_mut(current)?.state = State::Exited;
+        self.rotate(cpu)
+    }
+
+    /// Accounts the tick of the timer to the current task of the core, preempting it at the end
+    /// of the time slice.
+    pub(crate) fn tick(&mut self, cpu: usize) {
+        let Some(core) = self.core_mut(cpu) else {
+            return;
+        };
+
+        core.slice = core.slice.saturating_sub(1);
+        if core.slice == 0 {
+            core.preempting = true;
+        }
+
+        let current = core.current;
+        if let Some(task) = self.task_mut(current) {
+            task.ticks += 1;
+        }
+    }
+
+    /// Switches the core to another task if one at a higher level is ready, or the time slice is
+    /// over.
+    pub(crate) fn preempt(&mut self, cpu: usize) -> Option<Switch> {
+        match core::mem::take(&mut self.core_mut(cpu)?.preempting) {
+            true => self.rotate(cpu),
+            false => None,
+        }
+    }
+
+    fn switch_to(&mut self, cpu: usize, next: TaskId) -> Option<Switch> {
+        self.task_mut(next)?.state = State::Running;
+        let core = self.core_mut(cpu)?;
+        core.slice = TIME_SLICE;
+        let current = core.current;
+        if next == current {
+            return None;
+        }
+
+        core.current = next;
+        let from = self.task_mut(current)?.context.as_mut() as *mut Context;
+        let to = self.task(next)?.context.as_ref() as *const Context;
+        Some((from, to))
+    }
+}
+
+/// Turns the caller into the main task, and starts the idle task of the boot core.
+pub(crate) fn init() {
+    interrupts::without_interrupts(|| *SCHEDULER.lock() = Some(Scheduler::new()));
+}
+
+/// Turns the caller into the idle task of the secondary core, which runs the tasks from now on.
+pub(crate) fn init_cpu() {
+    schedule_with(|s, cpu| s.start_core(cpu));
+}
+
+/// Runs the closure on the scheduler with the core calling this, then switches tasks as it
+/// decided.
+///
+/// The scheduler stays locked until the next task releases it once switched, so that no other
+/// core resumes the previous task before its registers are saved.
+fn schedule<F>(f: F)
+where
+    F: FnOnce(&mut Scheduler, usize) -> Option<Switch>,
+{
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let Some(switch) = scheduler.as_mut().and_then(|s| f(s, smp::id())) else {
+            return;
+        };
+
+        core::mem::forget(scheduler);
+        let (from, to) = switch;
+        unsafe {
+            context::switch(from, to);
+            SCHEDULER.force_unlock();
+        }
+    })
+}
+
+/// Runs the closure on the scheduler with the core calling this, leaving the switch to the next
+/// preemption.
+fn schedule_with<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Scheduler, usize) -> R,
+{
+    interrupts::without_interrupts(|| SCHEDULER.lock().as_mut().map(|s| f(s, smp::id())))
+}
+
+/// Starts a task at the level calling the entry with the argument, which exits when the entry
+/// returns.
+pub(crate) fn spawn(entry: Entry, arg: u64, level: Level) -> Option<TaskId> {
+    schedule_with(|s, _| s.spawn(entry, arg, level))
+}
+
+pub(crate) fn set_level(id: TaskId, level: Level) {
+    schedule(|s, cpu| {
+        s.set_level(id, level);
+        s.preempt(cpu)
+    });
+}
+
+/// Puts the calling task to sleep if the condition on it holds, which is checked with the scheduler
+/// locked so that a `wake` right after the check is not missed.
+pub(crate) fn sleep_if<F>(condition: F)
+where
+    F: FnOnce(TaskId) -> bool,
+{
+    schedule(|s, cpu| {
+        let id = s.current(cpu)?;
+        match condition(id) {
+            true => s.sleep(cpu, id),
+            false => None,
+        }
+    });
+}
+
+/// Wakes up the task, which is safe to call from interrupt handlers.
+pub(crate) fn wake(id: TaskId) {
+    schedule_with(|s, _| s.wake(id));
+}
+
+/// Sends the message to the task, which is safe to call from interrupt handlers.
+pub(crate) fn send(id: TaskId, message: Message) -> Result<(), Message> {
+    schedule_with(|s, _| s.send(id, message)).unwrap_or(Err(message))
+}
+
+/// Takes the message at the front of the queue of the calling task, blocking until one arrives.
+pub(crate) fn receive() -> Message {
+    loop {
+        let mut message = None;
+        schedule(|s, cpu| match s.receive(cpu) {
+            Some(m) => {
+                message = Some(m);
+                None
+            }
+            None => s.block(cpu),
+        });
+
+        if let Some(message) = message {
+            return message;
+        }
+    }
+}
+
+/// Counts the tick of the timer on the calling core, called from the IRQ handler.
+pub(crate) fn tick() {
+    schedule_with(|s, cpu| s.tick(cpu));
+}
+
+/// Switches tasks if the scheduler asked to, called at the end of the IRQ handler.
+pub(crate) fn preempt() {
+    schedule(|s, cpu| s.preempt(cpu));
+}
+
+/// Where every task but the main one starts, with IRQs masked and the scheduler locked by the
+/// switch.
+extern "C" fn start() -> ! {
+    unsafe { SCHEDULER.force_unlock() };
+    let (entry, arg) = schedule_with(|s, cpu| s.task(s.current(cpu)?)?.entry)
+        .flatten()
+        .expect("Task has no entry");
+
+    interrupts::unmask();
+    entry(arg);
+
+    schedule(|s, cpu| s.exit(cpu));
+    unreachable!("Exited task has been resumed");
+}
+
+/// Runs when no task is ready, waiting for interrupts with `wfi`.
+fn idle(_: u64) {
+    loop {
+        aarch64::instructions::halt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+
+    const CPU: usize = smp::BOOT_CPU;
+
+    fn noop(_: u64) {}
+
+    fn switched(scheduler: &Scheduler, switch: Option<Switch>, from: TaskId, to: TaskId) -> bool {
+        let context = |id| scheduler.task(id).unwrap().context.as_ref() as *const Context;
+        switch.is_some_and(|(f, t)| ptr::eq(f, context(from)) && ptr::eq(t, context(to)))
+    }
+
+    #[test]
+    fn set_up_new_stack() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(noop, 42, DEFAULT_LEVEL);
+        let task = scheduler.task(a).unwrap();
+
+        assert_eq!(State::Ready, task.state());
+        assert_eq!(0, task.context.sp % 16);
+        assert_eq!(start as *const () as usize as u64, task.context.x[11]);
+        assert_eq!(Some(42), task.entry.map(|(_, arg)| arg));
+    }
+
+    #[test]
+    fn rotate_in_order() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+        let b = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+
+        let switch = scheduler.rotate(CPU);
+        assert!(switched(&scheduler, switch, MAIN, a));
+        let switch = scheduler.rotate(CPU);
+        assert!(switched(&scheduler, switch, a, b));
+        let switch = scheduler.rotate(CPU);
+        assert!(switched(&scheduler, switch, b, MAIN));
+        assert_eq!(State::Ready, scheduler.task(b).unwrap().state());
+        assert_eq!(State::Running, scheduler.task(MAIN).unwrap().state());
+    }
+
+    #[test]
+    fn run_higher_levels_first() {
+        let mut scheduler = Scheduler::new();
+        let low = scheduler.spawn(noop, 0, 0);
+        assert!(scheduler.preempt(CPU).is_none());
+
+        // Spawning a task at a higher level preempts the current one.
+        let high = scheduler.spawn(noop, 0, MAX_LEVEL);
+        let switch = scheduler.preempt(CPU);
+        assert!(switched(&scheduler, switch, MAIN, high));
+        assert!(scheduler.rotate(CPU).is_none());
+
+        // The lower levels run once the higher ones sleep.
+        let switch = scheduler.sleep(CPU, high);
+        assert!(switched(&scheduler, switch, high, MAIN));
+        let switch = scheduler.sleep(CPU, MAIN);
+        assert!(switched(&scheduler, switch, MAIN, low));
+
+        scheduler.set_level(MAIN, MAX_LEVEL);
+        scheduler.wake(MAIN);
+        let switch = scheduler.preempt(CPU);
+        assert!(switched(&scheduler, switch, low, MAIN));
+        assert_eq!(MAX_LEVEL, scheduler.task(MAIN).unwrap().level());
+    }
+
+    #[test]
+    fn preempt_at_end_of_time_slice() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+
+        (1..TIME_SLICE).for_each(|_| scheduler.tick(CPU));
+        assert!(scheduler.preempt(CPU).is_none());
+        scheduler.tick(CPU);
+        let switch = scheduler.preempt(CPU);
+        assert!(switched(&scheduler, switch, MAIN, a));
+
+        scheduler.tick(CPU);
+        assert_eq!(TIME_SLICE, scheduler.task(MAIN).unwrap().ticks());
+        assert_eq!(1, scheduler.task(a).unwrap().ticks());
+    }
+
+    #[test]
+    fn sleep_and_wake() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+
+        // Sleeping another task only takes it off the queue.
+        assert!(scheduler.sleep(CPU, a).is_none());
+        assert!(scheduler.rotate(CPU).is_none());
+
+        // With nothing else ready, the idle task runs.
+        let idle = scheduler.core(CPU).unwrap().idle;
+        let switch = scheduler.sleep(CPU, MAIN);
+        assert!(switched(&scheduler, switch, MAIN, idle));
+
+        scheduler.wake(a);
+        scheduler.wake(MAIN);
+        let switch = scheduler.rotate(CPU);
+        assert!(switched(&scheduler, switch, idle, a));
+        let switch = scheduler.exit(CPU);
+        assert!(switched(&scheduler, switch, a, MAIN));
+
+        // Exited tasks are neither woken up nor kept once another task is spawned.
+        scheduler.wake(a);
+        assert!(scheduler.rotate(CPU).is_none());
+        scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+        assert!(scheduler.task(a).is_none());
+    }
+
+    #[test]
+    fn block_until_message() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+        let message = Message::Xhci;
+
+        assert!(scheduler.receive(CPU).is_none());
+        let switch = scheduler.block(CPU);
+        assert!(switched(&scheduler, switch, MAIN, a));
+        assert_eq!(State::Blocked, scheduler.task(MAIN).unwrap().state());
+
+        // Sending to a task not blocked only queues the message.
+        assert_eq!(Ok(()), scheduler.send(a, message));
+        assert_eq!(State::Running, scheduler.task(a).unwrap().state());
+        assert_eq!(Some(message), scheduler.receive(CPU));
+
+        assert_eq!(Ok(()), scheduler.send(MAIN, message));
+        assert_eq!(State::Ready, scheduler.task(MAIN).unwrap().state());
+        let switch = scheduler.rotate(CPU);
+        assert!(switched(&scheduler, switch, a, MAIN));
+        assert_eq!(Some(message), scheduler.receive(CPU));
+    }
+
+    #[test]
+    fn run_on_secondary_cores() {
+        let mut scheduler = Scheduler::new();
+        scheduler.start_core(1);
+        let idle = scheduler.current(1).unwrap();
+        let a = scheduler.spawn(noop, 0, DEFAULT_LEVEL);
+
+        // The idle core takes the task, which the other core never runs at the same time.
+        let switch = scheduler.preempt(1);
+        assert!(switched(&scheduler, switch, idle, a));
+        assert!(scheduler.rotate(CPU).is_none());
+
+        // Sleeping a task on another core switches away only there, unless woken up before.
+        assert!(scheduler.sleep(CPU, a).is_none());
+        scheduler.wake(a);
+        assert!(scheduler.preempt(1).is_none());
+        assert_eq!(Some(a), scheduler.current(1));
+
+        assert!(scheduler.sleep(CPU, a).is_none());
+        let switch = scheduler.preempt(1);
+        assert!(switched(&scheduler, switch, a, idle));
+        assert_eq!(State::Sleeping, scheduler.task(a).unwrap().state());
+    }
+}
@@ -0,0 +1,334 @@
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::interrupts::{self, IntId, Trigger};
+use crate::message::{self, Message};
+use crate::smp;
+use crate::sync::SpinLock;
+use crate::task::{self, TaskId};
+
+/// INTID of the EL1 physical timer on QEMU's `virt` board, a PPI routed to every core.
+pub(crate) const VIRT_INTID: IntId = 30;
+
+/// Frequency of the periodic tick.
+pub(crate) const TICK_HZ: u64 = 100;
+
+const CNTP_CTL_ENABLE: u64 = 1 << 0;
+
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static INTID: AtomicU32 = AtomicU32::new(VIRT_INTID);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TIMERS: SpinLock<TimerQueue> = SpinLock::new(TimerQueue::new());
+/// Timers of the sleeping tasks, with the task to wake up, or `None` once expired before the task
+/// went to sleep. Never held while taking another lock.
+static SLEEPERS: SpinLock<Vec<(TimerId, Option<TaskId>)>> = SpinLock::new(Vec::new());
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TimerId(u64);
+
+pub(crate) type Callback = fn(TimerId);
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Timer {
+    pub(crate) id: TimerId,
+    pub(crate) deadline: u64,
+    pub(crate) period: Option<u64>,
+    pub(crate) callback: Callback,
+}
+
+/// INTIDs of the architected timer, as described by the firmware.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct TimerInterrupts {
+    pub(crate) secure_physical: IntId,
+    pub(crate) non_secure_physical: IntId,
+    pub(crate) virtual_: IntId,
+    pub(crate) hypervisor_physical: IntId,
+}
+
+/// Software timers sorted by their deadlines in ticks, the nearest one at the end.
+pub(crate) struct TimerQueue {
+    timers: Vec<Timer>,
+    next_id: u64,
+}
+
+impl TimerQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            timers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds a timer firing at the deadline, then every `period` ticks if specified.
+    pub(crate) fn add(
+        &mut self,
+        deadline: u64,
+        period: Option<u64>,
+        callback: Callback,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.insert(Timer {
+            id,
+            deadline,
+            period,
+            callback,
+        });
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        let len = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != len
+    }
+
+    /// Pops the timers due at `now` and passes them to `f`, rescheduling repeating ones.
+    ///
+    /// Rescheduling never grows the queue, so this is safe to call from an interrupt handler.
+    pub(crate) fn expire<F>(&mut self, now: u64, mut f: F)
+    where
+        F: FnMut(&Timer),
+    {
+        while self.timers.last().is_some_and(|t| t.deadline <= now) {
+            let timer = self.timers.pop().unwrap();
+            f(&timer);
+
+            if let Some(period) = timer.period {
+                self.insert(Timer {
+                    deadline: timer.deadline + period.max(1),
+                    ..timer
+                });
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    fn insert(&mut self, timer: Timer) {
+        let index = self.timers.partition_point(|t| t.deadline > timer.deadline);
+        self.timers.insert(index, timer);
+    }
+}
+
+#[inline]
+fn counter() -> u64 {
+    let count: u64;
+    unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) count, options(nomem, nostack)) };
+    count
+}
+
+#[inline]
+fn reload() {
+    let interval = FREQUENCY.load(Ordering::Relaxed) / TICK_HZ;
+    unsafe { asm!("msr cntp_tval_el0, {}", in(reg) interval, options(nomem, nostack)) };
+}
+
+/// Starts the periodic tick on the boot core, driven by the EL1 physical timer at the INTID.
+pub(crate) fn init(intid: IntId) {
+    let frequency = read_frequency();
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    INTID.store(intid, Ordering::Relaxed);
+
+    init_cpu();
+
+    log::info!("Timer: {} Hz counter, {} Hz tick", frequency, TICK_HZ);
+}
+
+/// Starts the periodic tick on the calling core, after `init` has run on the boot core.
+pub(crate) fn init_cpu() {
+    reload();
+    unsafe { asm!("msr cntp_ctl_el0, {}", in(reg) CNTP_CTL_ENABLE, options(nomem, nostack)) };
+
+    interrupts::register(
+        INTID.load(Ordering::Relaxed),
+        Trigger::Level,
+        interrupts::DEFAULT_PRIORITY,
+        handle_tick,
+    );
+}
+
+/// Every core ticks its own scheduler, but only the boot core counts the ticks and fires the
+/// software timers.
+fn handle_tick(_: IntId) {
+    reload();
+
+    if smp::id() == smp::BOOT_CPU {
+        let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+        TIMERS
+            .lock()
+            .expire(now, |timer| (timer.callback)(timer.id));
+    }
+
+    crate::task::tick();
+}
+
+#[inline]
+fn read_frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack)) };
+    frequency
+}
+
+/// Frequency of the system counter, which can be read even before the timer starts.
+#[inline]
+pub(crate) fn frequency() -> u64 {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => read_frequency(),
+        frequency => frequency,
+    }
+}
+
+/// Number of ticks since the timer started.
+#[inline]
+pub(crate) fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time elapsed since the system counter started, which is usually the power-on.
+pub(crate) fn uptime() -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        frequency => {
+            Duration::from_nanos((counter() as u128 * 1_000_000_000 / frequency as u128) as u64)
+        }
+    }
+}
+
+#[inline]
+fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICK_HZ).div_ceil(1000).max(1)
+}
+
+/// Calls the callback once, after the duration elapses.
+pub(crate) fn set_timeout(ms: u64, callback: Callback) -> TimerId {
+    interrupts::without_interrupts(|| TIMERS.lock().add(ticks() + ms_to_ticks(ms), None, callback))
+}
+
+/// Calls the callback repeatedly, every time the duration elapses.
+pub(crate) fn set_interval(ms: u64, callback: Callback) -> TimerId {
+    let period = ms_to_ticks(ms);
+    interrupts::without_interrupts(|| TIMERS.lock().add(ticks() + period, Some(period), callback))
+}
+
+/// Puts the calling task to sleep for the duration, without spinning. Returns right away if called
+/// before the scheduler starts.
+#[allow(dead_code)]
+pub(crate) fn sleep_ms(ms: u64) {
+    let id = set_timeout(ms, wake_sleeper);
+
+    let mut first = true;
+    loop {
+        let mut sleeping = false;
+        task::sleep_if(|task| {
+            let mut sleepers = SLEEPERS.lock();
+            let index = sleepers.iter().position(|&(i, _)| i == id);
+            sleeping = match (index, first) {
+                (None, true) => {
+                    sleepers.push((id, Some(task)));
+                    true
+                }
+                // Expired before the task could sleep.
+                (Some(index), true) => {
+                    sleepers.swap_remove(index);
+                    false
+                }
+                // Woken up by something else than the timer.
+                (Some(_), false) => true,
+                (None, false) => false,
+            };
+            sleeping
+        });
+
+        if !sleeping {
+            break;
+        }
+        first = false;
+    }
+}
+
+/// Wakes up the task sleeping on the timer, or tells it not to sleep if it has not yet.
+fn wake_sleeper(id: TimerId) {
+    let task = {
+        let mut sleepers = SLEEPERS.lock();
+        match sleepers.iter().position(|&(i, _)| i == id) {
+            Some(index) => sleepers.swap_remove(index).1,
+            None => {
+                sleepers.push((id, None));
+                None
+            }
+        }
+    };
+
+    if let Some(task) = task {
+        task::wake(task);
+    }
+}
+
+/// Callback posting the expiry to the main queue, to handle it outside the interrupt handler.
+pub(crate) fn post_timeout(id: TimerId) {
+    message::post(Message::Timer(id));
+}
+
+pub(crate) fn cancel(id: TimerId) -> bool {
+    interrupts::without_interrupts(|| TIMERS.lock().cancel(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_: TimerId) {}
+
+    fn expire(queue: &mut TimerQueue, now: u64) -> Vec<(TimerId, u64)> {
+        let mut fired = Vec::new();
+        queue.expire(now, |t| fired.push((t.id, t.deadline)));
+        fired
+    }
+
+    #[test]
+    fn expire_in_order_of_deadlines() {
+        let mut queue = TimerQueue::new();
+        let a = queue.add(30, None, noop);
+        let b = queue.add(10, None, noop);
+        let c = queue.add(20, None, noop);
+
+        assert_eq!(vec![(b, 10)], expire(&mut queue, 15));
+        assert_eq!(vec![(c, 20), (a, 30)], expire(&mut queue, 30));
+        assert_eq!(0, queue.len());
+    }
+
+    #[test]
+    fn reschedule_repeating_timers() {
+        let mut queue = TimerQueue::new();
+        let a = queue.add(10, Some(10), noop);
+
+        assert_eq!(vec![(a, 10), (a, 20), (a, 30)], expire(&mut queue, 35));
+        assert_eq!(1, queue.len());
+        assert!(expire(&mut queue, 39).is_empty());
+    }
+
+    #[test]
+    fn cancel_timers() {
+        let mut queue = TimerQueue::new();
+        let a = queue.add(10, None, noop);
+        let b = queue.add(10, Some(5), noop);
+
+        assert!(queue.cancel(b));
+        assert!(!queue.cancel(b));
+        assert_eq!(vec![(a, 10)], expire(&mut queue, 100));
+    }
+
+    #[test]
+    fn convert_ms_to_ticks() {
+        assert_eq!(1, ms_to_ticks(0));
+        assert_eq!(1, ms_to_ticks(10));
+        assert_eq!(2, ms_to_ticks(11));
+        assert_eq!(100, ms_to_ticks(1000));
+    }
+}
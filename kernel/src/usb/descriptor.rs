@@ -0,0 +1,231 @@
+//! Standard USB descriptors, and finding the HID boot interfaces in a configuration.
+
+use alloc::vec::Vec;
+
+pub(crate) const DESCRIPTOR_DEVICE: u8 = 1;
+pub(crate) const DESCRIPTOR_CONFIGURATION: u8 = 2;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+
+const ENDPOINT_IN: u8 = 1 << 7;
+const ENDPOINT_TRANSFER_TYPE: u8 = 0b11;
+const ENDPOINT_INTERRUPT: u8 = 0b11;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DeviceDescriptor {
+    pub(crate) usb_version: u16,
+    pub(crate) class: u8,
+    pub(crate) max_packet_size: u8,
+    pub(crate) vendor_id: u16,
+    pub(crate) product_id: u16,
+    pub(crate) configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 18 || bytes[1] != DESCRIPTOR_DEVICE {
+            return None;
+        }
+
+        Some(Self {
+            usb_version: u16::from_le_bytes([bytes[2], bytes[3]]),
+            class: bytes[4],
+            max_packet_size: bytes[7],
+            vendor_id: u16::from_le_bytes([bytes[8], bytes[9]]),
+            product_id: u16::from_le_bytes([bytes[10], bytes[11]]),
+            configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct EndpointDescriptor {
+    pub(crate) address: u8,
+    pub(crate) attributes: u8,
+    pub(crate) max_packet_size: u16,
+    pub(crate) interval: u8,
+}
+
+impl EndpointDescriptor {
+    #[inline]
+    pub(crate) fn number(&self) -> u8 {
+        self.address & 0xF
+    }
+
+    #[inline]
+    pub(crate) fn is_in(&self) -> bool {
+        self.address & ENDPOINT_IN != 0
+    }
+
+    #[inline]
+    pub(crate) fn is_interrupt(&self) -> bool {
+        self.attributes & ENDPOINT_TRANSFER_TYPE == ENDPOINT_INTERRUPT
+    }
+
+    /// Device Context Index of the endpoint, as numbered by xHCI.
+    #[inline]
+    pub(crate) fn dci(&self) -> u8 {
+        self.number() * 2 + self.is_in() as u8
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum BootProtocol {
+    Keyboard,
+    Mouse,
+}
+
+/// An interface supporting a HID boot protocol, with its interrupt IN endpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct HidInterface {
+    pub(crate) number: u8,
+    pub(crate) protocol: BootProtocol,
+    pub(crate) endpoint: EndpointDescriptor,
+}
+
+/// The whole configuration descriptor, followed by its interface and endpoint descriptors.
+#[derive(Clone, Debug)]
+pub(crate) struct Configuration {
+    pub(crate) value: u8,
+    pub(crate) hid_interfaces: Vec<HidInterface>,
+}
+
+impl Configuration {
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 9 || bytes[1] != DESCRIPTOR_CONFIGURATION {
+            return None;
+        }
+
+        let total_length = (u16::from_le_bytes([bytes[2], bytes[3]]) as usize).min(bytes.len());
+        let mut hid_interfaces = Vec::new();
+        let mut interface = None;
+
+        descriptors(&bytes[..total_length]).for_each(|descriptor| match descriptor[1] {
+            DESCRIPTOR_INTERFACE if descriptor.len() >= 9 => {
+                interface = match (descriptor[5], descriptor[6], descriptor[7]) {
+                    (CLASS_HID, SUBCLASS_BOOT, 1) => Some((descriptor[2], BootProtocol::Keyboard)),
+                    (CLASS_HID, SUBCLASS_BOOT, 2) => Some((descriptor[2], BootProtocol::Mouse)),
+                    _ => None,
+                };
+            }
+            DESCRIPTOR_ENDPOINT if descriptor.len() >= 7 => {
+                let endpoint = EndpointDescriptor {
+                    address: descriptor[2],
+                    attributes: descriptor[3],
+                    max_packet_size: u16::from_le_bytes([descriptor[4], descriptor[5]]),
+                    interval: descriptor[6],
+                };
+
+                if let Some((number, protocol)) = interface {
+                    if endpoint.is_in() && endpoint.is_interrupt() {
+                        hid_interfaces.push(HidInterface {
+                            number,
+                            protocol,
+                            endpoint,
+                        });
+                        interface = None;
+                    }
+                }
+            }
+            _ => {}
+        });
+
+        Some(Self {
+            value: bytes[5],
+            hid_interfaces,
+        })
+    }
+}
+
+/// Splits the bytes into descriptors by their lengths in the first byte.
+fn descriptors(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        let len = *bytes.first()? as usize;
+        if len < 2 || len > bytes.len() {
+            return None;
+        }
+
+        let (descriptor, rest) = bytes.split_at(len);
+        bytes = rest;
+        Some(descriptor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The configuration of QEMU's `usb-kbd`.
+    const KEYBOARD_CONFIGURATION: &[u8] = &[
+        0x09, 0x02, 0x22, 0x00, 0x01, 0x01, 0x06, 0xA0, 0x32, // configuration
+        0x09, 0x04, 0x00, 0x00, 0x01, 0x03, 0x01, 0x01, 0x00, // interface
+        0x09, 0x21, 0x11, 0x01, 0x00, 0x01, 0x22, 0x3F, 0x00, // HID
+        0x07, 0x05, 0x81, 0x03, 0x08, 0x00, 0x07, // endpoint
+    ];
+
+    #[test]
+    fn parse_device_descriptor() {
+        let bytes = [
+            0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x27, 0x06, 0x01, 0x00, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x01,
+        ];
+
+        assert_eq!(
+            Some(DeviceDescriptor {
+                usb_version: 0x0200,
+                class: 0,
+                max_packet_size: 64,
+                vendor_id: 0x0627,
+                product_id: 0x0001,
+                configurations: 1,
+            }),
+            DeviceDescriptor::parse(&bytes)
+        );
+    }
+
+    #[test]
+    fn find_boot_keyboard() {
+        let configuration = Configuration::parse(KEYBOARD_CONFIGURATION).unwrap();
+        let endpoint = EndpointDescriptor {
+            address: 0x81,
+            attributes: 0x03,
+            max_packet_size: 8,
+            interval: 7,
+        };
+
+        assert_eq!(1, configuration.value);
+        assert_eq!(
+            vec![HidInterface {
+                number: 0,
+                protocol: BootProtocol::Keyboard,
+                endpoint,
+            }],
+            configuration.hid_interfaces
+        );
+        assert_eq!(3, endpoint.dci());
+    }
+
+    #[test]
+    fn ignore_non_boot_interfaces() {
+        let mut bytes = KEYBOARD_CONFIGURATION.to_vec();
+        bytes[9 + 6] = 0;
+
+        assert!(Configuration::parse(&bytes)
+            .unwrap()
+            .hid_interfaces
+            .is_empty());
+    }
+
+    #[test]
+    fn stop_at_truncated_descriptor() {
+        let bytes = &KEYBOARD_CONFIGURATION[..KEYBOARD_CONFIGURATION.len() - 1];
+
+        assert!(Configuration::parse(bytes)
+            .unwrap()
+            .hid_interfaces
+            .is_empty());
+    }
+}
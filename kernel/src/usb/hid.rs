@@ -0,0 +1,143 @@
+//! Reports of the HID boot protocol, turned into keyboard and mouse events.
+
+use crate::message::{KeyEvent, MouseEvent};
+
+/// Usage IDs of the modifier keys, from Left Control to Right GUI, in the order of the bits.
+const MODIFIER_USAGES: u8 = 0xE0;
+/// Reported in every slot when too many keys are pressed at once.
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Keyboard of the boot protocol, whose reports are the modifier bits, a reserved byte and six
+/// usages of the pressed keys.
+#[derive(Default)]
+pub(crate) struct BootKeyboard {
+    previous: [u8; 8],
+}
+
+impl BootKeyboard {
+    /// Calls the closure for the keys pressed or released since the previous report.
+    pub(crate) fn handle<F>(&mut self, report: &[u8], mut f: F)
+    where
+        F: FnMut(KeyEvent),
+    {
+        let Some(report) = report.get(..8).and_then(|r| <[u8; 8]>::try_from(r).ok()) else {
+            return;
+        };
+        if report[2..].contains(&ERROR_ROLL_OVER) {
+            return;
+        }
+
+        let modifiers = report[0];
+        let changed = self.previous[0] ^ modifiers;
+        (0..8).filter(|i| changed & (1 << i) != 0).for_each(|i| {
+            f(KeyEvent {
+                modifiers,
+                usage: MODIFIER_USAGES + i,
+                pressed: modifiers & (1 << i) != 0,
+            })
+        });
+
+        let (previous, current) = (&self.previous[2..], &report[2..]);
+        previous
+            .iter()
+            .filter(|&&usage| usage != 0 && !current.contains(&usage))
+            .for_each(|&usage| {
+                f(KeyEvent {
+                    modifiers,
+                    usage,
+                    pressed: false,
+                })
+            });
+        current
+            .iter()
+            .filter(|&&usage| usage != 0 && !previous.contains(&usage))
+            .for_each(|&usage| {
+                f(KeyEvent {
+                    modifiers,
+                    usage,
+                    pressed: true,
+                })
+            });
+
+        self.previous = report;
+    }
+}
+
+/// Parses a report of a mouse of the boot protocol, which are the buttons and the movements.
+pub(crate) fn parse_mouse_report(report: &[u8]) -> Option<MouseEvent> {
+    match report {
+        [buttons, dx, dy, ..] => Some(MouseEvent {
+            buttons: *buttons,
+            dx: *dx as i8 as i32,
+            dy: *dy as i8 as i32,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(keyboard: &mut BootKeyboard, report: [u8; 8]) -> Vec<(u8, bool)> {
+        let mut events = Vec::new();
+        keyboard.handle(&report, |e| events.push((e.usage, e.pressed)));
+        events
+    }
+
+    #[test]
+    fn report_pressed_and_released_keys() {
+        let mut keyboard = BootKeyboard::default();
+
+        assert_eq!(
+            vec![(0x04, true)],
+            events(&mut keyboard, [0, 0, 0x04, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(
+            vec![(0x05, true)],
+            events(&mut keyboard, [0, 0, 0x04, 0x05, 0, 0, 0, 0])
+        );
+        assert_eq!(
+            vec![(0x04, false)],
+            events(&mut keyboard, [0, 0, 0x05, 0, 0, 0, 0, 0])
+        );
+        assert!(events(&mut keyboard, [0, 0, 0x05, 0, 0, 0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn report_modifiers_as_keys() {
+        let mut keyboard = BootKeyboard::default();
+
+        // Left Shift, then Right Shift replacing it.
+        assert_eq!(
+            vec![(0xE1, true)],
+            events(&mut keyboard, [0x02, 0, 0, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(
+            vec![(0xE1, false), (0xE5, true)],
+            events(&mut keyboard, [0x20, 0, 0, 0, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn ignore_roll_over_errors() {
+        let mut keyboard = BootKeyboard::default();
+        events(&mut keyboard, [0, 0, 0x04, 0, 0, 0, 0, 0]);
+
+        assert!(events(&mut keyboard, [0, 0, 1, 1, 1, 1, 1, 1]).is_empty());
+        assert!(events(&mut keyboard, [0, 0, 0x04, 0, 0, 0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn parse_mouse_movements() {
+        assert_eq!(
+            Some(MouseEvent {
+                buttons: 0x01,
+                dx: -3,
+                dy: 5
+            }),
+            parse_mouse_report(&[0x01, 0xFD, 0x05, 0x00])
+        );
+        assert_eq!(None, parse_mouse_report(&[0x01, 0xFD]));
+    }
+}
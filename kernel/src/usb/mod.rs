@@ -0,0 +1,76 @@
+//! USB through an xHCI controller on PCI, with class drivers for keyboards and mice.
+
+pub(crate) mod descriptor;
+pub(crate) mod hid;
+pub(crate) mod xhci;
+
+use crate::interrupts::{self, IntId, Trigger};
+use crate::message::{self, Message};
+use crate::pci::{self, BarKind};
+use crate::sync::SpinLock;
+
+use xhci::{Controller, Interrupter};
+
+/// Only driven from the main loop, while the interrupter is shared with the IRQ handler.
+static CONTROLLER: SpinLock<Option<Controller>> = SpinLock::new(None);
+static INTERRUPTER: SpinLock<Option<Interrupter>> = SpinLock::new(None);
+
+/// Brings up the first xHCI controller found on PCI, if any.
+pub(crate) fn init() {
+    let Some(device) = pci::find_by_class(0x0C, 0x03, 0x30) else {
+        return;
+    };
+    let Some(bar) = device
+        .bar(0)
+        .filter(|b| b.kind != BarKind::Io && b.address != 0)
+    else {
+        log::warn!("xHCI: {} has no registers mapped", device.address);
+        return;
+    };
+    let Some(intid) = pci::legacy_intid(device) else {
+        log::warn!("xHCI: {} has no interrupt pin", device.address);
+        return;
+    };
+
+    device.enable_bus_master();
+    let Some(mut controller) = (unsafe { Controller::new(bar.address as usize) }) else {
+        log::warn!("xHCI: {} did not come out of reset", device.address);
+        return;
+    };
+
+    *INTERRUPTER.lock_irqsave() = Some(controller.interrupter());
+    interrupts::register(
+        intid,
+        Trigger::Level,
+        interrupts::DEFAULT_PRIORITY,
+        handle_irq,
+    );
+
+    if controller.run().is_none() {
+        log::warn!("xHCI: {} did not start", device.address);
+        interrupts::unregister(intid);
+        return;
+    }
+
+    log::info!(
+        "xHCI: {} at {:#x}, INTID {}",
+        device.address,
+        bar.address,
+        intid
+    );
+    *CONTROLLER.lock() = Some(controller);
+}
+
+/// Handles the events posted by the controller, called from the main loop.
+pub(crate) fn process_events() {
+    if let Some(controller) = CONTROLLER.lock().as_mut() {
+        controller.process_events();
+    }
+}
+
+fn handle_irq(_: IntId) {
+    if let Some(interrupter) = INTERRUPTER.lock().as_ref() {
+        interrupter.acknowledge();
+        message::post(Message::Xhci);
+    }
+}
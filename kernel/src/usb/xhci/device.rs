@@ -0,0 +1,457 @@
+//! A device on a root hub port, set up through its default control endpoint.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::ring::{Ring, Trb, COMPLETION_SHORT_PACKET, COMPLETION_SUCCESS};
+use super::{Doorbell, Page};
+use crate::memory::zeroed;
+use crate::message::{self, Message};
+use crate::usb::descriptor::{
+    BootProtocol, Configuration, DeviceDescriptor, HidInterface, DESCRIPTOR_CONFIGURATION,
+    DESCRIPTOR_DEVICE,
+};
+use crate::usb::hid::{self, BootKeyboard};
+
+/// Device Context Index of the default control endpoint.
+const CONTROL_DCI: u8 = 1;
+
+const SPEED_FULL: u8 = 1;
+const SPEED_LOW: u8 = 2;
+const SPEED_HIGH: u8 = 3;
+const SPEED_SUPER: u8 = 4;
+
+const ENDPOINT_TYPE_CONTROL: u32 = 4;
+const ENDPOINT_TYPE_INTERRUPT_IN: u32 = 7;
+/// Retries of a transaction before the endpoint halts.
+const ERROR_COUNT: u32 = 3;
+
+const REQUEST_GET_DESCRIPTOR: u8 = 6;
+const REQUEST_SET_CONFIGURATION: u8 = 9;
+const REQUEST_SET_PROTOCOL: u8 = 0x0B;
+const REQUEST_TYPE_DEVICE_IN: u8 = 0x80;
+const REQUEST_TYPE_DEVICE_OUT: u8 = 0x00;
+const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+const PROTOCOL_BOOT: u16 = 0;
+
+/// The buffer page holds the data of control transfers first, then a report per interface.
+const REPORT_BUFFERS: usize = 2048;
+const REPORT_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum State {
+    Addressing,
+    DeviceDescriptor,
+    ConfigurationDescriptor,
+    SetConfiguration,
+    ConfiguringEndpoints,
+    /// Switching the interface at the index to the boot protocol.
+    SetProtocol(usize),
+    Running,
+    /// Has no boot interfaces, or failed to be set up.
+    Unsupported,
+}
+
+struct Interface {
+    hid: HidInterface,
+    ring: Ring,
+    keyboard: BootKeyboard,
+}
+
+pub(crate) struct UsbDevice {
+    pub(crate) slot: u8,
+    pub(crate) port: u8,
+    speed: u8,
+    context_size: usize,
+    pub(crate) state: State,
+    output: Box<Page>,
+    input: Box<Page>,
+    buffer: Box<Page>,
+    control: Ring,
+    doorbell: Doorbell,
+    interfaces: Vec<Interface>,
+}
+
+impl UsbDevice {
+    /// Creates the device with the input context for the Address Device command.
+    pub(crate) fn new(
+        slot: u8,
+        port: u8,
+        speed: u8,
+        context_size: usize,
+        doorbell: Doorbell,
+    ) -> Self {
+        let mut device = Self {
+            slot,
+            port,
+            speed,
+            context_size,
+            state: State::Addressing,
+            output: zeroed(),
+            input: zeroed(),
+            buffer: zeroed(),
+            control: Ring::new(),
+            doorbell,
+            interfaces: Vec::new(),
+        };
+
+        // Adds the slot context and the default control endpoint.
+        device.input.write_u32(4, 0b11);
+        device.write_slot_context(CONTROL_DCI);
+        let offset = device.input_endpoint_offset(CONTROL_DCI);
+        write_endpoint_context(
+            &mut device.input,
+            offset,
+            EndpointContext {
+                ty: ENDPOINT_TYPE_CONTROL,
+                max_packet_size: default_max_packet_size(speed),
+                interval: 0,
+                average_length: 8,
+                ring: device.control.address(),
+            },
+        );
+
+        device
+    }
+
+    #[inline]
+    pub(crate) fn output_context_address(&self) -> u64 {
+        self.output.address()
+    }
+
+    #[inline]
+    pub(crate) fn input_context_address(&self) -> u64 {
+        self.input.address()
+    }
+
+    /// Offset of the endpoint context in the input context, which starts with the input control
+    /// context and the slot context.
+    #[inline]
+    fn input_endpoint_offset(&self, dci: u8) -> usize {
+        (dci as usize + 1) * self.context_size
+    }
+
+    fn write_slot_context(&mut self, last_dci: u8) {
+        let offset = self.context_size;
+        self.input
+            .write_u32(offset, (self.speed as u32) << 20 | (last_dci as u32) << 27);
+        self.input.write_u32(offset + 4, (self.port as u32) << 16);
+    }
+
+    pub(crate) fn on_addressed(&mut self) {
+        self.state = State::DeviceDescriptor;
+        self.get_descriptor(DESCRIPTOR_DEVICE, 18);
+    }
+
+    pub(crate) fn on_configured(&mut self) {
+        self.set_protocol(0);
+    }
+
+    /// Handles the transfer event on one of the endpoints. Returns the Configure Endpoint command
+    /// to issue once the configuration is set.
+    pub(crate) fn on_transfer(&mut self, event: &Trb) -> Option<Trb> {
+        let code = event.completion_code();
+        if code != COMPLETION_SUCCESS && code != COMPLETION_SHORT_PACKET {
+            log::warn!(
+                "USB: Transfer on slot {} endpoint {} failed with code {}",
+                self.slot,
+                event.endpoint_id(),
+                code
+            );
+            if event.endpoint_id() == CONTROL_DCI {
+                self.state = State::Unsupported;
+            }
+            return None;
+        }
+
+        match event.endpoint_id() {
+            CONTROL_DCI => self.on_control_completed(),
+            dci => {
+                self.on_report(dci, event.residual_length() as usize);
+                None
+            }
+        }
+    }
+
+    fn on_control_completed(&mut self) -> Option<Trb> {
+        match self.state {
+            State::DeviceDescriptor => {
+                let Some(descriptor) = DeviceDescriptor::parse(self.buffer.bytes()) else {
+                    self.state = State::Unsupported;
+                    return None;
+                };
+
+                log::info!(
+                    "USB: {:04x}:{:04x} on port {}, slot {}",
+                    descriptor.vendor_id,
+                    descriptor.product_id,
+                    self.port,
+                    self.slot
+                );
+                self.state = State::ConfigurationDescriptor;
+                self.get_descriptor(DESCRIPTOR_CONFIGURATION, 255);
+            }
+            State::ConfigurationDescriptor => {
+                let Some(configuration) = Configuration::parse(self.buffer.bytes()) else {
+                    self.state = State::Unsupported;
+                    return None;
+                };
+                if configuration.hid_interfaces.is_empty() {
+                    log::info!("USB: No boot interfaces on slot {}", self.slot);
+                    self.state = State::Unsupported;
+                    return None;
+                }
+
+                self.interfaces = configuration
+                    .hid_interfaces
+                    .iter()
+                    .map(|&hid| Interface {
+                        hid,
+                        ring: Ring::new(),
+                        keyboard: BootKeyboard::default(),
+                    })
+                    .collect();
+                self.state = State::SetConfiguration;
+                self.control_transfer(
+                    REQUEST_TYPE_DEVICE_OUT,
+                    REQUEST_SET_CONFIGURATION,
+                    configuration.value as u16,
+                    0,
+                    0,
+                );
+            }
+            State::SetConfiguration => {
+                self.state = State::ConfiguringEndpoints;
+                self.prepare_endpoints();
+                return Some(Trb::configure_endpoint(
+                    self.input_context_address(),
+                    self.slot,
+                ));
+            }
+            State::SetProtocol(index) => self.set_protocol(index + 1),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Rewrites the input context to add the interrupt IN endpoints of the interfaces.
+    fn prepare_endpoints(&mut self) {
+        let last_dci = self
+            .interfaces
+            .iter()
+            .map(|i| i.hid.endpoint.dci())
+            .max()
+            .unwrap_or(CONTROL_DCI);
+
+        self.input.clear();
+        let flags = self
+            .interfaces
+            .iter()
+            .fold(1, |flags, i| flags | 1 << i.hid.endpoint.dci());
+        self.input.write_u32(4, flags);
+        self.write_slot_context(last_dci);
+
+        (0..self.interfaces.len()).for_each(|i| {
+            let endpoint = self.interfaces[i].hid.endpoint;
+            let offset = self.input_endpoint_offset(endpoint.dci());
+            write_endpoint_context(
+                &mut self.input,
+                offset,
+                EndpointContext {
+                    ty: ENDPOINT_TYPE_INTERRUPT_IN,
+                    max_packet_size: endpoint.max_packet_size,
+                    interval: interval(self.speed, endpoint.interval),
+                    average_length: endpoint.max_packet_size,
+                    ring: self.interfaces[i].ring.address(),
+                },
+            );
+        });
+    }
+
+    /// Switches the interfaces to the boot protocol one by one from the index, then starts
+    /// polling them.
+    fn set_protocol(&mut self, index: usize) {
+        let Some(interface) = self.interfaces.get(index) else {
+            self.state = State::Running;
+            (0..self.interfaces.len()).for_each(|i| self.request_report(i));
+            return;
+        };
+
+        let number = interface.hid.number as u16;
+        self.state = State::SetProtocol(index);
+        self.control_transfer(
+            REQUEST_TYPE_CLASS_INTERFACE_OUT,
+            REQUEST_SET_PROTOCOL,
+            PROTOCOL_BOOT,
+            number,
+            0,
+        );
+    }
+
+    fn report_buffer(&self, index: usize) -> (usize, usize) {
+        let offset = REPORT_BUFFERS + index * REPORT_SIZE;
+        let len = self.interfaces[index]
+            .hid
+            .endpoint
+            .max_packet_size
+            .min(REPORT_SIZE as u16);
+        (offset, len as usize)
+    }
+
+    fn request_report(&mut self, index: usize) {
+        let (offset, len) = self.report_buffer(index);
+        let address = self.buffer.address() + offset as u64;
+        let interface = &mut self.interfaces[index];
+        interface.ring.push(Trb::normal(address, len as u32));
+        self.doorbell.ring(self.slot, interface.hid.endpoint.dci());
+    }
+
+    fn on_report(&mut self, dci: u8, residual: usize) {
+        let Some(index) = self
+            .interfaces
+            .iter()
+            .position(|i| i.hid.endpoint.dci() == dci)
+        else {
+            return;
+        };
+
+        let (offset, len) = self.report_buffer(index);
+        let report = &self.buffer.bytes()[offset..offset + len.saturating_sub(residual)];
+        let interface = &mut self.interfaces[index];
+        match interface.hid.protocol {
+            BootProtocol::Keyboard => interface
+                .keyboard
+                .handle(report, |event| message::post(Message::Keyboard(event))),
+            BootProtocol::Mouse => {
+                if let Some(event) = hid::parse_mouse_report(report) {
+                    message::post(Message::Mouse(event));
+                }
+            }
+        }
+
+        self.request_report(index);
+    }
+
+    fn get_descriptor(&mut self, ty: u8, len: u16) {
+        self.control_transfer(
+            REQUEST_TYPE_DEVICE_IN,
+            REQUEST_GET_DESCRIPTOR,
+            (ty as u16) << 8,
+            0,
+            len,
+        );
+    }
+
+    /// Queues the stages of the control transfer, with the data stage from or to the buffer.
+    fn control_transfer(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: u16,
+    ) {
+        let setup = setup_packet(request_type, request, value, index, len);
+        match len {
+            0 => {
+                self.control.push(Trb::setup(setup, None));
+                self.control.push(Trb::status(false));
+            }
+            _ => {
+                let data_in = request_type & REQUEST_TYPE_DEVICE_IN != 0;
+                self.control.push(Trb::setup(setup, Some(data_in)));
+                self.control
+                    .push(Trb::data(self.buffer.address(), len as u32, data_in));
+                self.control.push(Trb::status(data_in));
+            }
+        }
+        self.doorbell.ring(self.slot, CONTROL_DCI);
+    }
+}
+
+struct EndpointContext {
+    ty: u32,
+    max_packet_size: u16,
+    /// Interval in 2^n * 125 us.
+    interval: u32,
+    average_length: u16,
+    ring: u64,
+}
+
+fn write_endpoint_context(page: &mut Page, offset: usize, context: EndpointContext) {
+    page.write_u32(offset, context.interval << 16);
+    page.write_u32(
+        offset + 4,
+        ERROR_COUNT << 1 | context.ty << 3 | (context.max_packet_size as u32) << 16,
+    );
+    // The dequeue cycle state matches the initial cycle of the ring.
+    page.write_u64(offset + 8, context.ring | 1);
+    page.write_u32(
+        offset + 16,
+        context.average_length as u32 | (context.max_packet_size as u32) << 16,
+    );
+}
+
+/// Max packet size of the default control endpoint until the device descriptor is read.
+fn default_max_packet_size(speed: u8) -> u16 {
+    match speed {
+        SPEED_SUPER => 512,
+        SPEED_HIGH => 64,
+        _ => 8,
+    }
+}
+
+/// Converts `bInterval` of an interrupt endpoint, in frames for low and full speed and in
+/// 2^(n-1) microframes otherwise, into the exponent of microframes.
+fn interval(speed: u8, interval: u8) -> u32 {
+    match speed {
+        SPEED_LOW | SPEED_FULL => (interval.max(1) as u32).ilog2() + 3,
+        _ => interval.clamp(1, 16) as u32 - 1,
+    }
+}
+
+fn setup_packet(request_type: u8, request: u8, value: u16, index: u16, len: u16) -> u64 {
+    request_type as u64
+        | (request as u64) << 8
+        | (value as u64) << 16
+        | (index as u64) << 32
+        | (len as u64) << 48
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_intervals() {
+        assert_eq!(3, interval(SPEED_FULL, 1));
+        assert_eq!(6, interval(SPEED_LOW, 10));
+        assert_eq!(6, interval(SPEED_HIGH, 7));
+        assert_eq!(0, interval(SPEED_SUPER, 0));
+    }
+
+    #[test]
+    fn encode_setup_packets() {
+        assert_eq!(
+            0x0012_0000_0100_0680,
+            setup_packet(
+                REQUEST_TYPE_DEVICE_IN,
+                REQUEST_GET_DESCRIPTOR,
+                (DESCRIPTOR_DEVICE as u16) << 8,
+                0,
+                18
+            )
+        );
+        assert_eq!(
+            0x0000_0001_0000_0B21,
+            setup_packet(
+                REQUEST_TYPE_CLASS_INTERFACE_OUT,
+                REQUEST_SET_PROTOCOL,
+                PROTOCOL_BOOT,
+                1,
+                0
+            )
+        );
+    }
+}
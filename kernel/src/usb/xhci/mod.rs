@@ -0,0 +1,450 @@
+//! xHCI host controller, which enumerates the devices on the root hub ports one at a time.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+pub(crate) mod device;
+pub(crate) mod ring;
+
+use crate::memory::zeroed;
+use crate::mmio::Mmio;
+
+use device::UsbDevice;
+use ring::{
+    EventRing, Ring, Trb, COMPLETION_SUCCESS, TRB_COMMAND_COMPLETION_EVENT,
+    TRB_PORT_STATUS_CHANGE_EVENT, TRB_TRANSFER_EVENT,
+};
+
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+const CAPLENGTH: usize = 0x00;
+const HCSPARAMS1: usize = 0x04;
+const HCSPARAMS2: usize = 0x08;
+const HCCPARAMS1: usize = 0x10;
+const DBOFF: usize = 0x14;
+const RTSOFF: usize = 0x18;
+
+const USBCMD: usize = 0x00;
+const USBSTS: usize = 0x04;
+const CRCR: usize = 0x18;
+const DCBAAP: usize = 0x30;
+const CONFIG: usize = 0x38;
+const PORTSC: usize = 0x400;
+const PORT_REGISTERS_SIZE: usize = 0x10;
+
+/// Offset of the first interrupter in the runtime registers.
+const INTERRUPTER0: usize = 0x20;
+const IMAN: usize = 0x00;
+const IMOD: usize = 0x04;
+const ERSTSZ: usize = 0x08;
+const ERSTBA: usize = 0x10;
+const ERDP: usize = 0x18;
+
+const USBCMD_RUN: u32 = 1 << 0;
+const USBCMD_RESET: u32 = 1 << 1;
+const USBCMD_INTERRUPT_ENABLE: u32 = 1 << 2;
+const USBSTS_HALTED: u32 = 1 << 0;
+const USBSTS_EVENT_INTERRUPT: u32 = 1 << 3;
+const USBSTS_NOT_READY: u32 = 1 << 11;
+const HCCPARAMS1_CONTEXT_SIZE: u32 = 1 << 2;
+const CRCR_RING_CYCLE: u64 = 1 << 0;
+const IMAN_PENDING: u32 = 1 << 0;
+const IMAN_ENABLE: u32 = 1 << 1;
+const ERDP_HANDLER_BUSY: u64 = 1 << 3;
+
+const PORTSC_CONNECTED: u32 = 1 << 0;
+const PORTSC_ENABLED: u32 = 1 << 1;
+const PORTSC_RESET: u32 = 1 << 4;
+const PORTSC_CONNECT_CHANGE: u32 = 1 << 17;
+const PORTSC_RESET_CHANGE: u32 = 1 << 21;
+/// Bits cleared by writing one, which are the change bits and the enabled bit.
+const PORTSC_WRITE_ONE_TO_CLEAR: u32 = 0x7F << 17 | PORTSC_ENABLED;
+/// Bits which do something when written with one, other than the bits to clear.
+const PORTSC_WRITE_ONE_TO_ACT: u32 = PORTSC_RESET | 1 << 16 | 1 << 31;
+
+/// Slots enabled at most, which is plenty for a mouse and a keyboard.
+const MAX_SLOTS: u8 = 16;
+/// Moderates interrupts to one per 1 ms, in units of 250 ns.
+const INTERRUPT_MODERATION: u32 = 4000;
+const RETRIES: usize = 10_000_000;
+
+/// A page of memory shared with the controller, which never crosses a page boundary.
+#[repr(C, align(4096))]
+pub(crate) struct Page([u8; PAGE_SIZE]);
+
+impl Page {
+    #[inline]
+    pub(crate) fn address(&self) -> u64 {
+        self.0.as_ptr() as u64
+    }
+
+    #[inline]
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.fill(0);
+    }
+
+    pub(crate) fn write_u32(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u64(&mut self, offset: usize, value: u64) {
+        self.0[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// The doorbell registers, rung to make the controller look at a ring.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Doorbell {
+    registers: Mmio,
+}
+
+impl Doorbell {
+    /// Rings the doorbell of the slot, where slot zero with target zero is the command ring.
+    #[inline]
+    pub(crate) fn ring(&self, slot: u8, target: u8) {
+        self.registers
+            .write::<u32>(slot as usize * 4, target as u32);
+    }
+}
+
+/// The registers touched by the interrupt handler, apart from the rest of the controller.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Interrupter {
+    operational: Mmio,
+    registers: Mmio,
+}
+
+impl Interrupter {
+    /// Clears the pending interrupt, which deasserts the interrupt line.
+    pub(crate) fn acknowledge(&self) {
+        let iman = self.registers.read::<u32>(IMAN);
+        self.registers.write(IMAN, iman | IMAN_PENDING);
+        self.operational.write(USBSTS, USBSTS_EVENT_INTERRUPT);
+    }
+}
+
+/// Commands waiting for their completion events.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Command {
+    EnableSlot { port: u8 },
+    AddressDevice { slot: u8 },
+    ConfigureEndpoint { slot: u8 },
+}
+
+pub(crate) struct Controller {
+    operational: Mmio,
+    interrupter: Interrupter,
+    doorbell: Doorbell,
+    context_size: usize,
+    max_ports: u8,
+    device_contexts: Box<Page>,
+    _scratchpads: Vec<Box<Page>>,
+    commands: Ring,
+    events: EventRing,
+    pending: Vec<(u64, Command)>,
+    devices: Vec<Option<UsbDevice>>,
+    waiting_ports: VecDeque<u8>,
+    resetting: Option<u8>,
+}
+
+impl Controller {
+    /// Resets the controller and sets up its rings, leaving it halted until `run`.
+    ///
+    /// # Safety
+    /// `base` must be the address of the registers of an xHC, which are identity mapped.
+    pub(crate) unsafe fn new(base: usize) -> Option<Self> {
+        let capability = Mmio::new(base);
+        let operational = capability.offset(capability.read::<u8>(CAPLENGTH) as usize);
+        let runtime = capability.offset(capability.read::<u32>(RTSOFF) as usize & !0x1F);
+        let doorbell = Doorbell {
+            registers: capability.offset(capability.read::<u32>(DBOFF) as usize & !0b11),
+        };
+
+        let command = operational.read::<u32>(USBCMD);
+        operational.write(USBCMD, command & !USBCMD_RUN);
+        wait_until(|| operational.read::<u32>(USBSTS) & USBSTS_HALTED != 0)?;
+
+        operational.write(USBCMD, USBCMD_RESET);
+        wait_until(|| {
+            operational.read::<u32>(USBCMD) & USBCMD_RESET == 0
+                && operational.read::<u32>(USBSTS) & USBSTS_NOT_READY == 0
+        })?;
+
+        let params1 = capability.read::<u32>(HCSPARAMS1);
+        let max_slots = (params1 as u8).min(MAX_SLOTS);
+        let max_ports = (params1 >> 24) as u8;
+        operational.write::<u32>(CONFIG, max_slots as u32);
+
+        let mut device_contexts = zeroed::<Page>();
+        let params2 = capability.read::<u32>(HCSPARAMS2);
+        let scratchpad_count = ((params2 >> 21) & 0x1F) << 5 | (params2 >> 27) & 0x1F;
+        let scratchpads = (0..scratchpad_count)
+            .map(|_| zeroed::<Page>())
+            .collect::<Vec<_>>();
+        if !scratchpads.is_empty() {
+            // The array of the scratchpad buffers is pointed to by the first entry.
+            let mut array = zeroed::<Page>();
+            scratchpads
+                .iter()
+                .enumerate()
+                .for_each(|(i, page)| array.write_u64(i * 8, page.address()));
+            device_contexts.write_u64(0, array.address());
+            Box::leak(array);
+        }
+        operational.write::<u64>(DCBAAP, device_contexts.address());
+
+        let commands = Ring::new();
+        operational.write::<u64>(CRCR, commands.address() | CRCR_RING_CYCLE);
+
+        let events = EventRing::new();
+        let interrupter = runtime.offset(INTERRUPTER0);
+        interrupter.write::<u32>(ERSTSZ, 1);
+        interrupter.write::<u64>(ERDP, events.dequeue_pointer());
+        interrupter.write::<u64>(ERSTBA, events.table_address());
+        interrupter.write::<u32>(IMOD, INTERRUPT_MODERATION);
+        interrupter.write::<u32>(IMAN, IMAN_ENABLE | IMAN_PENDING);
+
+        let context_size = match capability.read::<u32>(HCCPARAMS1) & HCCPARAMS1_CONTEXT_SIZE {
+            0 => 32,
+            _ => 64,
+        };
+
+        Some(Self {
+            operational,
+            interrupter: Interrupter {
+                operational,
+                registers: interrupter,
+            },
+            doorbell,
+            context_size,
+            max_ports,
+            device_contexts,
+            _scratchpads: scratchpads,
+            commands,
+            events,
+            pending: Vec::new(),
+            devices: (0..=max_slots).map(|_| None).collect(),
+            waiting_ports: VecDeque::new(),
+            resetting: None,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn interrupter(&self) -> Interrupter {
+        self.interrupter
+    }
+
+    /// Starts the controller, then resets the ports which already have devices connected.
+    pub(crate) fn run(&mut self) -> Option<()> {
+        let command = self.operational.read::<u32>(USBCMD);
+        self.operational
+            .write(USBCMD, command | USBCMD_RUN | USBCMD_INTERRUPT_ENABLE);
+        wait_until(|| self.operational.read::<u32>(USBSTS) & USBSTS_HALTED == 0)?;
+
+        (1..=self.max_ports).for_each(|port| {
+            let status = self.port_status(port);
+            if status & PORTSC_CONNECTED != 0 {
+                self.write_port(port, neutral(status) | PORTSC_CONNECT_CHANGE);
+                self.waiting_ports.push_back(port);
+            }
+        });
+        self.reset_next_port();
+
+        Some(())
+    }
+
+    /// Handles the events the controller has posted so far.
+    pub(crate) fn process_events(&mut self) {
+        while let Some(event) = self.events.pop() {
+            match event.ty() {
+                TRB_PORT_STATUS_CHANGE_EVENT => self.on_port_status_change(event.port_id()),
+                TRB_COMMAND_COMPLETION_EVENT => self.on_command_completion(&event),
+                TRB_TRANSFER_EVENT => self.on_transfer(&event),
+                ty => log::warn!("xHCI: Unexpected event of type {}", ty),
+            }
+        }
+
+        self.interrupter
+            .registers
+            .write::<u64>(ERDP, self.events.dequeue_pointer() | ERDP_HANDLER_BUSY);
+    }
+
+    fn port(&self, port: u8) -> Mmio {
+        self.operational
+            .offset(PORTSC + (port as usize - 1) * PORT_REGISTERS_SIZE)
+    }
+
+    fn port_status(&self, port: u8) -> u32 {
+        self.port(port).read(0)
+    }
+
+    fn write_port(&self, port: u8, value: u32) {
+        self.port(port).write(0, value)
+    }
+
+    /// Resets the next port in the queue, unless one is in the middle of enumeration. Devices
+    /// answer at the default address until addressed, so only one of them is enumerated at a time.
+    fn reset_next_port(&mut self) {
+        if self.resetting.is_some() {
+            return;
+        }
+        let Some(port) = self.waiting_ports.pop_front() else {
+            return;
+        };
+
+        let status = self.port_status(port);
+        self.write_port(port, neutral(status) | PORTSC_RESET);
+        self.resetting = Some(port);
+    }
+
+    fn finish_enumeration(&mut self) {
+        self.resetting = None;
+        self.reset_next_port();
+    }
+
+    fn on_port_status_change(&mut self, port: u8) {
+        let status = self.port_status(port);
+        self.write_port(port, neutral(status) | status & PORTSC_WRITE_ONE_TO_CLEAR);
+
+        if self.resetting == Some(port) {
+            if status & PORTSC_RESET_CHANGE == 0 {
+                return;
+            }
+            match status & PORTSC_ENABLED {
+                0 => {
+                    log::warn!("xHCI: Port {} is not enabled after reset", port);
+                    self.finish_enumeration();
+                }
+                _ => self.push_command(Trb::enable_slot(), Command::EnableSlot { port }),
+            }
+        } else if status & PORTSC_CONNECT_CHANGE != 0
+            && status & PORTSC_CONNECTED != 0
+            && !self.waiting_ports.contains(&port)
+        {
+            self.waiting_ports.push_back(port);
+            self.reset_next_port();
+        }
+    }
+
+    fn on_command_completion(&mut self, event: &Trb) {
+        let Some(index) = self.pending.iter().position(|(a, _)| *a == event.parameter) else {
+            log::warn!(
+                "xHCI: Completion of unknown command at {:#x}",
+                event.parameter
+            );
+            return;
+        };
+
+        let (_, command) = self.pending.swap_remove(index);
+        if event.completion_code() != COMPLETION_SUCCESS {
+            log::warn!(
+                "xHCI: {:?} failed with code {}",
+                command,
+                event.completion_code()
+            );
+            if !matches!(command, Command::ConfigureEndpoint { .. }) {
+                self.finish_enumeration();
+            }
+            return;
+        }
+
+        match command {
+            Command::EnableSlot { port } => {
+                let slot = event.slot_id();
+                let speed = ((self.port_status(port) >> 10) & 0xF) as u8;
+                let device = UsbDevice::new(slot, port, speed, self.context_size, self.doorbell);
+
+                self.device_contexts
+                    .write_u64(slot as usize * 8, device.output_context_address());
+                let trb = Trb::address_device(device.input_context_address(), slot);
+                self.devices[slot as usize] = Some(device);
+                self.push_command(trb, Command::AddressDevice { slot });
+            }
+            Command::AddressDevice { slot } => {
+                if let Some(device) = self.device(slot) {
+                    device.on_addressed();
+                }
+                self.finish_enumeration();
+            }
+            Command::ConfigureEndpoint { slot } => {
+                if let Some(device) = self.device(slot) {
+                    device.on_configured();
+                }
+            }
+        }
+    }
+
+    fn on_transfer(&mut self, event: &Trb) {
+        let slot = event.slot_id();
+        let Some(device) = self.device(slot) else {
+            return;
+        };
+
+        if let Some(trb) = device.on_transfer(event) {
+            self.push_command(trb, Command::ConfigureEndpoint { slot });
+        }
+    }
+
+    fn device(&mut self, slot: u8) -> Option<&mut UsbDevice> {
+        self.devices.get_mut(slot as usize)?.as_mut()
+    }
+
+    fn push_command(&mut self, trb: Trb, command: Command) {
+        let address = self.commands.push(trb);
+        self.pending.push((address, command));
+        self.doorbell.ring(0, 0);
+    }
+}
+
+/// The value to write back to PORTSC without clearing or triggering anything.
+#[inline]
+fn neutral(status: u32) -> u32 {
+    status & !(PORTSC_WRITE_ONE_TO_CLEAR | PORTSC_WRITE_ONE_TO_ACT)
+}
+
+fn wait_until<F>(f: F) -> Option<()>
+where
+    F: Fn() -> bool,
+{
+    (0..RETRIES).find(|_| {
+        core::hint::spin_loop();
+        f()
+    })?;
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_back_port_status_neutrally() {
+        // Connected, enabled, powered at high speed, with the connect and reset changes.
+        let status = PORTSC_CONNECTED
+            | PORTSC_ENABLED
+            | 1 << 9
+            | 3 << 10
+            | PORTSC_CONNECT_CHANGE
+            | PORTSC_RESET_CHANGE;
+
+        assert_eq!(PORTSC_CONNECTED | 1 << 9 | 3 << 10, neutral(status));
+    }
+
+    #[test]
+    fn allocate_aligned_pages() {
+        let mut page = zeroed::<Page>();
+        assert_eq!(0, page.address() % PAGE_SIZE as u64);
+        assert!(page.bytes().iter().all(|&b| b == 0));
+
+        page.write_u64(8, 0x1122_3344_5566_7788);
+        assert_eq!(
+            &[0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+            &page.bytes()[8..16]
+        );
+    }
+}
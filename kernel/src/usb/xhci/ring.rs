@@ -0,0 +1,336 @@
+use alloc::boxed::Box;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::memory::zeroed;
+
+pub(crate) const TRBS_PER_SEGMENT: usize = 256;
+
+pub(crate) const TRB_NORMAL: u8 = 1;
+pub(crate) const TRB_SETUP_STAGE: u8 = 2;
+pub(crate) const TRB_DATA_STAGE: u8 = 3;
+pub(crate) const TRB_STATUS_STAGE: u8 = 4;
+pub(crate) const TRB_LINK: u8 = 6;
+pub(crate) const TRB_ENABLE_SLOT: u8 = 9;
+pub(crate) const TRB_ADDRESS_DEVICE: u8 = 11;
+pub(crate) const TRB_CONFIGURE_ENDPOINT: u8 = 12;
+pub(crate) const TRB_TRANSFER_EVENT: u8 = 32;
+pub(crate) const TRB_COMMAND_COMPLETION_EVENT: u8 = 33;
+pub(crate) const TRB_PORT_STATUS_CHANGE_EVENT: u8 = 34;
+
+pub(crate) const COMPLETION_SUCCESS: u8 = 1;
+pub(crate) const COMPLETION_SHORT_PACKET: u8 = 13;
+
+const CONTROL_CYCLE: u32 = 1 << 0;
+const CONTROL_TOGGLE_CYCLE: u32 = 1 << 1;
+const CONTROL_INTERRUPT_ON_SHORT_PACKET: u32 = 1 << 2;
+const CONTROL_INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const CONTROL_IMMEDIATE_DATA: u32 = 1 << 6;
+const CONTROL_DIRECTION_IN: u32 = 1 << 16;
+
+/// Transfer types in a setup stage TRB.
+const TRANSFER_TYPE_NO_DATA: u32 = 0;
+const TRANSFER_TYPE_OUT: u32 = 2;
+const TRANSFER_TYPE_IN: u32 = 3;
+
+/// Transfer Request Block, the unit of every ring.
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Trb {
+    pub(crate) parameter: u64,
+    pub(crate) status: u32,
+    pub(crate) control: u32,
+}
+
+impl Trb {
+    const fn new(ty: u8, parameter: u64, status: u32, control: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: (ty as u32) << 10 | control,
+        }
+    }
+
+    pub(crate) const fn normal(buffer: u64, len: u32) -> Self {
+        Self::new(
+            TRB_NORMAL,
+            buffer,
+            len,
+            CONTROL_INTERRUPT_ON_SHORT_PACKET | CONTROL_INTERRUPT_ON_COMPLETION,
+        )
+    }
+
+    /// The setup stage of a control transfer, whose data stage is IN, OUT or none by `data_in`.
+    pub(crate) const fn setup(request: u64, data_in: Option<bool>) -> Self {
+        let transfer_type = match data_in {
+            Some(true) => TRANSFER_TYPE_IN,
+            Some(false) => TRANSFER_TYPE_OUT,
+            None => TRANSFER_TYPE_NO_DATA,
+        };
+        Self::new(
+            TRB_SETUP_STAGE,
+            request,
+            8,
+            CONTROL_IMMEDIATE_DATA | transfer_type << 16,
+        )
+    }
+
+    pub(crate) const fn data(buffer: u64, len: u32, data_in: bool) -> Self {
+        let direction = if data_in { CONTROL_DIRECTION_IN } else { 0 };
+        Self::new(TRB_DATA_STAGE, buffer, len, direction)
+    }
+
+    /// The status stage, whose direction is opposite to the data stage, or IN if there is none.
+    pub(crate) const fn status(data_in: bool) -> Self {
+        let direction = if data_in { 0 } else { CONTROL_DIRECTION_IN };
+        Self::new(
+            TRB_STATUS_STAGE,
+            0,
+            0,
+            direction | CONTROL_INTERRUPT_ON_COMPLETION,
+        )
+    }
+
+    pub(crate) const fn link(segment: u64) -> Self {
+        Self::new(TRB_LINK, segment, 0, CONTROL_TOGGLE_CYCLE)
+    }
+
+    pub(crate) const fn enable_slot() -> Self {
+        Self::new(TRB_ENABLE_SLOT, 0, 0, 0)
+    }
+
+    pub(crate) const fn address_device(input_context: u64, slot: u8) -> Self {
+        Self::new(TRB_ADDRESS_DEVICE, input_context, 0, (slot as u32) << 24)
+    }
+
+    pub(crate) const fn configure_endpoint(input_context: u64, slot: u8) -> Self {
+        Self::new(
+            TRB_CONFIGURE_ENDPOINT,
+            input_context,
+            0,
+            (slot as u32) << 24,
+        )
+    }
+
+    #[inline]
+    pub(crate) fn ty(&self) -> u8 {
+        ((self.control >> 10) & 0x3F) as u8
+    }
+
+    #[inline]
+    fn cycle(&self) -> bool {
+        self.control & CONTROL_CYCLE != 0
+    }
+
+    #[inline]
+    pub(crate) fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    /// Device Context Index of the endpoint in a transfer event.
+    #[inline]
+    pub(crate) fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1F) as u8
+    }
+
+    /// Root hub port number in a port status change event.
+    #[inline]
+    pub(crate) fn port_id(&self) -> u8 {
+        (self.parameter >> 24) as u8
+    }
+
+    #[inline]
+    pub(crate) fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// Bytes not transferred in a transfer event.
+    #[inline]
+    pub(crate) fn residual_length(&self) -> u32 {
+        self.status & 0xFF_FFFF
+    }
+}
+
+#[repr(C, align(4096))]
+struct Segment([Trb; TRBS_PER_SEGMENT]);
+
+/// A command or transfer ring of a single segment, linked back to its head.
+pub(crate) struct Ring {
+    segment: Box<Segment>,
+    index: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub(crate) fn new() -> Self {
+        Self {
+            segment: zeroed(),
+            index: 0,
+            cycle: true,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn address(&self) -> u64 {
+        self.segment.0.as_ptr() as u64
+    }
+
+    fn write(&mut self, trb: Trb) {
+        let slot = &mut self.segment.0[self.index];
+        slot.parameter = trb.parameter;
+        slot.status = trb.status;
+
+        // The controller owns the TRB as soon as the cycle bit flips, so it must be written last.
+        fence(Ordering::Release);
+        let control = (trb.control & !CONTROL_CYCLE) | self.cycle as u32;
+        unsafe { write_volatile(&mut slot.control, control) };
+    }
+
+    /// Enqueues the TRB and returns its address, which events refer to.
+    pub(crate) fn push(&mut self, trb: Trb) -> u64 {
+        let address = self.address() + (self.index * core::mem::size_of::<Trb>()) as u64;
+        self.write(trb);
+        self.index += 1;
+
+        if self.index == TRBS_PER_SEGMENT - 1 {
+            self.write(Trb::link(self.address()));
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+
+        address
+    }
+}
+
+/// An entry of the Event Ring Segment Table.
+#[repr(C, align(64))]
+pub(crate) struct SegmentTableEntry {
+    base: u64,
+    size: u32,
+    _reserved: u32,
+}
+
+/// The event ring of an interrupter, consumed by the software.
+pub(crate) struct EventRing {
+    segment: Box<Segment>,
+    table: Box<SegmentTableEntry>,
+    index: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub(crate) fn new() -> Self {
+        let segment = zeroed::<Segment>();
+        let mut table = zeroed::<SegmentTableEntry>();
+        table.base = segment.0.as_ptr() as u64;
+        table.size = TRBS_PER_SEGMENT as u32;
+
+        Self {
+            segment,
+            table,
+            index: 0,
+            cycle: true,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn table_address(&self) -> u64 {
+        &*self.table as *const _ as u64
+    }
+
+    /// Address of the next TRB to consume, written to ERDP.
+    #[inline]
+    pub(crate) fn dequeue_pointer(&self) -> u64 {
+        self.segment.0.as_ptr() as u64 + (self.index * core::mem::size_of::<Trb>()) as u64
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Trb> {
+        let trb = unsafe { read_volatile(&self.segment.0[self.index]) };
+        if trb.cycle() != self.cycle {
+            return None;
+        }
+
+        fence(Ordering::Acquire);
+        self.index += 1;
+        if self.index == TRBS_PER_SEGMENT {
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+
+        Some(trb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_around_with_link() {
+        let mut ring = Ring::new();
+        let head = ring.address();
+
+        (0..TRBS_PER_SEGMENT - 1).for_each(|i| {
+            assert_eq!(head + i as u64 * 16, ring.push(Trb::enable_slot()));
+        });
+
+        let link = ring.segment.0[TRBS_PER_SEGMENT - 1];
+        assert_eq!(TRB_LINK, link.ty());
+        assert_eq!(head, link.parameter);
+        assert!(link.cycle());
+
+        // The cycle bit is flipped after wrapping around.
+        assert_eq!(head, ring.push(Trb::enable_slot()));
+        assert!(!ring.segment.0[0].cycle());
+    }
+
+    #[test]
+    fn consume_events_by_cycle() {
+        let mut events = EventRing::new();
+        assert_eq!(None, events.pop());
+
+        events.segment.0[0] = Trb::new(TRB_PORT_STATUS_CHANGE_EVENT, 3 << 24, 0, 1);
+        let event = events.pop().unwrap();
+        assert_eq!(TRB_PORT_STATUS_CHANGE_EVENT, event.ty());
+        assert_eq!(3, event.port_id());
+        assert_eq!(None, events.pop());
+        assert_eq!(
+            events.segment.0.as_ptr() as u64 + 16,
+            events.dequeue_pointer()
+        );
+    }
+
+    #[test]
+    fn toggle_event_cycle_after_wrapping() {
+        let mut events = EventRing::new();
+        events
+            .segment
+            .0
+            .iter_mut()
+            .for_each(|trb| *trb = Trb::new(TRB_TRANSFER_EVENT, 0, 0, 1));
+
+        assert_eq!(
+            TRBS_PER_SEGMENT,
+            core::iter::from_fn(|| events.pop()).count()
+        );
+        assert!(!events.cycle);
+
+        events.segment.0[0].control &= !CONTROL_CYCLE;
+        assert!(events.pop().is_some());
+    }
+
+    #[test]
+    fn build_control_transfer() {
+        let setup = Trb::setup(0x0012_0000_0100_0680, Some(true));
+        assert_eq!(TRB_SETUP_STAGE, setup.ty());
+        assert_eq!(8, setup.status);
+        assert_eq!(TRANSFER_TYPE_IN, (setup.control >> 16) & 0b11);
+
+        assert_ne!(
+            0,
+            Trb::data(0x1000, 18, true).control & CONTROL_DIRECTION_IN
+        );
+        assert_eq!(0, Trb::status(true).control & CONTROL_DIRECTION_IN);
+        assert_ne!(0, Trb::status(false).control & CONTROL_DIRECTION_IN);
+    }
+}
@@ -0,0 +1,342 @@
+//! virtio-input, whose events are those of evdev on Linux, turned into the kernel messages.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::ptr::read_volatile;
+
+use crate::memory::zeroed;
+use crate::message::{self, KeyEvent, Message, MouseEvent, PointerEvent};
+use crate::mmio::Mmio;
+
+use super::queue::{Virtqueue, MAX_QUEUE_SIZE};
+use super::{InterruptStatus, Transport};
+
+const EVENT_QUEUE: u16 = 0;
+
+const CONFIG_SELECT: usize = 0;
+const CONFIG_SUBSEL: usize = 1;
+const CONFIG_SIZE: usize = 2;
+const CONFIG_DATA: usize = 8;
+const CONFIG_ID_NAME: u8 = 0x01;
+const CONFIG_ABS_INFO: u8 = 0x12;
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0;
+const REL_Y: u16 = 1;
+const ABS_X: u16 = 0;
+const ABS_Y: u16 = 1;
+const BTN_LEFT: u16 = 0x110;
+const BTN_MIDDLE: u16 = 0x112;
+const KEY_REPEATED: u32 = 2;
+
+/// Usage IDs of the modifier keys, from Left Control to Right GUI, in the order of the bits.
+const MODIFIER_USAGES: u8 = 0xE0;
+
+/// Range of the absolute axes unless the device tells, which is what QEMU's tablet reports.
+const DEFAULT_ABS_RANGE: (u32, u32) = (0, 0x7FFF);
+
+/// HID usage IDs of the evdev key codes, or zero if none.
+#[rustfmt::skip]
+const USAGES: [u8; 128] = [
+    0x00, 0x29, 0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x2D, 0x2E, 0x2A, 0x2B,
+    0x14, 0x1A, 0x08, 0x15, 0x17, 0x1C, 0x18, 0x0C, 0x12, 0x13, 0x2F, 0x30, 0x28, 0xE0, 0x04, 0x16,
+    0x07, 0x09, 0x0A, 0x0B, 0x0D, 0x0E, 0x0F, 0x33, 0x34, 0x35, 0xE1, 0x31, 0x1D, 0x1B, 0x06, 0x19,
+    0x05, 0x11, 0x10, 0x36, 0x37, 0x38, 0xE5, 0x55, 0xE2, 0x2C, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+    0x3F, 0x40, 0x41, 0x42, 0x43, 0x53, 0x47, 0x5F, 0x60, 0x61, 0x56, 0x5C, 0x5D, 0x5E, 0x57, 0x59,
+    0x5A, 0x5B, 0x62, 0x63, 0x00, 0x94, 0x64, 0x44, 0x45, 0x87, 0x92, 0x93, 0x8A, 0x88, 0x8B, 0x8C,
+    0x58, 0xE4, 0x54, 0x46, 0xE6, 0x00, 0x4A, 0x52, 0x4B, 0x50, 0x4F, 0x4D, 0x51, 0x4E, 0x49, 0x4C,
+    0x00, 0x7F, 0x81, 0x80, 0x66, 0x67, 0x00, 0x48, 0x00, 0x85, 0x90, 0x91, 0x89, 0xE3, 0xE7, 0x65,
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct InputEvent {
+    pub(crate) ty: u16,
+    pub(crate) code: u16,
+    pub(crate) value: u32,
+}
+
+/// Turns evdev events into messages. Keys are sent as they come, while the pointer is sent once
+/// per `SYN_REPORT`.
+pub(crate) struct Decoder {
+    modifiers: u8,
+    buttons: u8,
+    motion: (i32, i32),
+    position: (u16, u16),
+    ranges: [(u32, u32); 2],
+    absolute: bool,
+    changed: bool,
+}
+
+impl Decoder {
+    pub(crate) fn new(x_range: (u32, u32), y_range: (u32, u32)) -> Self {
+        Self {
+            modifiers: 0,
+            buttons: 0,
+            motion: (0, 0),
+            position: (0, 0),
+            ranges: [x_range, y_range],
+            absolute: false,
+            changed: false,
+        }
+    }
+
+    pub(crate) fn decode(&mut self, event: InputEvent) -> Option<Message> {
+        match (event.ty, event.code) {
+            (EV_KEY, BTN_LEFT..=BTN_MIDDLE) => {
+                let bit = 1 << (event.code - BTN_LEFT);
+                match event.value {
+                    0 => self.buttons &= !bit,
+                    _ => self.buttons |= bit,
+                }
+                self.changed = true;
+                None
+            }
+            (EV_KEY, code) => {
+                let usage = *USAGES.get(code as usize).filter(|&&u| u != 0)?;
+                if event.value == KEY_REPEATED {
+                    return None;
+                }
+
+                let pressed = event.value != 0;
+                if let Some(bit) = usage.checked_sub(MODIFIER_USAGES).filter(|&b| b < 8) {
+                    match pressed {
+                        true => self.modifiers |= 1 << bit,
+                        false => self.modifiers &= !(1 << bit),
+                    }
+                }
+
+                Some(Message::Keyboard(KeyEvent {
+                    modifiers: self.modifiers,
+                    usage,
+                    pressed,
+                }))
+            }
+            (EV_REL, REL_X) => {
+                self.motion.0 += event.value as i32;
+                self.changed = true;
+                None
+            }
+            (EV_REL, REL_Y) => {
+                self.motion.1 += event.value as i32;
+                self.changed = true;
+                None
+            }
+            (EV_ABS, ABS_X) => {
+                self.position.0 = scale(event.value, self.ranges[0]);
+                self.absolute = true;
+                self.changed = true;
+                None
+            }
+            (EV_ABS, ABS_Y) => {
+                self.position.1 = scale(event.value, self.ranges[1]);
+                self.absolute = true;
+                self.changed = true;
+                None
+            }
+            (EV_SYN, SYN_REPORT) if self.changed => {
+                self.changed = false;
+                let message = match self.absolute {
+                    true => Message::Pointer(PointerEvent {
+                        buttons: self.buttons,
+                        x: self.position.0,
+                        y: self.position.1,
+                    }),
+                    false => Message::Mouse(MouseEvent {
+                        buttons: self.buttons,
+                        dx: self.motion.0,
+                        dy: self.motion.1,
+                    }),
+                };
+                self.motion = (0, 0);
+                Some(message)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Scales the value in the range to the whole range of `u16`.
+fn scale(value: u32, (min, max): (u32, u32)) -> u16 {
+    let span = max.saturating_sub(min).max(1) as u64;
+    let value = value.clamp(min, max).saturating_sub(min) as u64;
+    (value * u16::MAX as u64 / span) as u16
+}
+
+/// A keyboard, a mouse or a tablet, which all deliver evdev events in the event queue.
+pub(crate) struct InputDevice {
+    transport: Box<dyn Transport>,
+    queue: Virtqueue,
+    events: Box<[InputEvent; MAX_QUEUE_SIZE as usize]>,
+    decoder: Decoder,
+    name: String,
+}
+
+impl InputDevice {
+    pub(crate) fn new(transport: Box<dyn Transport>) -> Option<Self> {
+        super::negotiate(&*transport, 0)?;
+
+        let size = transport.max_queue_size(EVENT_QUEUE).min(MAX_QUEUE_SIZE);
+        let mut queue = Virtqueue::new(size);
+        if size == 0 || transport.setup_queue(EVENT_QUEUE, &queue).is_none() {
+            super::fail(&*transport);
+            return None;
+        }
+
+        let events = zeroed::<[InputEvent; MAX_QUEUE_SIZE as usize]>();
+        (0..queue.size()).for_each(|id| {
+            let event = &events[id as usize];
+            queue.add(id, event as *const _ as u64, 8, true);
+        });
+
+        let config = transport.config();
+        let decoder = Decoder::new(
+            abs_range(config, ABS_X).unwrap_or(DEFAULT_ABS_RANGE),
+            abs_range(config, ABS_Y).unwrap_or(DEFAULT_ABS_RANGE),
+        );
+        let name = name(config);
+
+        super::finish(&*transport);
+        transport.notify(EVENT_QUEUE);
+
+        Some(Self {
+            transport,
+            queue,
+            events,
+            decoder,
+            name,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub(crate) fn interrupt_status(&self) -> InterruptStatus {
+        self.transport.interrupt_status()
+    }
+
+    /// Posts the events the device has written, then hands the buffers back.
+    pub(crate) fn process_events(&mut self) {
+        let mut used = false;
+        while let Some((id, _)) = self.queue.pop_used() {
+            let event = unsafe { read_volatile(&self.events[id as usize]) };
+            if let Some(message) = self.decoder.decode(event) {
+                message::post(message);
+            }
+
+            let address = &self.events[id as usize] as *const _ as u64;
+            self.queue.add(id, address, 8, true);
+            used = true;
+        }
+
+        if used {
+            self.transport.notify(EVENT_QUEUE);
+        }
+    }
+}
+
+/// Selects the configuration, returning the size of the data.
+fn select(config: Mmio, select: u8, subsel: u8) -> usize {
+    config.write::<u8>(CONFIG_SELECT, select);
+    config.write::<u8>(CONFIG_SUBSEL, subsel);
+    config.read::<u8>(CONFIG_SIZE) as usize
+}
+
+fn name(config: Mmio) -> String {
+    let size = select(config, CONFIG_ID_NAME, 0);
+    (0..size)
+        .map(|i| config.read::<u8>(CONFIG_DATA + i) as char)
+        .collect()
+}
+
+/// The minimum and the maximum of the absolute axis.
+fn abs_range(config: Mmio, axis: u16) -> Option<(u32, u32)> {
+    if select(config, CONFIG_ABS_INFO, axis as u8) < 8 {
+        return None;
+    }
+
+    Some((
+        config.read::<u32>(CONFIG_DATA),
+        config.read::<u32>(CONFIG_DATA + 4),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(ty: u16, code: u16, value: u32) -> InputEvent {
+        InputEvent { ty, code, value }
+    }
+
+    #[test]
+    fn decode_keys_with_modifiers() {
+        let mut decoder = Decoder::new(DEFAULT_ABS_RANGE, DEFAULT_ABS_RANGE);
+        let key = |modifiers, usage, pressed| {
+            Some(Message::Keyboard(KeyEvent {
+                modifiers,
+                usage,
+                pressed,
+            }))
+        };
+
+        // Left Shift, then A.
+        assert_eq!(key(0x02, 0xE1, true), decoder.decode(event(EV_KEY, 42, 1)));
+        assert_eq!(key(0x02, 0x04, true), decoder.decode(event(EV_KEY, 30, 1)));
+        assert_eq!(None, decoder.decode(event(EV_KEY, 30, KEY_REPEATED)));
+        assert_eq!(key(0x00, 0xE1, false), decoder.decode(event(EV_KEY, 42, 0)));
+        assert_eq!(None, decoder.decode(event(EV_KEY, 0x1FF, 1)));
+    }
+
+    #[test]
+    fn decode_relative_motions_per_report() {
+        let mut decoder = Decoder::new(DEFAULT_ABS_RANGE, DEFAULT_ABS_RANGE);
+
+        assert_eq!(None, decoder.decode(event(EV_SYN, SYN_REPORT, 0)));
+        assert_eq!(None, decoder.decode(event(EV_REL, REL_X, -3i32 as u32)));
+        assert_eq!(None, decoder.decode(event(EV_REL, REL_Y, 2)));
+        assert_eq!(None, decoder.decode(event(EV_KEY, BTN_LEFT, 1)));
+        assert_eq!(
+            Some(Message::Mouse(MouseEvent {
+                buttons: 0x01,
+                dx: -3,
+                dy: 2
+            })),
+            decoder.decode(event(EV_SYN, SYN_REPORT, 0))
+        );
+    }
+
+    #[test]
+    fn decode_absolute_positions() {
+        let mut decoder = Decoder::new((0, 0x7FFF), (100, 200));
+
+        decoder.decode(event(EV_ABS, ABS_X, 0x7FFF));
+        decoder.decode(event(EV_ABS, ABS_Y, 150));
+        assert_eq!(
+            Some(Message::Pointer(PointerEvent {
+                buttons: 0,
+                x: u16::MAX,
+                y: 0x7FFF
+            })),
+            decoder.decode(event(EV_SYN, SYN_REPORT, 0))
+        );
+
+        // Buttons alone keep the position.
+        decoder.decode(event(EV_KEY, BTN_MIDDLE, 1));
+        assert_eq!(
+            Some(Message::Pointer(PointerEvent {
+                buttons: 0x04,
+                x: u16::MAX,
+                y: 0x7FFF
+            })),
+            decoder.decode(event(EV_SYN, SYN_REPORT, 0))
+        );
+    }
+}
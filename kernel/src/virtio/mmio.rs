@@ -0,0 +1,166 @@
+use crate::interrupts::{IntId, Trigger};
+use crate::mmio::Mmio;
+
+use super::queue::{Virtqueue, LEGACY_ALIGN, MAX_QUEUE_SIZE};
+use super::{InterruptStatus, Transport};
+
+/// "virt" in little endian.
+const MAGIC: u32 = 0x7472_6976;
+
+/// The transports on QEMU's `virt` board, every one of which exists whether a device is behind.
+const VIRT_BASE: usize = 0x0A00_0000;
+const VIRT_SIZE: usize = 0x200;
+const VIRT_COUNT: usize = 32;
+const VIRT_INTID: IntId = 48;
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03C;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const STATUS: usize = 0x070;
+const QUEUE_DESC: usize = 0x080;
+const QUEUE_DRIVER: usize = 0x090;
+const QUEUE_DEVICE: usize = 0x0A0;
+const CONFIG: usize = 0x100;
+
+const VERSION_LEGACY: u32 = 1;
+const VERSION_MODERN: u32 = 2;
+
+/// Transports on QEMU's `virt` board, for when the firmware describes them only in AML. Their
+/// interrupts are edge-triggered there.
+pub(crate) fn qemu_virt() -> impl Iterator<Item = (usize, IntId, Trigger)> {
+    (0..VIRT_COUNT).map(|i| {
+        (
+            VIRT_BASE + i * VIRT_SIZE,
+            VIRT_INTID + i as IntId,
+            Trigger::Edge,
+        )
+    })
+}
+
+/// The virtio-mmio transport, in either the legacy (version 1) or the modern (version 2) layout.
+pub(crate) struct MmioTransport {
+    registers: Mmio,
+    version: u32,
+}
+
+impl MmioTransport {
+    /// Returns `None` unless a device is behind the transport.
+    ///
+    /// # Safety
+    /// `base` must be the address of virtio-mmio registers, which are identity mapped.
+    pub(crate) unsafe fn new(base: usize) -> Option<Self> {
+        let registers = Mmio::new(base);
+        if registers.read::<u32>(MAGIC_VALUE) != MAGIC {
+            return None;
+        }
+
+        let version = registers.read::<u32>(VERSION);
+        if !(VERSION_LEGACY..=VERSION_MODERN).contains(&version)
+            || registers.read::<u32>(DEVICE_ID) == 0
+        {
+            return None;
+        }
+
+        Some(Self { registers, version })
+    }
+
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.registers.write::<u32>(offset, value as u32);
+        self.registers
+            .write::<u32>(offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl Transport for MmioTransport {
+    fn device_type(&self) -> u32 {
+        self.registers.read(DEVICE_ID)
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.version == VERSION_LEGACY
+    }
+
+    fn device_features(&self) -> u64 {
+        self.registers.write::<u32>(DEVICE_FEATURES_SEL, 0);
+        let low = self.registers.read::<u32>(DEVICE_FEATURES);
+        self.registers.write::<u32>(DEVICE_FEATURES_SEL, 1);
+        let high = self.registers.read::<u32>(DEVICE_FEATURES);
+        (high as u64) << 32 | low as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.registers.write::<u32>(DRIVER_FEATURES_SEL, 0);
+        self.registers
+            .write::<u32>(DRIVER_FEATURES, features as u32);
+        self.registers.write::<u32>(DRIVER_FEATURES_SEL, 1);
+        self.registers
+            .write::<u32>(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.registers.read::<u32>(STATUS) as u8
+    }
+
+    fn set_status(&self, status: u8) {
+        self.registers.write::<u32>(STATUS, status as u32)
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        self.registers.write::<u32>(QUEUE_SEL, index as u32);
+        self.registers.read::<u32>(QUEUE_NUM_MAX) as u16
+    }
+
+    fn setup_queue(&self, index: u16, queue: &Virtqueue) -> Option<()> {
+        self.registers.write::<u32>(QUEUE_SEL, index as u32);
+        self.registers.write::<u32>(QUEUE_NUM, queue.size() as u32);
+
+        match self.is_legacy() {
+            true => {
+                if queue.size() != MAX_QUEUE_SIZE {
+                    return None;
+                }
+                self.registers
+                    .write::<u32>(GUEST_PAGE_SIZE, LEGACY_ALIGN as u32);
+                self.registers
+                    .write::<u32>(QUEUE_ALIGN, LEGACY_ALIGN as u32);
+                self.registers.write::<u32>(
+                    QUEUE_PFN,
+                    (queue.descriptor_address() / LEGACY_ALIGN as u64) as u32,
+                );
+            }
+            false => {
+                self.write_u64(QUEUE_DESC, queue.descriptor_address());
+                self.write_u64(QUEUE_DRIVER, queue.driver_address());
+                self.write_u64(QUEUE_DEVICE, queue.device_address());
+                self.registers.write::<u32>(QUEUE_READY, 1);
+            }
+        }
+
+        Some(())
+    }
+
+    fn notify(&self, index: u16) {
+        self.registers.write::<u32>(QUEUE_NOTIFY, index as u32);
+    }
+
+    fn interrupt_status(&self) -> InterruptStatus {
+        InterruptStatus::Mmio(self.registers.offset(INTERRUPT_STATUS))
+    }
+
+    fn config(&self) -> Mmio {
+        self.registers.offset(CONFIG)
+    }
+}
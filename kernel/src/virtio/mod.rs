@@ -0,0 +1,187 @@
+//! virtio devices over the MMIO and PCI transports, of which input devices are driven.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub(crate) mod input;
+pub(crate) mod mmio;
+pub(crate) mod pci;
+pub(crate) mod queue;
+
+use crate::interrupts::{self, IntId, Trigger};
+use crate::message::{self, Message};
+use crate::sync::SpinLock;
+
+use input::InputDevice;
+use mmio::MmioTransport;
+use pci::PciTransport;
+use queue::Virtqueue;
+
+pub(crate) const DEVICE_INPUT: u32 = 18;
+
+pub(crate) const FEATURE_VERSION_1: u64 = 1 << 32;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 0x80;
+
+/// Only driven from the main loop, while the interrupt status is shared with the IRQ handler.
+static DEVICES: SpinLock<Vec<InputDevice>> = SpinLock::new(Vec::new());
+static INTERRUPTS: SpinLock<Vec<(IntId, InterruptStatus)>> = SpinLock::new(Vec::new());
+
+/// The way to reach the registers of a device, which differs between the buses. Devices may be
+/// driven from any core, hence `Send`.
+pub(crate) trait Transport: Send {
+    fn device_type(&self) -> u32;
+
+    /// Whether the device speaks the legacy interface, which has no `FEATURES_OK` handshake.
+    fn is_legacy(&self) -> bool;
+
+    fn device_features(&self) -> u64;
+
+    fn set_driver_features(&self, features: u64);
+
+    fn status(&self) -> u8;
+
+    fn set_status(&self, status: u8);
+
+    fn max_queue_size(&self, index: u16) -> u16;
+
+    /// Hands the queue to the device, or fails if the transport cannot take it.
+    fn setup_queue(&self, index: u16, queue: &Virtqueue) -> Option<()>;
+
+    fn notify(&self, index: u16);
+
+    fn interrupt_status(&self) -> InterruptStatus;
+
+    /// The device-specific configuration.
+    fn config(&self) -> crate::mmio::Mmio;
+}
+
+/// The interrupt status of a transport, apart from the rest of it for the interrupt handler.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum InterruptStatus {
+    /// `InterruptStatus` followed by `InterruptACK` of the MMIO transport.
+    Mmio(crate::mmio::Mmio),
+    /// The ISR status of the PCI transport, cleared by reading it.
+    Pci(crate::mmio::Mmio),
+}
+
+impl InterruptStatus {
+    /// Acknowledges the pending interrupts, returning whether there were any.
+    pub(crate) fn acknowledge(&self) -> bool {
+        match self {
+            Self::Mmio(registers) => {
+                let status = registers.read::<u32>(0);
+                registers.write::<u32>(4, status);
+                status != 0
+            }
+            Self::Pci(registers) => registers.read::<u8>(0) != 0,
+        }
+    }
+}
+
+/// Resets the device and negotiates the features, after which the queues are set up.
+pub(crate) fn negotiate(transport: &dyn Transport, features: u64) -> Option<u64> {
+    transport.set_status(0);
+    transport.set_status(STATUS_ACKNOWLEDGE);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let required = match transport.is_legacy() {
+        true => 0,
+        false => FEATURE_VERSION_1,
+    };
+    let features = transport.device_features() & (features | required);
+    if features & required != required {
+        transport.set_status(STATUS_FAILED);
+        return None;
+    }
+    transport.set_driver_features(features);
+
+    if !transport.is_legacy() {
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+        if transport.status() & STATUS_FEATURES_OK == 0 {
+            transport.set_status(STATUS_FAILED);
+            return None;
+        }
+    }
+
+    Some(features)
+}
+
+/// Tells the device that the driver is ready, once the queues are set up.
+pub(crate) fn finish(transport: &dyn Transport) {
+    transport.set_status(transport.status() | STATUS_DRIVER_OK);
+}
+
+/// Tells the device that the driver has given up on it.
+pub(crate) fn fail(transport: &dyn Transport) {
+    transport.set_status(transport.status() | STATUS_FAILED);
+}
+
+/// Brings up the input devices on the MMIO transports and on PCI.
+pub(crate) fn init<I>(mmio: I)
+where
+    I: Iterator<Item = (usize, IntId, Trigger)>,
+{
+    let mmio = mmio.filter_map(|(base, intid, trigger)| {
+        let transport = unsafe { MmioTransport::new(base) }?;
+        (transport.device_type() == DEVICE_INPUT)
+            .then(|| (Box::new(transport) as Box<dyn Transport>, intid, trigger))
+    });
+    // Only the functions driven here may master the bus, so the type is checked first.
+    let pci = crate::pci::devices().iter().filter_map(|device| {
+        let transport = PciTransport::new(device)?;
+        if transport.device_type() != DEVICE_INPUT {
+            return None;
+        }
+
+        let intid = crate::pci::legacy_intid(device)?;
+        device.enable_bus_master();
+        Some((
+            Box::new(transport) as Box<dyn Transport>,
+            intid,
+            Trigger::Level,
+        ))
+    });
+
+    mmio.chain(pci).for_each(|(transport, intid, trigger)| {
+        let Some(device) = InputDevice::new(transport) else {
+            log::warn!(
+                "virtio: Failed to set up the input device at INTID {}",
+                intid
+            );
+            return;
+        };
+
+        log::info!("virtio: {} at INTID {}", device.name(), intid);
+        INTERRUPTS
+            .lock_irqsave()
+            .push((intid, device.interrupt_status()));
+        DEVICES.lock().push(device);
+        interrupts::register(intid, trigger, interrupts::DEFAULT_PRIORITY, handle_irq);
+    });
+}
+
+/// Handles the events the devices have posted, called from the main loop.
+pub(crate) fn process_events() {
+    DEVICES
+        .lock()
+        .iter_mut()
+        .for_each(|device| device.process_events());
+}
+
+/// Acknowledges every device on the interrupt line, since PCI ones may share it.
+fn handle_irq(intid: IntId) {
+    let pending = INTERRUPTS
+        .lock()
+        .iter()
+        .filter(|(id, _)| *id == intid)
+        .fold(false, |pending, (_, status)| status.acknowledge() | pending);
+
+    if pending {
+        message::post(Message::Virtio);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::mmio::Mmio;
+use crate::pci::capability::CAP_VENDOR_SPECIFIC;
+use crate::pci::{BarKind, Device};
+
+use super::queue::Virtqueue;
+use super::{InterruptStatus, Transport};
+
+pub(crate) const VENDOR_ID: u16 = 0x1AF4;
+
+/// Device IDs of the modern devices, which are the device type above this.
+const DEVICE_ID_MODERN: u16 = 0x1040;
+
+const CAP_COMMON_CONFIG: u8 = 1;
+const CAP_NOTIFY_CONFIG: u8 = 2;
+const CAP_ISR_CONFIG: u8 = 3;
+const CAP_DEVICE_CONFIG: u8 = 4;
+
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+/// The PCI transport of virtio 1.0, whose structures are found through vendor capabilities.
+pub(crate) struct PciTransport {
+    device_type: u32,
+    common: Mmio,
+    notify: Mmio,
+    notify_multiplier: u32,
+    isr: Mmio,
+    device: Mmio,
+}
+
+impl PciTransport {
+    /// Returns `None` unless the function is a virtio device with the structures mapped.
+    pub(crate) fn new(device: &Device) -> Option<Self> {
+        if device.vendor_id != VENDOR_ID {
+            return None;
+        }
+
+        // Transitional devices have the IDs of the legacy interface, but speak the modern one too.
+        let device_type = match device.device_id {
+            0x1000 => 1,
+            0x1001 => 2,
+            0x1002 => 5,
+            0x1003 => 3,
+            0x1004 => 8,
+            0x1005 => 4,
+            0x1009 => 9,
+            id if id >= DEVICE_ID_MODERN => (id - DEVICE_ID_MODERN) as u32,
+            _ => return None,
+        };
+
+        let notify = find_structure(device, CAP_NOTIFY_CONFIG)?;
+        Some(Self {
+            device_type,
+            common: find_structure(device, CAP_COMMON_CONFIG)?.0,
+            notify: notify.0,
+            notify_multiplier: device.config().read(notify.1 + 16),
+            isr: find_structure(device, CAP_ISR_CONFIG)?.0,
+            device: find_structure(device, CAP_DEVICE_CONFIG)?.0,
+        })
+    }
+
+    fn select_queue(&self, index: u16) {
+        self.common.write::<u16>(QUEUE_SELECT, index);
+    }
+
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.common.write::<u32>(offset, value as u32);
+        self.common.write::<u32>(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// The structure of the type pointed by a capability, with the offset of the capability.
+fn find_structure(device: &Device, ty: u8) -> Option<(Mmio, usize)> {
+    let config = device.config();
+    device
+        .capabilities()
+        .filter(|c| c.id == CAP_VENDOR_SPECIFIC)
+        .map(|c| c.offset as usize)
+        .find(|&offset| config.read::<u8>(offset + 3) == ty)
+        .and_then(|offset| {
+            let bar = device.bar(config.read::<u8>(offset + 4) as usize)?;
+            if bar.kind == BarKind::Io || bar.address == 0 {
+                return None;
+            }
+
+            let address = bar.address as usize + config.read::<u32>(offset + 8) as usize;
+            Some((unsafe { Mmio::new(address) }, offset))
+        })
+}
+
+impl Transport for PciTransport {
+    fn device_type(&self) -> u32 {
+        self.device_type
+    }
+
+    fn is_legacy(&self) -> bool {
+        false
+    }
+
+    fn device_features(&self) -> u64 {
+        self.common.write::<u32>(DEVICE_FEATURE_SELECT, 0);
+        let low = self.common.read::<u32>(DEVICE_FEATURE);
+        self.common.write::<u32>(DEVICE_FEATURE_SELECT, 1);
+        let high = self.common.read::<u32>(DEVICE_FEATURE);
+        (high as u64) << 32 | low as u64
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.common.write::<u32>(DRIVER_FEATURE_SELECT, 0);
+        self.common.write::<u32>(DRIVER_FEATURE, features as u32);
+        self.common.write::<u32>(DRIVER_FEATURE_SELECT, 1);
+        self.common
+            .write::<u32>(DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.common.read(DEVICE_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.common.write(DEVICE_STATUS, status)
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        self.select_queue(index);
+        self.common.read(QUEUE_SIZE)
+    }
+
+    fn setup_queue(&self, index: u16, queue: &Virtqueue) -> Option<()> {
+        self.select_queue(index);
+        self.common.write::<u16>(QUEUE_SIZE, queue.size());
+        self.write_u64(QUEUE_DESC, queue.descriptor_address());
+        self.write_u64(QUEUE_DRIVER, queue.driver_address());
+        self.write_u64(QUEUE_DEVICE, queue.device_address());
+        self.common.write::<u16>(QUEUE_ENABLE, 1);
+        Some(())
+    }
+
+    fn notify(&self, index: u16) {
+        self.select_queue(index);
+        let offset = self.common.read::<u16>(QUEUE_NOTIFY_OFF) as usize;
+        self.notify
+            .write::<u16>(offset * self.notify_multiplier as usize, index);
+    }
+
+    fn interrupt_status(&self) -> InterruptStatus {
+        InterruptStatus::Pci(self.isr)
+    }
+
+    fn config(&self) -> Mmio {
+        self.device
+    }
+}
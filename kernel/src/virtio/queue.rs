@@ -0,0 +1,163 @@
+use alloc::boxed::Box;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::memory::zeroed;
+
+/// Descriptors in a queue at most, with which the rings fit in a page each. The legacy interface
+/// derives the layout from the size, so it takes queues of exactly this size only.
+pub(crate) const MAX_QUEUE_SIZE: u16 = 64;
+
+/// Alignment of the used ring in the legacy layout.
+pub(crate) const LEGACY_ALIGN: usize = 4096;
+
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct Descriptor {
+    address: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// The descriptor table followed by the available ring, written by the driver.
+#[repr(C, align(4096))]
+struct DriverArea {
+    descriptors: [Descriptor; MAX_QUEUE_SIZE as usize],
+    available_flags: u16,
+    available_index: u16,
+    available: [u16; MAX_QUEUE_SIZE as usize],
+}
+
+/// The used ring, written by the device.
+#[repr(C, align(4096))]
+struct DeviceArea {
+    used_flags: u16,
+    used_index: u16,
+    used: [UsedElement; MAX_QUEUE_SIZE as usize],
+}
+
+/// Both areas in one allocation, laid out as the legacy interface expects too.
+#[repr(C)]
+struct Areas {
+    driver: DriverArea,
+    device: DeviceArea,
+}
+
+/// A split virtqueue, whose descriptors are handed to the device one buffer each.
+pub(crate) struct Virtqueue {
+    areas: Box<Areas>,
+    size: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    pub(crate) fn new(size: u16) -> Self {
+        Self {
+            areas: zeroed(),
+            size: size.clamp(1, MAX_QUEUE_SIZE),
+            last_used: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    pub(crate) fn descriptor_address(&self) -> u64 {
+        &self.areas.driver.descriptors as *const _ as u64
+    }
+
+    #[inline]
+    pub(crate) fn driver_address(&self) -> u64 {
+        &self.areas.driver.available_flags as *const _ as u64
+    }
+
+    #[inline]
+    pub(crate) fn device_address(&self) -> u64 {
+        &self.areas.device as *const _ as u64
+    }
+
+    /// Makes the buffer available to the device in the descriptor of the ID, which is returned
+    /// along with the buffer once used.
+    pub(crate) fn add(&mut self, id: u16, address: u64, len: u32, device_writable: bool) {
+        let driver = &mut self.areas.driver;
+        driver.descriptors[id as usize] = Descriptor {
+            address,
+            len,
+            flags: if device_writable { DESCRIPTOR_WRITE } else { 0 },
+            next: 0,
+        };
+
+        let index = unsafe { read_volatile(&driver.available_index) };
+        driver.available[(index % self.size) as usize] = id;
+
+        // The device may read the ring as soon as the index moves.
+        fence(Ordering::Release);
+        unsafe { write_volatile(&mut driver.available_index, index.wrapping_add(1)) };
+    }
+
+    /// Takes the next buffer the device has used, with the bytes it has written.
+    pub(crate) fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let device = &self.areas.device;
+        if unsafe { read_volatile(&device.used_index) } == self.last_used {
+            return None;
+        }
+
+        fence(Ordering::Acquire);
+        let element = unsafe { read_volatile(&device.used[(self.last_used % self.size) as usize]) };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((element.id as u16, element.len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lay_out_like_legacy() {
+        let queue = Virtqueue::new(MAX_QUEUE_SIZE);
+        let size = MAX_QUEUE_SIZE as u64;
+
+        assert_eq!(
+            queue.descriptor_address() + 16 * size,
+            queue.driver_address()
+        );
+        assert_eq!(
+            (queue.driver_address() + 6 + 2 * size).next_multiple_of(LEGACY_ALIGN as u64),
+            queue.device_address()
+        );
+    }
+
+    #[test]
+    fn add_and_pop_buffers() {
+        let mut queue = Virtqueue::new(4);
+        (0..4).for_each(|id| queue.add(id, 0x1000 + id as u64 * 8, 8, true));
+
+        assert_eq!(4, queue.areas.driver.available_index);
+        assert_eq!([0, 1, 2, 3], queue.areas.driver.available[..4]);
+        assert_eq!(DESCRIPTOR_WRITE, queue.areas.driver.descriptors[2].flags);
+        assert_eq!(None, queue.pop_used());
+
+        queue.areas.device.used[0] = UsedElement { id: 2, len: 8 };
+        queue.areas.device.used_index = 1;
+        assert_eq!(Some((2, 8)), queue.pop_used());
+        assert_eq!(None, queue.pop_used());
+
+        // Wraps around the ring by the size rather than the capacity.
+        queue.add(2, 0x1010, 8, true);
+        assert_eq!(2, queue.areas.driver.available[0]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Post-link step embedding the function symbols of the kernel into its own image.
+//!
+//! Reads `.symtab` of the linked ELF, encodes the function symbols into the compact table and
+//! writes it over the contents of `.symbols` section, which is reserved by the kernel. The layout
+//! of the image is not changed, so the addresses of the symbols are kept valid.
+
+use std::cmp::Reverse;
+use std::env;
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustc_demangle::demangle;
+
+use mikan_core::symbols::{encode, Symbol, ENTRY_SIZE, HEADER_SIZE};
+
+const SECTION_NAME: &str = ".symbols";
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SYMBOL_SIZE: usize = 24;
+
+struct Section {
+    name: u32,
+    ty: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_str(bytes: &[u8], offset: usize) -> Result<&str> {
+    let bytes = bytes.get(offset..).context("String out of range")?;
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .context("Unterminated string")?;
+
+    Ok(std::str::from_utf8(&bytes[..len])?)
+}
+
+fn sections(elf: &[u8]) -> Result<Vec<Section>> {
+    if elf.len() < 64 || elf[..4] != *b"\x7fELF" || elf[4] != 2 || elf[5] != 1 {
+        bail!("Not a 64-bit little-endian ELF");
+    }
+
+    let offset = read_u64(elf, 0x28) as usize;
+    let entry_size = read_u16(elf, 0x3A) as usize;
+    let count = read_u16(elf, 0x3C) as usize;
+    if elf.len() < offset + entry_size * count {
+        bail!("Section headers out of range");
+    }
+
+    Ok((0..count)
+        .map(|i| offset + i * entry_size)
+        .map(|header| Section {
+            name: read_u32(elf, header),
+            ty: read_u32(elf, header + 4),
+            offset: read_u64(elf, header + 24) as usize,
+            size: read_u64(elf, header + 32) as usize,
+            link: read_u32(elf, header + 40),
+        })
+        .collect())
+}
+
+fn function_symbols(elf: &[u8], sections: &[Section]) -> Result<Vec<(u64, u64, String)>> {
+    let symtab = sections
+        .iter()
+        .find(|s| s.ty == SHT_SYMTAB)
+        .ok_or_else(|| anyhow!("No symbol table found; is the kernel stripped?"))?;
+    let strtab = sections
+        .get(symtab.link as usize)
+        .context("No string table for the symbol table")?;
+    let strings = &elf[strtab.offset..strtab.offset + strtab.size];
+
+    let mut symbols = elf[symtab.offset..symtab.offset + symtab.size]
+        .chunks_exact(SYMBOL_SIZE)
+        .filter(|symbol| symbol[4] & 0xF == STT_FUNC && read_u64(symbol, 8) != 0)
+        .map(|symbol| {
+            let name = read_str(strings, read_u32(symbol, 0) as usize)?;
+            Ok((
+                read_u64(symbol, 8),
+                read_u64(symbol, 16),
+                format!("{:#}", demangle(name)),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    symbols.sort_by_key(|&(address, _, _)| address);
+    symbols.dedup_by_key(|&mut (address, _, _)| address);
+
+    Ok(symbols)
+}
+
+/// Drops the symbols with the longest names, mostly of generic instances, until the table fits
+/// in the size. The order of the others is kept.
+fn fit(symbols: Vec<Symbol>, size: usize) -> Vec<Symbol> {
+    let cost = |symbol: &Symbol| ENTRY_SIZE + symbol.name.len();
+    let mut len = HEADER_SIZE + symbols.iter().map(cost).sum::<usize>();
+
+    let mut by_length = (0..symbols.len()).collect::<Vec<_>>();
+    by_length.sort_by_key(|&i| Reverse(symbols[i].name.len()));
+    let mut dropped = vec![false; symbols.len()];
+    for i in by_length {
+        if len <= size {
+            break;
+        }
+        len -= cost(&symbols[i]);
+        dropped[i] = true;
+    }
+
+    symbols
+        .into_iter()
+        .zip(dropped)
+        .filter_map(|(symbol, dropped)| (!dropped).then_some(symbol))
+        .collect()
+}
+
+fn main() -> Result<()> {
+    let path = env::args().nth(1).context("Usage: symtab <kernel.elf>")?;
+    let mut elf = fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+
+    let sections = sections(&elf)?;
+    let shstrtab = sections
+        .get(read_u16(&elf, 0x3E) as usize)
+        .context("No section name table")?;
+    let names = &elf[shstrtab.offset..shstrtab.offset + shstrtab.size];
+    let target = sections
+        .iter()
+        .find(|s| read_str(names, s.name as usize).ok() == Some(SECTION_NAME))
+        .ok_or_else(|| anyhow!("No {} section found in {}", SECTION_NAME, path))?;
+
+    let symbols = function_symbols(&elf, &sections)?;
+    let symbols = symbols
+        .iter()
+        .map(|(address, size, name)| Symbol {
+            name,
+            address: *address,
+            size: *size,
+        })
+        .collect::<Vec<_>>();
+
+    let found = symbols.len();
+    let symbols = fit(symbols, target.size);
+    if symbols.len() < found {
+        eprintln!(
+            "warning: {} of {} symbols do not fit in {} bytes of {}; dropped the longest names",
+            found - symbols.len(),
+            found,
+            target.size,
+            SECTION_NAME,
+        );
+    }
+
+    let buf = &mut elf[target.offset..target.offset + target.size];
+    buf.fill(0);
+    let len = encode(&symbols, buf)
+        .ok_or_else(|| anyhow!("{} is too small for the header", SECTION_NAME))?;
+
+    fs::write(&path, &elf).with_context(|| format!("Failed to write {}", path))?;
+    println!(
+        "Embedded {} symbols ({} of {} bytes) into {}",
+        symbols.len(),
+        len,
+        target.size,
+        path,
+    );
+
+    Ok(())
+}